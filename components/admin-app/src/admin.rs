@@ -3,16 +3,51 @@ use ctaphid_dispatch::app::{self as hid, Command as HidCommand, Message};
 use ctaphid_dispatch::command::VendorCommand;
 use apdu_dispatch::{Command, command, response, app as apdu};
 use apdu_dispatch::iso7816::Status;
+use littlefs2::path::PathBuf;
 use trussed::{
-    syscall,
-    Client as TrussedClient,
+    client, syscall, try_syscall,
+    types::{Location, Message},
 };
 
+use crate::config::{Config, Update};
+use crate::led;
+use crate::selftest;
+use crate::status::{DeviceInfo, StatusReport};
+
 const UPDATE: VendorCommand = VendorCommand::H51;
 const REBOOT: VendorCommand = VendorCommand::H53;
 const RNG: VendorCommand = VendorCommand::H60;
 const VERSION: VendorCommand = VendorCommand::H61;
 const UUID: VendorCommand = VendorCommand::H62;
+const STATUS: VendorCommand = VendorCommand::H63;
+const FACTORY_RESET: VendorCommand = VendorCommand::H64;
+const GET_CONFIG: VendorCommand = VendorCommand::H65;
+const SET_CONFIG: VendorCommand = VendorCommand::H66;
+const ENABLED_APPS: VendorCommand = VendorCommand::H67;
+const SELF_TEST: VendorCommand = VendorCommand::H68;
+const LED: VendorCommand = VendorCommand::H69;
+
+/// Consecutive user presence confirmations, i.e. holding the button, for a factory reset
+/// and for disabling the buttons.
+const LONG_PRESS_CONFIRMATIONS: usize = 3;
+
+/// The Trussed client, with the mechanisms the self test checks.
+pub trait Client: trussed::Client
+    + client::Sha256
+    + client::HmacSha256
+    + client::Aes256Cbc
+    + client::P256
+    + client::Ed255
+{}
+
+impl<T> Client for T
+where T: trussed::Client
+       + client::Sha256
+       + client::HmacSha256
+       + client::Aes256Cbc
+       + client::P256
+       + client::Ed255
+{}
 
 pub trait Reboot {
     /// Reboots the device.
@@ -33,23 +68,29 @@ pub trait Reboot {
     fn reboot_to_firmware_update_destructive() -> !;
 }
 
-pub struct App<T, R>
-where T: TrussedClient,
+pub struct App<T, R, D>
+where T: Client,
       R: Reboot,
+      D: DeviceInfo,
 {
     got_wink: bool,
     trussed: T,
     uuid: [u8; 16],
     version: u32,
+    device: D,
+    config: Config,
+    self_tested: bool,
     boot_interface: PhantomData<R>,
 }
 
-impl<T, R> App<T, R>
-where T: TrussedClient,
+impl<T, R, D> App<T, R, D>
+where T: Client,
       R: Reboot,
+      D: DeviceInfo,
 {
-    pub fn new(client: T, uuid: [u8; 16], version: u32) -> Self {
-        Self { got_wink: false, trussed: client, uuid, version, boot_interface: PhantomData }
+    /// `config` is what the runner loaded at boot, or its defaults.
+    pub fn new(client: T, uuid: [u8; 16], version: u32, device: D, config: Config) -> Self {
+        Self { got_wink: false, trussed: client, uuid, version, device, config, self_tested: false, boot_interface: PhantomData }
     }
 
     /// Indicate if a wink was recieved
@@ -62,17 +103,138 @@ where T: TrussedClient,
         }
     }
 
+    /// Runs the self test once, if the configuration asks for it; call when Trussed is up.
+    pub fn boot_self_test(&mut self) {
+        if !self.self_tested {
+            self.self_tested = true;
+            if self.config.self_test_at_boot {
+                self.self_test();
+            }
+        }
+    }
+
+    fn self_test(&mut self) -> selftest::Report {
+        let nfc = self.device.nfc_present();
+        let report = selftest::Report::run(&mut self.trussed, nfc);
+        if !report.passed {
+            self.device.indicate_self_test_failure();
+        }
+        report
+    }
+
     fn user_present(&mut self) -> bool {
         let user_present = syscall!(self.trussed.confirm_user_present(15_000)).result;
         user_present.is_ok()
     }
 
+    fn long_press(&mut self) -> bool {
+        (0..LONG_PRESS_CONFIRMATIONS).all(|_| self.user_present())
+    }
+
+    /// Wipes all apps' storage except the attestation material, then reboots.
+    fn factory_reset(&mut self) -> Result<(), Status> {
+        if !self.long_press() {
+            return Err(Status::ConditionsOfUseNotSatisfied);
+        }
+        self.device.factory_reset().map_err(|_| Status::UnspecifiedPersistentExecutionError)?;
+        R::reboot();
+    }
+
+    fn get_config<const N: usize>(&mut self, reply: &mut heapless::Vec<u8, N>) -> Result<(), ()> {
+        let mut buffer = [0u8; 256];
+        let encoded = trussed::cbor_serialize(&self.config, &mut buffer).map_err(drop)?;
+        reply.extend_from_slice(encoded)
+    }
+
+    /// Applies the CBOR-encoded `Update` and persists the result, for the next boot.
+    ///
+    /// Without buttons, every user presence check succeeds, so disabling them takes a long press.
+    fn set_config(&mut self, data: &[u8]) -> Result<(), Status> {
+        let update: Update = trussed::cbor_deserialize(data)
+            .map_err(|_| Status::IncorrectDataParameter)?;
+        let mut config = self.config;
+        update.apply(&mut config);
+        if self.config.buttons && !config.buttons {
+            if !self.long_press() {
+                return Err(Status::ConditionsOfUseNotSatisfied);
+            }
+            self.write_config(config)
+        } else {
+            self.save_config(config)
+        }
+    }
+
+    /// Without data, replies with the USB and NFC app masks (`config::app`), as big endian
+    /// `u16` each. With these four bytes as data, sets them for the next boot.
+    fn enabled_apps<const N: usize>(&mut self, data: &[u8], reply: &mut heapless::Vec<u8, N>) -> Result<(), Status> {
+        match data.len() {
+            0 => {
+                reply.extend_from_slice(&self.config.usb_apps.to_be_bytes()).map_err(|_| Status::NotEnoughMemory)?;
+                reply.extend_from_slice(&self.config.nfc_apps.to_be_bytes()).map_err(|_| Status::NotEnoughMemory)
+            }
+            4 => {
+                let mut config = self.config;
+                config.usb_apps = u16::from_be_bytes([data[0], data[1]]);
+                config.nfc_apps = u16::from_be_bytes([data[2], data[3]]);
+                self.save_config(config)
+            }
+            _ => Err(Status::WrongLength),
+        }
+    }
+
+    fn save_config(&mut self, config: Config) -> Result<(), Status> {
+        if !self.user_present() {
+            return Err(Status::ConditionsOfUseNotSatisfied);
+        }
+        self.write_config(config)
+    }
+
+    fn write_config(&mut self, config: Config) -> Result<(), Status> {
+        let data: Message = trussed::cbor_serialize_bytes(&config)
+            .map_err(|_| Status::NotEnoughMemory)?;
+        try_syscall!(self.trussed.write_file(
+            Location::Internal,
+            PathBuf::from("config.cbor"),
+            data,
+            None,
+        )).map_err(|_| Status::NotEnoughMemory)?;
+        self.config = config;
+        Ok(())
+    }
+
+    /// Starts or cancels an LED animation, see `led::Request`.
+    fn led(&mut self, data: &[u8]) -> Result<(), Status> {
+        let request: led::Request = trussed::cbor_deserialize(data)
+            .map_err(|_| Status::IncorrectDataParameter)?;
+        let now = syscall!(self.trussed.uptime()).uptime;
+        let animation = request.animation(now).map_err(|_| Status::IncorrectDataParameter)?;
+        self.device.led(animation);
+        Ok(())
+    }
+
+    /// CBOR-encoded `selftest::Report`
+    fn self_test_report<const N: usize>(&mut self, reply: &mut heapless::Vec<u8, N>) -> Result<(), ()> {
+        let report = self.self_test();
+        let mut buffer = [0u8; 128];
+        let encoded = trussed::cbor_serialize(&report, &mut buffer).map_err(drop)?;
+        reply.extend_from_slice(encoded)
+    }
+
+    /// CBOR-encoded `StatusReport`, identical over CTAPHID and APDU.
+    fn status<const N: usize>(&mut self, reply: &mut heapless::Vec<u8, N>) -> Result<(), ()> {
+        let uptime = syscall!(self.trussed.uptime()).uptime;
+        let report = StatusReport::new(&mut self.device, self.uuid, uptime);
+        let mut buffer = [0u8; 512];
+        let encoded = trussed::cbor_serialize(&report, &mut buffer).map_err(drop)?;
+        reply.extend_from_slice(encoded)
+    }
 
 }
 
-impl<T, R> hid::App for App<T, R>
-where T: TrussedClient,
-      R: Reboot
+impl<T, R, D> hid::App for App<T, R, D>
+where T: Client,
+      R: Reboot,
+      D: DeviceInfo,
 {
     fn commands(&self) -> &'static [HidCommand] {
         &[
@@ -82,6 +244,13 @@ where T: TrussedClient,
             HidCommand::Vendor(RNG),
             HidCommand::Vendor(VERSION),
             HidCommand::Vendor(UUID),
+            HidCommand::Vendor(STATUS),
+            HidCommand::Vendor(FACTORY_RESET),
+            HidCommand::Vendor(GET_CONFIG),
+            HidCommand::Vendor(SET_CONFIG),
+            HidCommand::Vendor(ENABLED_APPS),
+            HidCommand::Vendor(SELF_TEST),
+            HidCommand::Vendor(LED),
         ]
     }
 
@@ -111,17 +280,47 @@ where T: TrussedClient,
                 // GET VERSION
                 response.extend_from_slice(&self.version.to_be_bytes()).ok();
             }
-            _ => {
+            HidCommand::Vendor(UUID) => {
+                response.extend_from_slice(&self.uuid).ok();
+            }
+            HidCommand::Vendor(STATUS) => {
+                self.status(response).map_err(|_| hid::Error::InvalidLength)?;
+            }
+            HidCommand::Vendor(FACTORY_RESET) => {
+                self.factory_reset().map_err(|_| hid::Error::InvalidLength)?;
+            }
+            HidCommand::Vendor(GET_CONFIG) => {
+                self.get_config(response).map_err(|_| hid::Error::InvalidLength)?;
+            }
+            HidCommand::Vendor(SET_CONFIG) => {
+                self.set_config(input_data).map_err(|_| hid::Error::InvalidLength)?;
+            }
+            HidCommand::Vendor(ENABLED_APPS) => {
+                self.enabled_apps(input_data, response).map_err(|_| hid::Error::InvalidLength)?;
+            }
+            HidCommand::Vendor(SELF_TEST) => {
+                self.self_test_report(response).map_err(|_| hid::Error::InvalidLength)?;
+            }
+            HidCommand::Vendor(LED) => {
+                self.led(input_data).map_err(|_| hid::Error::InvalidLength)?;
+            }
+            HidCommand::Wink => {
                 self.got_wink = true;
+                let now = syscall!(self.trussed.uptime()).uptime;
+                self.device.led(Some(led::Animation::new(led::Pattern::Identify, now)));
+            }
+            _ => {
+                return Err(hid::Error::InvalidCommand);
             }
         }
         Ok(())
     }
 }
 
-impl<T, R> iso7816::App for App<T, R>
-where T: TrussedClient,
-      R: Reboot
+impl<T, R, D> iso7816::App for App<T, R, D>
+where T: Client,
+      R: Reboot,
+      D: DeviceInfo,
 {
     // Solo management app
     fn aid(&self) -> iso7816::Aid {
@@ -129,9 +328,10 @@ where T: TrussedClient,
     }
 }
 
-impl<T, R> apdu::App<{command::SIZE}, {response::SIZE}> for App<T, R>
-where T: TrussedClient,
-      R: Reboot
+impl<T, R, D> apdu::App<{command::SIZE}, {response::SIZE}> for App<T, R, D>
+where T: Client,
+      R: Reboot,
+      D: DeviceInfo,
 {
 
     fn select(&mut self, _apdu: &Command, _reply: &mut response::Data) -> apdu::Result {
@@ -176,6 +376,34 @@ where T: TrussedClient,
                 reply.extend_from_slice(&self.uuid).ok();
             }
 
+            STATUS => {
+                self.status(reply).map_err(|_| Status::NotEnoughMemory)?;
+            }
+
+            FACTORY_RESET => {
+                self.factory_reset()?;
+            }
+
+            GET_CONFIG => {
+                self.get_config(reply).map_err(|_| Status::NotEnoughMemory)?;
+            }
+
+            SET_CONFIG => {
+                self.set_config(apdu.data())?;
+            }
+
+            ENABLED_APPS => {
+                self.enabled_apps(apdu.data(), reply)?;
+            }
+
+            SELF_TEST => {
+                self.self_test_report(reply).map_err(|_| Status::NotEnoughMemory)?;
+            }
+
+            LED => {
+                self.led(apdu.data())?;
+            }
+
             _ => {
                 return Err(Status::InstructionNotSupportedOrInvalid);
             }
@@ -0,0 +1,55 @@
+//! Device configuration: typed settings persisted by the admin app, applied by the runner at boot.
+//!
+//! GET CONFIG replies with the current `Config` as CBOR map. SET CONFIG takes a CBOR map
+//! with the keys to change, e.g. `{"buttons": false}`; unknown keys or wrongly typed values
+//! are rejected. Changes take effect after the next reboot. They need user presence, and
+//! disabling the buttons the long press of FACTORY RESET.
+//!
+//! The file lives in the admin app's namespace, so a factory reset restores the defaults.
+
+use serde::Deserialize;
+
+pub use device_config::{app, Config, PATH};
+
+/// The keys SET CONFIG changes, all others are kept.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Update {
+    buttons: Option<bool>,
+    fido_reset_time_window: Option<bool>,
+    reconfigure_nfc: Option<bool>,
+    led_brightness: Option<u8>,
+    stealth: Option<bool>,
+    usb_apps: Option<u16>,
+    nfc_apps: Option<u16>,
+    self_test_at_boot: Option<bool>,
+}
+
+impl Update {
+    pub(crate) fn apply(self, config: &mut Config) {
+        if let Some(buttons) = self.buttons {
+            config.buttons = buttons;
+        }
+        if let Some(fido_reset_time_window) = self.fido_reset_time_window {
+            config.fido_reset_time_window = fido_reset_time_window;
+        }
+        if let Some(reconfigure_nfc) = self.reconfigure_nfc {
+            config.reconfigure_nfc = reconfigure_nfc;
+        }
+        if let Some(led_brightness) = self.led_brightness {
+            config.led_brightness = led_brightness;
+        }
+        if let Some(stealth) = self.stealth {
+            config.stealth = stealth;
+        }
+        if let Some(usb_apps) = self.usb_apps {
+            config.usb_apps = usb_apps;
+        }
+        if let Some(nfc_apps) = self.nfc_apps {
+            config.nfc_apps = nfc_apps;
+        }
+        if let Some(self_test_at_boot) = self.self_test_at_boot {
+            config.self_test_at_boot = self_test_at_boot;
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! The FACTORY RESET command: wipe every app's storage, but keep what provisioning put there.
+//!
+//! Clients only see their own namespace, so the admin app cannot do this through Trussed.
+//! Instead the runner passes its store to `wipe` when asked via `DeviceInfo::factory_reset`.
+//!
+//! Everything but the paths in `KEEP` (and their ancestors) is removed, in all locations.
+//! In particular the FIDO state goes, so its key-encryption and key-wrapping keys are
+//! regenerated on first use afterwards.
+
+use littlefs2::fs::Filesystem;
+use littlefs2::path::{Path, PathBuf};
+use trussed::store::Store;
+use trussed::types::LfsStorage;
+
+/// Provisioned material, and the Trussed RNG state.
+const KEEP: &[&str] = &[
+    "/attn",
+    "/trussed",
+    "/fido/sec/00",
+    "/fido/dat/aaguid",
+    "/fido/x5c/00",
+    "/piv/sec/00",
+    "/piv/x5c/00",
+    "/ndef/dat/url",
+];
+
+/// Entries removed per directory listing; littlefs must not be modified while iterating.
+const BATCH: usize = 8;
+
+pub fn wipe<S: Store>(store: S) -> Result<(), ()> {
+    let root = PathBuf::from("/");
+    wipe_dir::<S::I>(store.ifs(), &root)?;
+    wipe_dir::<S::E>(store.efs(), &root)?;
+    wipe_dir::<S::V>(store.vfs(), &root)
+}
+
+enum Keep {
+    /// In `KEEP` itself, or below
+    All,
+    /// Something in `KEEP` is below
+    Some,
+    None,
+}
+
+fn keep(path: &str) -> Keep {
+    for kept in KEEP {
+        if let Some(rest) = path.strip_prefix(kept) {
+            if rest.is_empty() || rest.starts_with('/') {
+                return Keep::All;
+            }
+        }
+        if let Some(rest) = kept.strip_prefix(path) {
+            if rest.starts_with('/') {
+                return Keep::Some;
+            }
+        }
+    }
+    Keep::None
+}
+
+fn wipe_dir<S: LfsStorage>(fs: &Filesystem<'static, S>, dir: &Path) -> Result<(), ()> {
+    // (path, is_dir)
+    type Batch = heapless::Vec<(PathBuf, bool), BATCH>;
+
+    loop {
+        let batch: Batch = fs.read_dir_and_then(dir, |entries| {
+            let mut batch = Batch::new();
+            for entry in entries {
+                let entry = entry?;
+                let name: &str = entry.file_name().as_ref();
+                if name == "." || name == ".." {
+                    continue;
+                }
+                if let Keep::None = keep(entry.path().as_ref()) {
+                    if batch.push((PathBuf::from(entry.path()), entry.file_type().is_dir())).is_err() {
+                        break;
+                    }
+                }
+            }
+            Ok(batch)
+        }).map_err(drop)?;
+
+        if batch.is_empty() {
+            break;
+        }
+        for (path, is_dir) in batch {
+            if is_dir {
+                fs.remove_dir_all(&path).map_err(drop)?;
+            } else {
+                fs.remove(&path).map_err(drop)?;
+            }
+        }
+    }
+
+    // only ancestors of `KEEP` are left, at most one per entry
+    let partial: heapless::Vec<PathBuf, { KEEP.len() }> = fs.read_dir_and_then(dir, |entries| {
+        let mut partial = heapless::Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type().is_dir() {
+                if let Keep::Some = keep(entry.path().as_ref()) {
+                    partial.push(PathBuf::from(entry.path())).ok();
+                }
+            }
+        }
+        Ok(partial)
+    }).map_err(drop)?;
+
+    for path in partial {
+        wipe_dir(fs, &path)?;
+    }
+    Ok(())
+}
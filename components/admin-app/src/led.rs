@@ -0,0 +1,103 @@
+//! The LED command: named animations, e.g. to find one key among many on a desk.
+//!
+//! The request is a CBOR map with these keys, all optional:
+//!
+//! - `pattern`: `identify`, `success`, `error` or `waiting-for-touch`; without it, a running
+//!   animation is cancelled
+//! - `duration`: in milliseconds, otherwise the pattern's default
+//! - `color`: RGB word for `waiting-for-touch`, orange by default
+//!
+//! A CTAPHID WINK shows `identify` with its default duration.
+//!
+//! The admin app only picks the pattern and when it ends, the runner draws it with
+//! `Pattern::blink`, see `DeviceInfo::led`. Brightness and stealth mode (`Config::stealth`)
+//! apply to animations too.
+
+use core::time::Duration;
+use serde::Deserialize;
+
+/// Longest accepted `duration`.
+const MAX_DURATION: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Pattern {
+    /// White and blue, fast
+    Identify,
+    /// Green
+    Success,
+    /// Red, blinking
+    Error,
+    /// The given RGB word, blinking slowly
+    WaitingForTouch(u32),
+}
+
+impl Pattern {
+    /// The two RGB words the LED alternates between, and how many milliseconds each is shown.
+    pub fn blink(&self) -> ([u32; 2], u32) {
+        match *self {
+            Pattern::Identify => ([0xff_ff_ff, 0x00_00_ff], 150),
+            Pattern::Success => ([0x00_ff_00, 0x00_ff_00], 1000),
+            Pattern::Error => ([0xff_00_00, 0x00_00_00], 250),
+            Pattern::WaitingForTouch(color) => ([color, 0x00_00_00], 500),
+        }
+    }
+
+    fn default_duration(&self) -> Duration {
+        match self {
+            Pattern::Identify => Duration::from_secs(5),
+            Pattern::Success => Duration::from_secs(1),
+            Pattern::Error => Duration::from_secs(2),
+            Pattern::WaitingForTouch(_) => Duration::from_secs(15),
+        }
+    }
+}
+
+/// A pattern, shown until the given uptime.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Animation {
+    pub pattern: Pattern,
+    pub until: Duration,
+}
+
+impl Animation {
+    pub(crate) fn new(pattern: Pattern, now: Duration) -> Self {
+        Self { pattern, until: now + pattern.default_duration() }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Request<'a> {
+    #[serde(borrow)]
+    pattern: Option<&'a str>,
+    duration: Option<u32>,
+    color: Option<u32>,
+}
+
+impl Request<'_> {
+    /// `None` cancels.
+    pub(crate) fn animation(&self, now: Duration) -> Result<Option<Animation>, ()> {
+        let pattern = match self.pattern {
+            None => return Ok(None),
+            Some("identify") => Pattern::Identify,
+            Some("success") => Pattern::Success,
+            Some("error") => Pattern::Error,
+            Some("waiting-for-touch") => Pattern::WaitingForTouch(self.color.unwrap_or(0xff_7e_00)),
+            Some(_) => return Err(()),
+        };
+        match (self.color, pattern) {
+            (Some(color), Pattern::WaitingForTouch(_)) if color <= 0xff_ff_ff => {}
+            (Some(_), _) => return Err(()),
+            (None, _) => {}
+        }
+        let mut animation = Animation::new(pattern, now);
+        if let Some(duration) = self.duration {
+            let duration = Duration::from_millis(duration.into());
+            if duration > MAX_DURATION {
+                return Err(());
+            }
+            animation.until = now + duration;
+        }
+        Ok(Some(animation))
+    }
+}
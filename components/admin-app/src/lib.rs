@@ -7,4 +7,13 @@
 #![no_std]
 
 mod admin;
-pub use admin::{App, Reboot};
+pub use admin::{App, Client, Reboot};
+pub mod config;
+pub use config::Config;
+pub mod factory_reset;
+pub mod led;
+pub mod selftest;
+pub mod status;
+pub use status::{AppInfo, Blocks, BootReason, DeviceInfo};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -0,0 +1,168 @@
+//! The SELF TEST command, for acceptance testing of new hardware.
+//!
+//! Everything runs through Trussed, so it tests the actual implementations the apps use.
+//! The reply is a CBOR map with one boolean per test, `nfc` is `null` on boards without NFC,
+//! and `passed` if all of them passed.
+
+use hex_literal::hex;
+use littlefs2::path::PathBuf;
+use serde::Serialize;
+use trussed::{
+    client, try_syscall,
+    types::{KeyId, Location, Mechanism, Message, SignatureSerialization},
+};
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct Report {
+    pub passed: bool,
+    pub rng: bool,
+    pub sha256: bool,
+    pub hmac_sha256: bool,
+    pub aes256: bool,
+    pub p256: bool,
+    pub ed25519: bool,
+    pub filesystem: bool,
+    pub nfc: Option<bool>,
+}
+
+impl Report {
+    pub fn run<T>(trussed: &mut T, nfc: Option<bool>) -> Self
+    where T: client::Client
+           + client::Sha256
+           + client::HmacSha256
+           + client::Aes256Cbc
+           + client::P256
+           + client::Ed255,
+    {
+        let mut report = Self {
+            passed: false,
+            rng: rng(trussed).is_ok(),
+            sha256: sha256(trussed).is_ok(),
+            hmac_sha256: hmac_sha256(trussed).is_ok(),
+            aes256: aes256(trussed).is_ok(),
+            p256: p256(trussed).is_ok(),
+            ed25519: ed25519(trussed).is_ok(),
+            filesystem: filesystem(trussed).is_ok(),
+            nfc,
+        };
+        report.passed = report.rng && report.sha256 && report.hmac_sha256 && report.aes256
+            && report.p256 && report.ed25519 && report.filesystem && nfc != Some(false);
+        report
+    }
+}
+
+type Result = core::result::Result<(), ()>;
+
+fn check(condition: bool) -> Result {
+    if condition { Ok(()) } else { Err(()) }
+}
+
+/// SP 800-90B, 4.4 continuous health tests on one sample of bytes.
+///
+/// The cutoffs assume only one bit of entropy per byte, so they never fail a working RNG.
+fn rng<T: client::Client>(trussed: &mut T) -> Result {
+    const REPETITION_CUTOFF: usize = 21;
+    const WINDOW: usize = 512;
+    const PROPORTION_CUTOFF: usize = 410;
+
+    let bytes = try_syscall!(trussed.random_bytes(WINDOW)).map_err(drop)?.bytes;
+    check(bytes.len() == WINDOW)?;
+
+    // repetition count test
+    let mut repetitions = 1;
+    for pair in bytes.windows(2) {
+        repetitions = if pair[0] == pair[1] { repetitions + 1 } else { 1 };
+        check(repetitions < REPETITION_CUTOFF)?;
+    }
+
+    // adaptive proportion test
+    let first = bytes[0];
+    check(bytes.iter().filter(|&&byte| byte == first).count() < PROPORTION_CUTOFF)
+}
+
+/// FIPS 180-2, B.1
+fn sha256<T: client::Sha256>(trussed: &mut T) -> Result {
+    let hash = try_syscall!(trussed.hash_sha256(b"abc")).map_err(drop)?.hash;
+    check(hash[..] == hex!("ba7816bf 8f01cfea 414140de 5dae2223 b00361a3 96177a9c b410ff61 f20015ad"))
+}
+
+/// RFC 4231, test case 2
+fn hmac_sha256<T: client::Client + client::HmacSha256>(trussed: &mut T) -> Result {
+    let key = try_syscall!(trussed.unsafe_inject_shared_key(b"Jefe", Location::Volatile)).map_err(drop)?.key;
+    let signature = try_syscall!(trussed.sign_hmacsha256(key, b"what do ya want for nothing?"))
+        .map(|reply| reply.signature);
+    delete(trussed, key);
+    check(signature.map_err(drop)?[..] ==
+        hex!("5bdcc146 bf60754e 6a042426 089575c7 5a003f08 9d273983 9dec58b9 64ec3843"))
+}
+
+/// FIPS 197, C.3; one block with the zero IV is the plain block cipher
+fn aes256<T: client::Client + client::Aes256Cbc>(trussed: &mut T) -> Result {
+    let raw_key = hex!("00010203 04050607 08090a0b 0c0d0e0f 10111213 14151617 18191a1b 1c1d1e1f");
+    let key = try_syscall!(trussed.unsafe_inject_shared_key(&raw_key, Location::Volatile)).map_err(drop)?.key;
+    let plaintext = try_syscall!(trussed.decrypt_aes256cbc(key, &hex!("8ea2b7ca 516745bf eafc4990 4b496089")))
+        .map(|reply| reply.plaintext);
+    delete(trussed, key);
+    let plaintext = plaintext.map_err(drop)?.ok_or(())?;
+    check(plaintext[..] == hex!("00112233 44556677 8899aabb ccddeeff"))
+}
+
+/// RFC 6979, A.2.5 (SHA-256, "sample"); signatures are randomized, so the
+/// vector is verified and a fresh signature is checked too
+fn p256<T: client::Client + client::P256>(trussed: &mut T) -> Result {
+    let raw_key = hex!("C9AFA9D8 45BA7516 6B5C2157 67B1D693 4E50C3DB 36E89B12 7B8A622B 120F6721");
+    let signature = hex!("
+        EFD48B2A ACB6A8FD 1140DD9C D45E81D6 9D2C877B 56AAF991 C34D0EA8 4EAF3716
+        F7CB1C94 2D657C41 D436C7A1 B6E29F65 F3E900DB B9AFF406 4DC4AB2F 843ACDA8
+    ");
+
+    let private_key = try_syscall!(trussed.unsafe_inject_key(Mechanism::P256, &raw_key, Location::Volatile))
+        .map_err(drop)?.key;
+    let result = p256_with(trussed, private_key, &signature);
+    delete(trussed, private_key);
+    result
+}
+
+fn p256_with<T: client::Client + client::P256>(trussed: &mut T, private_key: KeyId, signature: &[u8]) -> Result {
+    let public_key = try_syscall!(trussed.derive_p256_public_key(private_key, Location::Volatile))
+        .map_err(drop)?.key;
+    let result = p256_verify(trussed, private_key, public_key, signature);
+    delete(trussed, public_key);
+    result
+}
+
+fn p256_verify<T: client::P256>(trussed: &mut T, private_key: KeyId, public_key: KeyId, signature: &[u8]) -> Result {
+    check(try_syscall!(trussed.verify_p256(public_key, b"sample", signature)).map_err(drop)?.valid)?;
+    let fresh = try_syscall!(trussed.sign_p256(private_key, b"sample", SignatureSerialization::Raw))
+        .map_err(drop)?.signature;
+    check(try_syscall!(trussed.verify_p256(public_key, b"sample", &fresh)).map_err(drop)?.valid)?;
+    check(!try_syscall!(trussed.verify_p256(public_key, b"simple", &fresh)).map_err(drop)?.valid)
+}
+
+/// RFC 8032, 7.1 test 1
+fn ed25519<T: client::Client + client::Ed255>(trussed: &mut T) -> Result {
+    let raw_key = hex!("9d61b19d effd5a60 ba844af4 92ec2cc4 4449c569 7b326919 703bac03 1cae7f60");
+    let key = try_syscall!(trussed.unsafe_inject_key(Mechanism::Ed255, &raw_key, Location::Volatile))
+        .map_err(drop)?.key;
+    let signature = try_syscall!(trussed.sign_ed255(key, &[])).map(|reply| reply.signature);
+    delete(trussed, key);
+    check(signature.map_err(drop)?[..] == hex!("
+        e5564300 c360ac72 9086e2cc 806e828a 84877f1e b8e5d974 d873e065 22490155
+        5fb88215 90a33bac c61e3970 1cf9b46b d25bf5f0 595bbe24 65514143 8e7a100b
+    "))
+}
+
+/// Write, read back and remove a file in RAM.
+fn filesystem<T: client::Client>(trussed: &mut T) -> Result {
+    let path = PathBuf::from("selftest");
+    let data: Message = try_syscall!(trussed.random_bytes(128)).map_err(drop)?.bytes;
+
+    try_syscall!(trussed.write_file(Location::Volatile, path.clone(), data.clone(), None)).map_err(drop)?;
+    let read = try_syscall!(trussed.read_file(Location::Volatile, path.clone())).map(|reply| reply.data);
+    try_syscall!(trussed.remove_file(Location::Volatile, path)).map_err(drop)?;
+    check(read.map_err(drop)? == data)
+}
+
+fn delete<T: client::Client>(trussed: &mut T, key: KeyId) {
+    try_syscall!(trussed.delete(key)).ok();
+}
@@ -0,0 +1,120 @@
+//! The STATUS command: what fleet inventory needs to know about a device.
+//!
+//! The reply is the same CBOR map over CTAPHID and APDU, with these keys:
+//!
+//! - `firmware`: version string, `git`: hash the firmware was built from
+//! - `board`: board variant
+//! - `uuid`: byte string
+//! - `filesystem`: `internal`, `external` and `volatile`, each `null` or with `free` and `used` blocks
+//! - `uptime`: milliseconds since boot
+//! - `apps`: array of `name` and `version` (`null` if unknown)
+//! - `locked`: debug access disabled, `secure_boot`: only signed firmware boots
+//! - `boot_reason`: one of the `BootReason` variants, as string
+
+use serde::Serialize;
+use trussed::types::Location;
+
+/// An app included in the firmware.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub struct AppInfo {
+    pub name: &'static str,
+    pub version: Option<&'static str>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct Blocks {
+    pub free: u32,
+    pub used: u32,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum BootReason {
+    PowerOn,
+    ResetPin,
+    Watchdog,
+    Software,
+    Lockup,
+    Brownout,
+    WakeUp,
+    Unknown,
+}
+
+/// What only the runner knows about the device it runs on.
+pub trait DeviceInfo {
+    /// e.g. "solo2", "nk3xn"
+    fn board(&self) -> &'static str;
+
+    fn firmware_version(&self) -> &'static str;
+
+    fn git_hash(&self) -> &'static str;
+
+    fn apps(&self) -> &'static [AppInfo];
+
+    /// `None` if there is no filesystem at that location.
+    fn filesystem(&mut self, location: Location) -> Option<Blocks>;
+
+    fn locked(&mut self) -> bool;
+
+    fn secure_boot(&mut self) -> bool;
+
+    fn boot_reason(&mut self) -> BootReason;
+
+    /// Wipe all app storage, typically with `factory_reset::wipe`.
+    fn factory_reset(&mut self) -> Result<(), ()>;
+
+    /// Whether the NFC chip responds, `None` if the board has none.
+    fn nfc_present(&mut self) -> Option<bool>;
+
+    /// Show the failure to the user, e.g. with an LED pattern, until the next reboot.
+    fn indicate_self_test_failure(&mut self);
+
+    /// Show `animation` until it ends, replacing any running one; `None` cancels.
+    fn led(&mut self, animation: Option<crate::led::Animation>);
+}
+
+#[derive(Serialize)]
+pub(crate) struct Filesystems {
+    pub internal: Option<Blocks>,
+    pub external: Option<Blocks>,
+    pub volatile: Option<Blocks>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct StatusReport<'a> {
+    pub firmware: &'a str,
+    pub git: &'a str,
+    pub board: &'a str,
+    #[serde(serialize_with = "byte_string")]
+    pub uuid: [u8; 16],
+    pub filesystem: Filesystems,
+    pub uptime: u64,
+    pub apps: &'a [AppInfo],
+    pub locked: bool,
+    pub secure_boot: bool,
+    pub boot_reason: BootReason,
+}
+
+impl StatusReport<'static> {
+    pub fn new<D: DeviceInfo>(device: &mut D, uuid: [u8; 16], uptime: core::time::Duration) -> Self {
+        Self {
+            filesystem: Filesystems {
+                internal: device.filesystem(Location::Internal),
+                external: device.filesystem(Location::External),
+                volatile: device.filesystem(Location::Volatile),
+            },
+            locked: device.locked(),
+            secure_boot: device.secure_boot(),
+            boot_reason: device.boot_reason(),
+            firmware: device.firmware_version(),
+            git: device.git_hash(),
+            board: device.board(),
+            uuid,
+            uptime: uptime.as_millis() as u64,
+            apps: device.apps(),
+        }
+    }
+}
+
+fn byte_string<S: serde::Serializer>(bytes: &[u8; 16], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bytes(bytes)
+}
@@ -0,0 +1,83 @@
+//! Device configuration: typed settings applied by the runner at boot.
+//!
+//! The admin app changes them with SET CONFIG, see `admin_app::config`. Runners without
+//! the admin app only ever get the defaults they pass to `Config::load`.
+#![no_std]
+
+use apdu_dispatch::app::Interface;
+use littlefs2::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use trussed::store::{self, Store};
+use trussed::types::{Location, Message};
+
+/// What the admin app writes to as `config.cbor`, as seen from the store.
+pub const PATH: &str = "/admin/dat/config.cbor";
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Config {
+    /// Use the touch button for user presence, otherwise it is always confirmed.
+    pub buttons: bool,
+    /// Only allow a FIDO reset within 10s after boot.
+    pub fido_reset_time_window: bool,
+    /// Rewrite the NFC chip's EEPROM configuration at boot.
+    pub reconfigure_nfc: bool,
+    /// Scales all LED colors, 255 is full brightness.
+    pub led_brightness: u8,
+    /// Keep the LED off, whatever happens.
+    pub stealth: bool,
+    /// Apps served over USB (CTAPHID and CCID), see `app`.
+    pub usb_apps: u16,
+    /// Apps served over NFC, see `app`.
+    pub nfc_apps: u16,
+    /// Run the self test at boot, see `admin_app::selftest`.
+    pub self_test_at_boot: bool,
+}
+
+/// Bits of `Config::usb_apps` and `Config::nfc_apps`.
+///
+/// The admin app itself cannot be disabled, otherwise there would be no way back.
+pub mod app {
+    pub const FIDO: u16 = 1 << 0;
+    pub const PIV: u16 = 1 << 1;
+    pub const OATH: u16 = 1 << 2;
+    pub const NDEF: u16 = 1 << 3;
+    pub const PROVISIONER: u16 = 1 << 4;
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            buttons: true,
+            fido_reset_time_window: true,
+            reconfigure_nfc: false,
+            led_brightness: u8::MAX,
+            stealth: false,
+            usb_apps: u16::MAX,
+            nfc_apps: u16::MAX,
+            self_test_at_boot: false,
+        }
+    }
+}
+
+impl Config {
+    /// The stored configuration, or `defaults` if there is none (or it cannot be read).
+    ///
+    /// For the runner, before any Trussed client exists.
+    pub fn load<S: Store>(store: S, defaults: Self) -> Self {
+        let data: Message = match store::read(store, Location::Internal, &PathBuf::from(PATH)) {
+            Ok(data) => data,
+            Err(_) => return defaults,
+        };
+        trussed::cbor_deserialize(&data).unwrap_or(defaults)
+    }
+
+    /// Whether `app` (one of the `app` bits) is served over `interface`.
+    pub fn is_enabled(&self, app: u16, interface: Interface) -> bool {
+        let apps = match interface {
+            Interface::Contact => self.usb_apps,
+            Interface::Contactless => self.nfc_apps,
+        };
+        apps & app != 0
+    }
+}
@@ -6,6 +6,8 @@ use core::convert::{TryFrom, TryInto};
 extern crate delog;
 generate_macros!();
 
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
 use trussed::{
     client, syscall, try_syscall,
     Client as TrussedClient,
@@ -143,6 +145,7 @@ where UP: UserPresence,
     trussed: T,
     state: state::State,
     up: UP,
+    reset_time_window: bool,
 }
 
 impl<UP, T> Authenticator<UP, T>
@@ -160,11 +163,19 @@ where UP: UserPresence,
     pub fn new(trussed: T, up: UP) -> Self {
 
         let state = state::State::new();
-        let authenticator = Self { trussed, state, up };
+        let reset_time_window = !cfg!(feature = "disable-reset-time-window");
+        let authenticator = Self { trussed, state, up, reset_time_window };
 
         authenticator
     }
 
+    /// Whether a reset is only allowed within 10s after boot, overriding the
+    /// `disable-reset-time-window` feature.
+    pub fn with_reset_time_window(mut self, enabled: bool) -> Self {
+        self.reset_time_window = enabled;
+        self
+    }
+
     #[inline(never)]
     pub fn call_u2f(&mut self, request: &U2fCommand) -> U2fResult<U2fResponse> {
         info!("called u2f");
@@ -1527,8 +1538,7 @@ where UP: UserPresence,
     fn reset(&mut self) -> Result<()> {
         // 1. >10s after bootup -> NotAllowed
         let uptime = syscall!(self.trussed.uptime()).uptime;
-        if uptime.as_secs() > 10 {
-            #[cfg(not(feature = "disable-reset-time-window"))]
+        if self.reset_time_window && uptime.as_secs() > 10 {
             return Err(Error::NotAllowed);
         }
         // 2. check for user presence
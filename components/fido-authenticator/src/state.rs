@@ -84,6 +84,7 @@ pub type Aaguid = [u8; 16];
 pub type Certificate = trussed::types::Message;
 
 impl Identity {
+    const AAGUID_FILENAME: &'static [u8] = b"aaguid";
 
     // Attempt to yank out the aaguid of a certificate.
     fn yank_aaguid(&mut self, der: &[u8]) -> Option<[u8; 16]> {
@@ -119,28 +120,48 @@ impl Identity {
         Some(aaguid)
     }
 
+    /// The AAGUID saved by the provisioner, which must agree with the certificate's.
+    fn saved_aaguid<T: TrussedClient>(&mut self, trussed: &mut T) -> Option<Aaguid> {
+        let data = try_syscall!(trussed.read_file(
+            Location::Internal,
+            PathBuf::from(Self::AAGUID_FILENAME),
+        )).ok()?.data;
+        let mut aaguid = [0u8; 16];
+        if data.len() != aaguid.len() {
+            return None;
+        }
+        aaguid.copy_from_slice(&data);
+        Some(aaguid)
+    }
+
+    /// Read on every call, so what the provisioner saves is used without a reboot.
     pub fn attestation<T: TrussedClient>(&mut self, trussed: &mut T) -> (Option<(KeyId, Certificate)>, Aaguid)
     {
+        let saved_aaguid = self.saved_aaguid(trussed);
+
         let key = crate::constants::ATTESTATION_KEY_ID;
         let attestation_key_exists = syscall!(trussed.exists(Mechanism::P256, key)).exists;
-        if attestation_key_exists {
-
-            // Will panic if certificate does not exist.
-            let cert = syscall!(trussed.read_certificate(
+        // during provisioning, the key can be there before its certificate
+        let cert = if attestation_key_exists {
+            try_syscall!(trussed.read_certificate(
                 crate::constants::ATTESTATION_CERT_ID
-            )).der;
-
-            let mut aaguid = self.yank_aaguid(&cert.as_slice());
-
-            if aaguid.is_none() {
-                // Provide a default
-                aaguid = Some(*b"AAGUID0123456789");
-            }
-
-            (Some((key, cert)), aaguid.unwrap())
+            )).ok().map(|reply| reply.der)
         } else {
-            info_now!("exist returns none");
-            (None, *b"AAGUID0123456789")
+            None
+        };
+
+        match cert {
+            Some(cert) => {
+                let aaguid = saved_aaguid
+                    .or_else(|| self.yank_aaguid(&cert.as_slice()))
+                    // Provide a default
+                    .unwrap_or(*b"AAGUID0123456789");
+                (Some((key, cert)), aaguid)
+            }
+            None => {
+                info_now!("no attestation key and certificate");
+                (None, saved_aaguid.unwrap_or(*b"AAGUID0123456789"))
+            }
         }
     }
 
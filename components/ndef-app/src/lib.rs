@@ -1,4 +1,8 @@
 #![no_std]
 
+pub mod message;
 pub mod ndef;
-pub use ndef::*;
\ No newline at end of file
+pub use ndef::*;
+pub mod template;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
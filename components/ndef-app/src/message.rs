@@ -0,0 +1,282 @@
+//! NDEF messages written with UPDATE BINARY, checked before they are stored (NFC Forum NDEF 1.0).
+//!
+//! Any well-formed message is accepted: MB on the first record only, ME on the last, lengths
+//! that add up exactly, no chunked records and no reserved TNF. Additionally, the contents of
+//! URI and text records and of vCards (`text/vcard`, `text/x-vcard`) are checked.
+//!
+//! A stored message is served instead of the template until CONFIGURE sets a new URL.
+
+pub const FILENAME: &[u8] = b"message";
+/// The write lock set with CONFIGURE: the NDEF file is read-only unless this file holds a zero
+/// byte. Without the file, it is read-only while there is a template, so that readers cannot
+/// replace the counter and MAC.
+pub const WRITE_LOCK_FILENAME: &[u8] = b"write-lock";
+
+mod flag {
+    pub const MB: u8 = 0x80;
+    pub const ME: u8 = 0x40;
+    pub const CF: u8 = 0x20;
+    pub const SR: u8 = 0x10;
+    pub const IL: u8 = 0x08;
+}
+
+mod tnf {
+    pub const EMPTY: u8 = 0x00;
+    pub const WELL_KNOWN: u8 = 0x01;
+    pub const MEDIA: u8 = 0x02;
+    pub const ABSOLUTE_URI: u8 = 0x03;
+    pub const EXTERNAL: u8 = 0x04;
+    pub const UNKNOWN: u8 = 0x05;
+}
+
+/// Highest URI identifier code (NFC Forum URI RTD).
+const MAX_URI_CODE: u8 = 0x23;
+
+pub struct Record<'a> {
+    pub tnf: u8,
+    pub kind: &'a [u8],
+    pub id: &'a [u8],
+    pub payload: &'a [u8],
+}
+
+/// The number of records, if `message` is valid.
+pub fn validate(message: &[u8]) -> Result<usize, ()> {
+    let mut rest = message;
+    let mut count = 0;
+    loop {
+        let (header, record) = next_record(&mut rest)?;
+        if (header & flag::MB != 0) != (count == 0) {
+            return Err(());
+        }
+        check_record(&record)?;
+        count += 1;
+        if header & flag::ME != 0 {
+            return if rest.is_empty() { Ok(count) } else { Err(()) };
+        }
+    }
+}
+
+fn take<'a>(data: &mut &'a [u8], length: usize) -> Result<&'a [u8], ()> {
+    if data.len() < length {
+        return Err(());
+    }
+    let (taken, rest) = data.split_at(length);
+    *data = rest;
+    Ok(taken)
+}
+
+fn next_record<'a>(data: &mut &'a [u8]) -> Result<(u8, Record<'a>), ()> {
+    let header = take(data, 1)?[0];
+    if header & flag::CF != 0 {
+        return Err(());
+    }
+    let type_length = take(data, 1)?[0] as usize;
+    let payload_length = if header & flag::SR != 0 {
+        take(data, 1)?[0] as usize
+    } else {
+        let bytes = take(data, 4)?;
+        u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize
+    };
+    let id_length = if header & flag::IL != 0 { take(data, 1)?[0] as usize } else { 0 };
+
+    let record = Record {
+        tnf: header & 0x07,
+        kind: take(data, type_length)?,
+        id: take(data, id_length)?,
+        payload: take(data, payload_length)?,
+    };
+    Ok((header, record))
+}
+
+fn check_record(record: &Record) -> Result<(), ()> {
+    match record.tnf {
+        tnf::EMPTY => {
+            if record.kind.is_empty() && record.id.is_empty() && record.payload.is_empty() {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+        tnf::WELL_KNOWN => match record.kind {
+            b"U" => check_uri(record.payload),
+            b"T" => check_text(record.payload),
+            b"" => Err(()),
+            _ => Ok(()),
+        },
+        tnf::MEDIA => {
+            if !record.kind.iter().all(|byte| (0x21..0x7f).contains(byte)) {
+                Err(())
+            } else if record.kind.eq_ignore_ascii_case(b"text/vcard") || record.kind.eq_ignore_ascii_case(b"text/x-vcard") {
+                check_vcard(record.payload)
+            } else if record.kind.is_empty() {
+                Err(())
+            } else {
+                Ok(())
+            }
+        }
+        tnf::ABSOLUTE_URI | tnf::EXTERNAL => {
+            if record.kind.is_empty() { Err(()) } else { Ok(()) }
+        }
+        tnf::UNKNOWN => {
+            if record.kind.is_empty() { Ok(()) } else { Err(()) }
+        }
+        // "unchanged" is only for chunks, 0x07 is reserved
+        _ => Err(()),
+    }
+}
+
+fn check_uri(payload: &[u8]) -> Result<(), ()> {
+    let (&code, uri) = payload.split_first().ok_or(())?;
+    let uri = core::str::from_utf8(uri).map_err(drop)?;
+    if code > MAX_URI_CODE || uri.chars().any(char::is_control) {
+        return Err(());
+    }
+    Ok(())
+}
+
+fn check_text(payload: &[u8]) -> Result<(), ()> {
+    let (&status, rest) = payload.split_first().ok_or(())?;
+    let utf16 = status & 0x80 != 0;
+    // bit 6 is reserved
+    if status & 0x40 != 0 {
+        return Err(());
+    }
+    let language_length = (status & 0x3f) as usize;
+    if language_length == 0 || rest.len() < language_length {
+        return Err(());
+    }
+    let (language, text) = rest.split_at(language_length);
+    if !language.iter().all(|byte| byte.is_ascii_alphanumeric() || *byte == b'-') {
+        return Err(());
+    }
+    if utf16 {
+        if text.len() % 2 != 0 { Err(()) } else { Ok(()) }
+    } else {
+        core::str::from_utf8(text).map(drop).map_err(drop)
+    }
+}
+
+fn check_vcard(payload: &[u8]) -> Result<(), ()> {
+    let vcard = core::str::from_utf8(payload).map_err(drop)?.trim();
+    let begins = vcard.get(..11).map_or(false, |begin| begin.eq_ignore_ascii_case("BEGIN:VCARD"));
+    let ends = vcard.len() >= 9 && vcard.get(vcard.len() - 9..).map_or(false, |end| end.eq_ignore_ascii_case("END:VCARD"));
+    if begins && ends { Ok(()) } else { Err(()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    #[test]
+    fn uri() {
+        // MB ME SR, well-known "U", "https://" "example.com"
+        assert_eq!(validate(&hex!("d1 01 0c 55 04 6578616d706c652e636f6d")), Ok(1));
+        assert_eq!(validate(&hex!("d1 01 01 55 00")), Ok(1));
+        // unknown prefix code
+        assert!(validate(&hex!("d1 01 02 55 24 61")).is_err());
+        // no prefix code
+        assert!(validate(&hex!("d1 01 00 55")).is_err());
+        // invalid UTF-8, control characters
+        assert!(validate(&hex!("d1 01 02 55 04 ff")).is_err());
+        assert!(validate(&hex!("d1 01 02 55 04 0a")).is_err());
+    }
+
+    #[test]
+    fn text() {
+        // UTF-8, "en", "hi"
+        assert_eq!(validate(&hex!("d1 01 05 54 02 656e 6869")), Ok(1));
+        // UTF-16, "en", "h"
+        assert_eq!(validate(&hex!("d1 01 05 54 82 656e 0068")), Ok(1));
+        // odd UTF-16
+        assert!(validate(&hex!("d1 01 04 54 82 656e 68")).is_err());
+        // reserved bit, no language, language too long, not a language
+        assert!(validate(&hex!("d1 01 03 54 42 656e")).is_err());
+        assert!(validate(&hex!("d1 01 03 54 00 6869")).is_err());
+        assert!(validate(&hex!("d1 01 03 54 03 656e")).is_err());
+        assert!(validate(&hex!("d1 01 03 54 02 e4e4")).is_err());
+        // invalid UTF-8
+        assert!(validate(&hex!("d1 01 04 54 02 656e ff")).is_err());
+    }
+
+    #[test]
+    fn vcard() {
+        let mut message = heapless::Vec::<u8, 128>::new();
+        let vcard = b"BEGIN:VCARD\r\nVERSION:3.0\r\nFN:Jane Doe\r\nEND:VCARD\r\n";
+        message.extend_from_slice(&[0xd2, 0x0a, vcard.len() as u8]).unwrap();
+        message.extend_from_slice(b"text/vcard").unwrap();
+        message.extend_from_slice(vcard).unwrap();
+        assert_eq!(validate(&message), Ok(1));
+
+        // the type is case-insensitive
+        message[3..13].copy_from_slice(b"TEXT/VCARD");
+        assert_eq!(validate(&message), Ok(1));
+
+        // not ending
+        let truncated = message.len() - 4;
+        message[2] -= 4;
+        assert!(validate(&message[..truncated]).is_err());
+
+        // other media types are not checked
+        assert_eq!(validate(&hex!("d2 0a 01 746578742f706c61696e ff")), Ok(1));
+        // but must be printable, and present
+        assert!(validate(&hex!("d2 01 00 20")).is_err());
+        assert!(validate(&hex!("d2 00 00")).is_err());
+    }
+
+    #[test]
+    fn records() {
+        // empty, external and unknown records
+        assert_eq!(validate(&hex!("d0 00 00")), Ok(1));
+        assert_eq!(validate(&hex!("d4 03 01 612e62 00")), Ok(1));
+        assert_eq!(validate(&hex!("d5 00 01 00")), Ok(1));
+        // empty records are empty, unknown records have no type
+        assert!(validate(&hex!("d0 00 01 00")).is_err());
+        assert!(validate(&hex!("d5 01 00 61")).is_err());
+        // unchanged and reserved TNF
+        assert!(validate(&hex!("d6 00 00")).is_err());
+        assert!(validate(&hex!("d7 00 00")).is_err());
+        // no chunks
+        assert!(validate(&hex!("f5 00 00")).is_err());
+        // with ID
+        assert_eq!(validate(&hex!("d9 01 01 02 55 6964 00")), Ok(1));
+        // long record
+        assert_eq!(validate(&hex!("c1 01 00000002 55 04 61")), Ok(1));
+    }
+
+    #[test]
+    fn flags() {
+        let first = hex!("91 01 02 55 04 61");
+        let last = hex!("51 01 02 55 04 62");
+        let middle = hex!("11 01 02 55 04 63");
+        let mut message = heapless::Vec::<u8, 32>::new();
+        message.extend_from_slice(&first).unwrap();
+        message.extend_from_slice(&middle).unwrap();
+        message.extend_from_slice(&last).unwrap();
+        assert_eq!(validate(&message), Ok(3));
+        assert!(validate(&message[..first.len()]).is_err(), "no ME");
+        assert!(validate(&message[first.len()..]).is_err(), "no MB");
+
+        // MB on a later record
+        message[first.len()] |= 0x80;
+        assert!(validate(&message).is_err());
+        // ME before the end
+        assert!(validate(&hex!("d1 01 02 55 04 61 51 01 02 55 04 62")).is_err());
+        assert!(validate(&[]).is_err());
+    }
+
+    #[test]
+    fn lengths() {
+        // payload, type and ID longer than the message
+        assert!(validate(&hex!("d1 01 03 55 04 61")).is_err());
+        assert!(validate(&hex!("d1 02 00 55")).is_err());
+        assert!(validate(&hex!("d9 01 01 02 55 69 00")).is_err());
+        assert!(validate(&hex!("c1 01 00000003 55 04 61")).is_err());
+        assert!(validate(&hex!("c1 01 ffffffff 55 04 61")).is_err());
+        // trailing bytes
+        assert!(validate(&hex!("d1 01 02 55 04 61 00")).is_err());
+        // header cut off
+        assert!(validate(&hex!("d1")).is_err());
+        assert!(validate(&hex!("c1 01 0000")).is_err());
+    }
+}
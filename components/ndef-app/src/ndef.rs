@@ -1,11 +1,38 @@
+use core::convert::TryInto;
 use iso7816::{Instruction, Status};
 use apdu_dispatch::{Command, response, app, command::SIZE as CommandSize, response::SIZE as ResponseSize};
+use littlefs2::path::PathBuf;
+use trussed::{
+    client, syscall, try_syscall,
+    types::{KeyId, Location, Message},
+};
 
-pub struct App<'a>{
-    reader: &'a [u8]
+use crate::message;
+use crate::template::{self, Configuration, Fields, NdefFile, Values};
+
+/// Sets the URL template and its fields, see `template::Configuration`. Only over the contact
+/// interface, and with user presence.
+const CONFIGURE: u8 = 0x71;
+
+const UPDATE_BINARY: u8 = 0xd6;
+
+enum Selected {
+    CapabilityContainer,
+    Ndef,
 }
 
-impl<'a> App<'a> {
+pub struct App<T> {
+    trussed: T,
+    uuid: [u8; 16],
+    selected: Option<Selected>,
+    /// The stored message, or generated from the template, whenever the NDEF file is selected
+    ndef: NdefFile,
+    write_locked: bool,
+}
+
+impl<T> App<T>
+where T: trussed::Client + client::HmacSha256,
+{
     pub const CAPABILITY_CONTAINER: [u8; 15] = [
         0x00, 0x0f, /* CCEN_HI, CCEN_LOW */
         0x20,       /* VERSION */
@@ -14,10 +41,12 @@ impl<'a> App<'a> {
         /* TLV */
         0x04,0x06,
         0xe1,0x04,
-        0x00,0x7f,
-        0x00,0x00,
+        (template::MAX_NDEF_FILE >> 8) as u8, template::MAX_NDEF_FILE as u8,
+        0x00,       /* read access */
+        0x00,       /* write access, 0xff if write locked */
     ];
 
+    /// Served if there is no template.
     pub const NDEF : [u8; 20] = [
         0x00, 0x12, /* two-byte length */
         0xd1, /* TNF: well-known + flags */
@@ -28,29 +57,221 @@ impl<'a> App<'a> {
         0x6e, 0x69, 0x74, 0x72, 0x6f, 0x6b, 0x65, 0x79, 0x2e, 0x63, 0x6f, 0x6d, 0x2f, /* nitrokey.com/ */
     ];
 
-    pub fn new() -> App<'a> {
-        App{
-            reader: &Self::NDEF,
+    pub fn new(trussed: T, uuid: [u8; 16]) -> App<T> {
+        App {
+            trussed,
+            uuid,
+            selected: None,
+            ndef: NdefFile::from_slice(&Self::NDEF).unwrap(),
+            write_locked: false,
+        }
+    }
+
+    /// See `message::WRITE_LOCK_FILENAME`.
+    fn is_write_locked(&mut self) -> bool {
+        match try_syscall!(self.trussed.read_file(Location::Internal, PathBuf::from(message::WRITE_LOCK_FILENAME))) {
+            Ok(reply) => reply.data.as_slice() != [0],
+            Err(_) => try_syscall!(self.trussed.read_file(Location::Internal, PathBuf::from(template::URL_FILENAME))).is_ok(),
+        }
+    }
+
+    fn capability_container(&self) -> [u8; 15] {
+        let mut container = Self::CAPABILITY_CONTAINER;
+        if self.write_locked {
+            container[14] = 0xff;
         }
+        container
+    }
+
+    /// The message written by a reader, if any.
+    fn stored(&mut self) -> Result<NdefFile, ()> {
+        let data = try_syscall!(self.trussed.read_file(Location::Internal, PathBuf::from(message::FILENAME)))
+            .map_err(drop)?.data;
+        message::validate(&data)?;
+        let mut file = NdefFile::new();
+        file.extend_from_slice(&(data.len() as u16).to_be_bytes())?;
+        file.extend_from_slice(&data)?;
+        Ok(file)
+    }
+
+    fn fields(&mut self) -> Fields {
+        try_syscall!(self.trussed.read_file(Location::Internal, PathBuf::from(template::FIELDS_FILENAME)))
+            .ok()
+            .and_then(|reply| trussed::cbor_deserialize(&reply.data).ok())
+            .unwrap_or_default()
+    }
+
+    /// The current counter value, `None` before the first tap.
+    fn counter(&mut self) -> Result<Option<u32>, ()> {
+        match try_syscall!(self.trussed.read_file(Location::Internal, PathBuf::from(template::COUNTER_FILENAME))) {
+            Ok(reply) => {
+                let bytes: [u8; 4] = reply.data.as_slice().try_into().map_err(drop)?;
+                Ok(Some(u32::from_be_bytes(bytes)))
+            }
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// The next counter value, persisted before it is used.
+    fn increment_counter(&mut self) -> Result<u32, ()> {
+        let counter = match self.counter()? {
+            Some(counter) => counter.checked_add(1).ok_or(())?,
+            None => 0,
+        };
+        let data = Message::from_slice(&counter.to_be_bytes()).unwrap();
+        try_syscall!(self.trussed.write_file(
+            Location::Internal,
+            PathBuf::from(template::COUNTER_FILENAME),
+            data,
+            None,
+        )).map_err(drop)?;
+        Ok(counter)
+    }
+
+    fn mac(&mut self, key: KeyId, counter: u32) -> Result<[u8; 8], ()> {
+        let message = template::mac_input(&self.uuid, counter);
+        let signature = try_syscall!(self.trussed.sign_hmacsha256(key, &message)).map_err(drop)?.signature;
+        let mut mac = [0u8; 8];
+        mac.copy_from_slice(&signature[..8]);
+        Ok(mac)
+    }
+
+    /// Only a tap, i.e. `interface` contactless, increments the counter.
+    fn generate(&mut self, interface: app::Interface) -> Result<NdefFile, ()> {
+        let url = try_syscall!(self.trussed.read_file(Location::Internal, PathBuf::from(template::URL_FILENAME)))
+            .map_err(drop)?.data;
+        let fields = self.fields();
+        let counter = match (fields.counter, interface) {
+            (false, _) => None,
+            (true, app::Interface::Contactless) => Some(self.increment_counter()?),
+            (true, app::Interface::Contact) => Some(self.counter()?.unwrap_or(0)),
+        };
+        let mac = match (fields.hmac, counter) {
+            (Some(key), Some(counter)) => Some(self.mac(key, counter)?),
+            _ => None,
+        };
+        let values = Values { uuid: fields.uuid.then(|| self.uuid), counter, mac };
+        template::render(&url, &values)
+    }
+
+    fn configure(&mut self, data: &[u8]) -> Result<(), Status> {
+        let configuration: Configuration = trussed::cbor_deserialize(data)
+            .map_err(|_| Status::IncorrectDataParameter)?;
+        if !configuration.is_valid() {
+            return Err(Status::IncorrectDataParameter);
+        }
+        let previous = self.fields();
+        if configuration.hmac && configuration.hmac_key.is_none() && previous.hmac.is_none() {
+            return Err(Status::ConditionsOfUseNotSatisfied);
+        }
+        if syscall!(self.trussed.confirm_user_present(15_000)).result.is_err() {
+            return Err(Status::ConditionsOfUseNotSatisfied);
+        }
+
+        if let Some(url) = configuration.url {
+            try_syscall!(self.trussed.write_file(
+                Location::Internal,
+                PathBuf::from(template::URL_FILENAME),
+                Message::from_slice(url.as_bytes()).unwrap(),
+                None,
+            )).map_err(|_| Status::NotEnoughMemory)?;
+            // there may be none
+            try_syscall!(self.trussed.remove_file(Location::Internal, PathBuf::from(message::FILENAME))).ok();
+        }
+
+        if let Some(write_lock) = configuration.write_lock {
+            try_syscall!(self.trussed.write_file(
+                Location::Internal,
+                PathBuf::from(message::WRITE_LOCK_FILENAME),
+                Message::from_slice(&[write_lock as u8]).unwrap(),
+                None,
+            )).map_err(|_| Status::NotEnoughMemory)?;
+        }
+
+        let mut hmac = previous.hmac;
+        if configuration.hmac_key.is_some() || !configuration.hmac {
+            if let Some(key) = hmac.take() {
+                syscall!(self.trussed.delete(key));
+            }
+        }
+        if let (true, Some(key)) = (configuration.hmac, configuration.hmac_key) {
+            hmac = Some(try_syscall!(self.trussed.unsafe_inject_shared_key(key, Location::Internal))
+                .map_err(|_| Status::NotEnoughMemory)?.key);
+        }
+
+        let fields = Fields { uuid: configuration.uuid, counter: configuration.counter, hmac };
+        let data: Message = trussed::cbor_serialize_bytes(&fields).map_err(|_| Status::NotEnoughMemory)?;
+        try_syscall!(self.trussed.write_file(
+            Location::Internal,
+            PathBuf::from(template::FIELDS_FILENAME),
+            data,
+            None,
+        )).map_err(|_| Status::NotEnoughMemory)?;
+        Ok(())
+    }
+
+    /// Writes to the NDEF file, as readers do: NLEN zero first, then the message, then NLEN.
+    /// Whenever NLEN is not zero afterwards, the message must be valid, and is stored.
+    fn update_binary(&mut self, offset: usize, data: &[u8]) -> Result<(), Status> {
+        match self.selected {
+            Some(Selected::Ndef) => {}
+            Some(Selected::CapabilityContainer) => return Err(Status::SecurityStatusNotSatisfied),
+            None => return Err(Status::ConditionsOfUseNotSatisfied),
+        }
+        if self.write_locked {
+            return Err(Status::SecurityStatusNotSatisfied);
+        }
+        let end = offset.checked_add(data.len())
+            .filter(|end| *end <= template::MAX_NDEF_FILE)
+            .ok_or(Status::WrongLength)?;
+
+        let mut file = self.ndef.clone();
+        if file.len() < end {
+            file.resize(end, 0).unwrap();
+        }
+        file[offset..end].copy_from_slice(data);
+
+        let length = match file.get(..2) {
+            Some(nlen) => u16::from_be_bytes([nlen[0], nlen[1]]) as usize,
+            None => 0,
+        };
+        if length != 0 {
+            let written = file.get(2..2 + length).ok_or(Status::WrongLength)?;
+            message::validate(written).map_err(|_| Status::IncorrectDataParameter)?;
+            try_syscall!(self.trussed.write_file(
+                Location::Internal,
+                PathBuf::from(message::FILENAME),
+                Message::from_slice(written).map_err(|_| Status::NotEnoughMemory)?,
+                None,
+            )).map_err(|_| Status::NotEnoughMemory)?;
+            file.truncate(2 + length);
+        }
+        self.ndef = file;
+        Ok(())
     }
 }
 
-impl<'a> iso7816::App for App<'a> {
+impl<T> iso7816::App for App<T> {
     fn aid(&self) -> iso7816::Aid {
         iso7816::Aid::new(&[0xD2u8, 0x76, 0x00, 0x00, 0x85, 0x01, 0x01])
     }
 }
 
-impl<'a> app::App<CommandSize, ResponseSize> for App<'a> {
+impl<T> app::App<CommandSize, ResponseSize> for App<T>
+where T: trussed::Client + client::HmacSha256,
+{
 
     fn select(&mut self, _apdu: &Command, _reply: &mut response::Data) -> app::Result {
+        self.selected = None;
+        self.write_locked = self.is_write_locked();
         Ok(())
     }
 
     fn deselect(&mut self) {}
 
-    fn call(&mut self, _type: app::Interface, apdu: &Command, reply: &mut response::Data) -> app::Result {
+    fn call(&mut self, interface: app::Interface, apdu: &Command, reply: &mut response::Data) -> app::Result {
         let instruction = apdu.instruction();
+        let ins: u8 = apdu.instruction().into();
         let p1 = apdu.p1;
         let p2 = apdu.p2;
         let expected = apdu.expected();
@@ -61,31 +282,58 @@ impl<'a> app::App<CommandSize, ResponseSize> for App<'a> {
             Instruction::Select => {
 
                 if payload.starts_with(&[0xE1u8, 0x03]) {
-                    self.reader = &Self::CAPABILITY_CONTAINER;
+                    self.selected = Some(Selected::CapabilityContainer);
                     Ok(())
                 } else if payload.starts_with(&[0xE1u8, 0x04]) {
-                    self.reader = &Self::NDEF;
+                    // unlike SELECT of the app, this tells the interface, so the counter goes up here
+                    self.selected = Some(Selected::Ndef);
+                    self.ndef = self.stored()
+                        .or_else(|_| self.generate(interface))
+                        .unwrap_or_else(|_| NdefFile::from_slice(&Self::NDEF).unwrap());
                     Ok(())
                 } else {
                     Err(Status::NotFound)
                 }
             }
             Instruction::ReadBinary => {
+                let container;
+                let file: &[u8] = match self.selected {
+                    Some(Selected::CapabilityContainer) => {
+                        container = self.capability_container();
+                        &container
+                    }
+                    Some(Selected::Ndef) => &self.ndef,
+                    None => return Err(Status::ConditionsOfUseNotSatisfied),
+                };
                 let offset = (((p1 & 0xef) as usize) << 8) | p2 as usize;
+                if offset > file.len() {
+                    return Err(Status::IncorrectP1OrP2Parameter);
+                }
                 let len_to_read =
-                    if expected as usize > (self.reader.len() - offset) {
-                        self.reader.len() - offset
+                    if expected as usize > (file.len() - offset) {
+                        file.len() - offset
                     } else {
                         if expected > 0 {
                             expected as usize
                         } else {
-                            self.reader.len() - offset
+                            file.len() - offset
                         }
                     };
 
-                reply.extend_from_slice(& self.reader[offset .. offset + len_to_read]).ok();
+                reply.extend_from_slice(& file[offset .. offset + len_to_read]).ok();
                 Ok(())
             }
+            Instruction::Unknown(CONFIGURE) if interface == app::Interface::Contact => {
+                self.configure(payload)
+            }
+            _ if ins == UPDATE_BINARY => {
+                // P1 bit 8 would be a short EF identifier
+                if p1 & 0x80 != 0 {
+                    return Err(Status::IncorrectP1OrP2Parameter);
+                }
+                let offset = ((p1 as usize) << 8) | p2 as usize;
+                self.update_binary(offset, payload)
+            }
             _ => {
                 Err(Status::ConditionsOfUseNotSatisfied)
             }
@@ -0,0 +1,242 @@
+//! NDEF messages generated from a URL template.
+//!
+//! The template is the URL in the file `url` (the provisioner writes it as `/ndef/dat/url`),
+//! with the enabled `Fields` appended as query parameters, in this order:
+//!
+//! - `uid`: the device UUID, 32 hex digits
+//! - `ctr`: the tap counter, 8 hex digits, incremented whenever the NDEF file is selected over
+//!   NFC; over USB, the current value is shown
+//! - `mac`: the first 8 bytes of HMAC-SHA256 over `mac_input`, 16 hex digits; the key is set
+//!   with CONFIGURE
+//!
+//! A backend knowing the key can check the URL came from a tap on this device, and reject
+//! replays by the counter, much like NTAG 424 SUN messages.
+
+use serde::{Deserialize, Serialize};
+use trussed::types::KeyId;
+
+pub const URL_FILENAME: &[u8] = b"url";
+pub const FIELDS_FILENAME: &[u8] = b"fields.cbor";
+pub const COUNTER_FILENAME: &[u8] = b"counter";
+
+/// As the provisioner's manifest accepts it.
+pub const MAX_URL: usize = 128;
+pub const MIN_HMAC_KEY: usize = 16;
+pub const MAX_HMAC_KEY: usize = 32;
+
+/// The NDEF file, i.e. two bytes NLEN and the message, which is stored as one Trussed `Message`.
+pub const MAX_NDEF_FILE: usize = 1024;
+pub type NdefFile = heapless::Vec<u8, MAX_NDEF_FILE>;
+
+/// Which fields are appended, persisted as `FIELDS_FILENAME`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub struct Fields {
+    pub uuid: bool,
+    pub counter: bool,
+    /// The HMAC key, if `mac` is appended
+    pub hmac: Option<KeyId>,
+}
+
+/// The values to append, if their field is enabled.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Values {
+    pub uuid: Option<[u8; 16]>,
+    pub counter: Option<u32>,
+    pub mac: Option<[u8; 8]>,
+}
+
+/// Data of the CONFIGURE instruction, a CBOR map. Without `url`, the current one is kept,
+/// otherwise a message written by a reader is removed. Without `hmac_key`, `hmac` uses the key
+/// set before. With `write_lock`, readers cannot write the NDEF file; without it, the current
+/// setting is kept, see `message::WRITE_LOCK_FILENAME`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Configuration<'a> {
+    #[serde(borrow)]
+    pub url: Option<&'a str>,
+    #[serde(default)]
+    pub uuid: bool,
+    #[serde(default)]
+    pub counter: bool,
+    #[serde(default)]
+    pub hmac: bool,
+    #[serde(borrow)]
+    pub hmac_key: Option<&'a [u8]>,
+    pub write_lock: Option<bool>,
+}
+
+impl Configuration<'_> {
+    pub fn is_valid(&self) -> bool {
+        let url_valid = self.url.map_or(true, is_url);
+        let key_valid = self.hmac_key.map_or(true, |key| (MIN_HMAC_KEY..=MAX_HMAC_KEY).contains(&key.len()));
+        // the MAC is over the counter
+        url_valid && key_valid && (self.counter || !self.hmac)
+    }
+}
+
+/// Printable ASCII, as URLs in NDEF URI records are.
+pub fn is_url(url: &str) -> bool {
+    !url.is_empty() && url.len() <= MAX_URL && url.bytes().all(|byte| (0x21..0x7f).contains(&byte))
+}
+
+/// The UUID and the counter (big endian `u32`).
+pub fn mac_input(uuid: &[u8; 16], counter: u32) -> [u8; 20] {
+    let mut input = [0u8; 20];
+    input[..16].copy_from_slice(uuid);
+    input[16..].copy_from_slice(&counter.to_be_bytes());
+    input
+}
+
+/// URI identifier codes (NFC Forum URI RTD) for the prefixes we abbreviate.
+const PREFIXES: [(u8, &[u8]); 4] = [
+    (0x02, b"https://www."),
+    (0x01, b"http://www."),
+    (0x04, b"https://"),
+    (0x03, b"http://"),
+];
+
+/// The NDEF file with a single URI record.
+pub fn render(url: &[u8], values: &Values) -> Result<NdefFile, ()> {
+    let (code, rest) = PREFIXES.iter()
+        .find(|(_, prefix)| url.starts_with(prefix))
+        .map(|(code, prefix)| (*code, &url[prefix.len()..]))
+        .unwrap_or((0x00, url));
+
+    // short record
+    let mut payload = heapless::Vec::<u8, 255>::new();
+    payload.push(code).map_err(drop)?;
+    payload.extend_from_slice(rest)?;
+
+    let mut separator = if url.contains(&b'?') { b'&' } else { b'?' };
+    if let Some(uuid) = &values.uuid {
+        append(&mut payload, &mut separator, b"uid", uuid)?;
+    }
+    if let Some(counter) = values.counter {
+        append(&mut payload, &mut separator, b"ctr", &counter.to_be_bytes())?;
+    }
+    if let Some(mac) = &values.mac {
+        append(&mut payload, &mut separator, b"mac", mac)?;
+    }
+
+    let record = [
+        0xd1, /* TNF: well-known, MB, ME, SR */
+        0x01, /* payload type length */
+        payload.len() as u8,
+        0x55, /* payload type: U = URL */
+    ];
+    let length = (record.len() + payload.len()) as u16;
+    let mut file = NdefFile::new();
+    file.extend_from_slice(&length.to_be_bytes())?;
+    file.extend_from_slice(&record)?;
+    file.extend_from_slice(&payload)?;
+    Ok(file)
+}
+
+fn append(payload: &mut heapless::Vec<u8, 255>, separator: &mut u8, name: &[u8], value: &[u8]) -> Result<(), ()> {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    payload.push(*separator).map_err(drop)?;
+    payload.extend_from_slice(name)?;
+    payload.push(b'=').map_err(drop)?;
+    for byte in value {
+        payload.push(DIGITS[(byte >> 4) as usize]).map_err(drop)?;
+        payload.push(DIGITS[(byte & 0xf) as usize]).map_err(drop)?;
+    }
+    *separator = b'&';
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    const UUID: [u8; 16] = hex!("0123456789abcdef 0011223344556677");
+
+    /// The URI record's payload, checking the header.
+    fn payload(file: &NdefFile) -> &[u8] {
+        let length = u16::from_be_bytes([file[0], file[1]]) as usize;
+        assert_eq!(length, file.len() - 2);
+        assert_eq!(file[2..4], [0xd1, 0x01]);
+        assert_eq!(file[4] as usize, file.len() - 6);
+        assert_eq!(file[5], 0x55);
+        &file[6..]
+    }
+
+    #[test]
+    fn plain() {
+        let file = render(b"https://example.com", &Values::default()).unwrap();
+        assert_eq!(&file[..], b"\x00\x10\xd1\x01\x0c\x55\x04example.com");
+    }
+
+    #[test]
+    fn prefixes() {
+        let cases: [(&[u8], &[u8]); 5] = [
+            (b"https://www.nitrokey.com/", b"\x02nitrokey.com/"),
+            (b"http://www.nitrokey.com/", b"\x01nitrokey.com/"),
+            (b"https://nitrokey.com/", b"\x04nitrokey.com/"),
+            (b"http://nitrokey.com/", b"\x03nitrokey.com/"),
+            (b"ftp://nitrokey.com/", b"\x00ftp://nitrokey.com/"),
+        ];
+        for (url, expected) in cases {
+            assert_eq!(payload(&render(url, &Values::default()).unwrap()), expected);
+        }
+    }
+
+    #[test]
+    fn fields_in_order() {
+        let values = Values { uuid: Some(UUID), counter: Some(0x0102), mac: Some(hex!("f0e1d2c3b4a59687")) };
+        let file = render(b"https://example.com/t", &values).unwrap();
+        assert_eq!(
+            payload(&file),
+            &b"\x04example.com/t?uid=0123456789abcdef0011223344556677&ctr=00000102&mac=f0e1d2c3b4a59687"[..],
+        );
+
+        let values = Values { counter: Some(7), ..Values::default() };
+        let file = render(b"https://example.com/t", &values).unwrap();
+        assert_eq!(payload(&file), &b"\x04example.com/t?ctr=00000007"[..]);
+    }
+
+    #[test]
+    fn existing_query() {
+        let values = Values { uuid: Some(UUID), counter: Some(1), mac: None };
+        let file = render(b"https://example.com/t?x=1", &values).unwrap();
+        assert_eq!(
+            payload(&file),
+            &b"\x04example.com/t?x=1&uid=0123456789abcdef0011223344556677&ctr=00000001"[..],
+        );
+    }
+
+    #[test]
+    fn mac_input_layout() {
+        assert_eq!(mac_input(&UUID, 0x0102_0304), hex!("0123456789abcdef 0011223344556677 01020304"));
+    }
+
+    fn configuration<'a>() -> Configuration<'a> {
+        Configuration { url: None, uuid: false, counter: false, hmac: false, hmac_key: None, write_lock: None }
+    }
+
+    #[test]
+    fn valid_configurations() {
+        assert!(configuration().is_valid());
+        assert!(Configuration { url: Some("https://example.com"), uuid: true, ..configuration() }.is_valid());
+        assert!(Configuration { counter: true, ..configuration() }.is_valid());
+        assert!(Configuration { counter: true, hmac: true, hmac_key: Some(&[0; MIN_HMAC_KEY]), ..configuration() }.is_valid());
+        assert!(Configuration { counter: true, hmac: true, hmac_key: Some(&[0; MAX_HMAC_KEY]), ..configuration() }.is_valid());
+        // the key set before is used
+        assert!(Configuration { counter: true, hmac: true, ..configuration() }.is_valid());
+    }
+
+    #[test]
+    fn invalid_configurations() {
+        // the MAC is over the counter
+        assert!(!Configuration { hmac: true, hmac_key: Some(&[0; 16]), ..configuration() }.is_valid());
+        assert!(!Configuration { counter: true, hmac: true, hmac_key: Some(&[0; MIN_HMAC_KEY - 1]), ..configuration() }.is_valid());
+        assert!(!Configuration { counter: true, hmac: true, hmac_key: Some(&[0; MAX_HMAC_KEY + 1]), ..configuration() }.is_valid());
+
+        let long = [b'a'; MAX_URL + 1];
+        for url in ["", "https://example.com/a b", "https://example.com/\u{e4}", core::str::from_utf8(&long).unwrap()] {
+            assert!(!Configuration { url: Some(url), ..configuration() }.is_valid(), "{}", url);
+        }
+        assert!(Configuration { url: Some(core::str::from_utf8(&long[1..]).unwrap()), ..configuration() }.is_valid());
+    }
+}
@@ -277,7 +277,7 @@ where
         }
     }
 
-    pub fn borrow<F: Fn(&mut DEV) -> () >(&mut self, func: F) {
+    pub fn borrow<F: FnMut(&mut DEV) -> () >(&mut self, mut func: F) {
         func(&mut self.device);
     }
 
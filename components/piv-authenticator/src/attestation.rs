@@ -0,0 +1,233 @@
+//! On-device attestation certificates, in the format of Yubico's ATTEST command.
+//!
+//! The attested key's certificate is issued by the attestation key in slot F9, whose own
+//! certificate is injected by the provisioner app. Besides the public key, it carries Yubico's
+//! extensions under 1.3.6.1.4.1.41482.3, so existing tooling (`ykman`, `yubico-piv-tool`,
+//! `piv-go`) can interpret it.
+//!
+//! Reference: <https://developers.yubico.com/PIV/Introduction/PIV_attestation.html>
+
+use heapless::Vec;
+
+use crate::derp;
+use crate::state::{AsymmetricAlgorithm, KeyMetadata};
+
+/// Enough for the TBSCertificate, given an issuer name of reasonable size.
+pub const MAX_TBS_CERTIFICATE_LENGTH: usize = 1024;
+pub type TbsCertificate = Vec<u8, MAX_TBS_CERTIFICATE_LENGTH>;
+
+// 1.2.840.10045.4.3.2
+const OID_ECDSA_WITH_SHA256: &[u8] = &hex!("2A8648CE3D040302");
+// 1.2.840.10045.2.1
+const OID_EC_PUBLIC_KEY: &[u8] = &hex!("2A8648CE3D0201");
+// 1.2.840.10045.3.1.7
+const OID_PRIME256V1: &[u8] = &hex!("2A8648CE3D030107");
+// 1.3.101.112
+const OID_ED25519: &[u8] = &hex!("2B6570");
+// 1.3.101.110
+const OID_X25519: &[u8] = &hex!("2B656E");
+// 2.5.4.3
+const OID_COMMON_NAME: &[u8] = &hex!("550403");
+// 1.3.6.1.4.1.41482.3.3
+const OID_YUBICO_FIRMWARE_VERSION: &[u8] = &hex!("2B0601040182C40A0303");
+// 1.3.6.1.4.1.41482.3.7
+const OID_YUBICO_SERIAL: &[u8] = &hex!("2B0601040182C40A0307");
+// 1.3.6.1.4.1.41482.3.8
+const OID_YUBICO_POLICY: &[u8] = &hex!("2B0601040182C40A0308");
+
+// We have no clock, so use a fixed validity period,
+// ending with the "no well-defined expiration date" of RFC 5280, section 4.1.2.5.
+const NOT_BEFORE: &[u8] = b"210101000000Z";
+const NOT_AFTER: &[u8] = b"99991231235959Z";
+
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+const INTEGER: u8 = 0x02;
+const BIT_STRING: u8 = 0x03;
+const OCTET_STRING: u8 = 0x04;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const UTF8_STRING: u8 = 0x0c;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+
+pub type Result<T> = core::result::Result<T, ()>;
+
+/// Append the DER encoding of `tag`, the length of `value`, and `value`.
+fn tlv<const N: usize>(out: &mut Vec<u8, N>, tag: u8, value: &[u8]) -> Result<()> {
+    out.push(tag).map_err(drop)?;
+    match value.len() {
+        len @ 0..=0x7f => out.push(len as u8).map_err(drop)?,
+        len @ 0x80..=0xff => out.extend_from_slice(&[0x81, len as u8])?,
+        len @ 0x100..=0xffff => out.extend_from_slice(&[0x82, (len >> 8) as u8, len as u8])?,
+        _ => return Err(()),
+    }
+    out.extend_from_slice(value)
+}
+
+/// Return the (DER-encoded) subject of the DER-encoded certificate.
+///
+/// This is used as issuer of the attestation certificates.
+pub fn subject<const N: usize>(certificate: &[u8]) -> Option<Vec<u8, N>> {
+    let subject = derp::Input::from(certificate).read_all(derp::Error::Read, |input| {
+        derp::nested(input, SEQUENCE, |certificate| {
+            let tbs_certificate = derp::expect_tag_and_get_value(certificate, SEQUENCE)?;
+            certificate.skip_to_end();
+            tbs_certificate.read_all(derp::Error::Read, |tbs_certificate| {
+                // version, is optional
+                if tbs_certificate.peek(0xa0) {
+                    derp::read_tag_and_get_value(tbs_certificate)?;
+                }
+                // serialNumber, signature, issuer, validity
+                derp::expect_tag_and_get_value(tbs_certificate, INTEGER)?;
+                derp::expect_tag_and_get_value(tbs_certificate, SEQUENCE)?;
+                derp::expect_tag_and_get_value(tbs_certificate, SEQUENCE)?;
+                derp::expect_tag_and_get_value(tbs_certificate, SEQUENCE)?;
+                let subject = derp::expect_tag_and_get_value(tbs_certificate, SEQUENCE)?;
+                tbs_certificate.skip_to_end();
+                Ok(subject.as_slice_less_safe())
+            })
+        })
+    }).ok()?;
+
+    let mut encoded = Vec::new();
+    tlv(&mut encoded, SEQUENCE, subject).ok()?;
+    Some(encoded)
+}
+
+/// Everything that goes into the attestation certificate of a key.
+pub struct Attestation<'a> {
+    /// The reference of the slot holding the key, e.g. 0x9a
+    pub slot: u8,
+    pub metadata: KeyMetadata,
+    /// The raw public key, as serialized by Trussed (for P256, the concatenated coordinates)
+    pub public_key: &'a [u8],
+    /// Randomly generated by the caller, must be positive
+    pub serial_number: &'a [u8],
+    /// The DER encoded subject of the attestation certificate
+    pub issuer: &'a [u8],
+    pub firmware_version: [u8; 3],
+    pub device_serial: u32,
+}
+
+impl Attestation<'_> {
+    fn subject_public_key_info(&self) -> Result<Vec<u8, 128>> {
+        let mut algorithm = Vec::<u8, 32>::new();
+        let mut public_key = Vec::<u8, 72>::new();
+        // unused bits
+        public_key.push(0).map_err(drop)?;
+        match self.metadata.algorithm {
+            AsymmetricAlgorithm::P256 => {
+                tlv(&mut algorithm, OBJECT_IDENTIFIER, OID_EC_PUBLIC_KEY)?;
+                tlv(&mut algorithm, OBJECT_IDENTIFIER, OID_PRIME256V1)?;
+                // uncompressed point
+                public_key.push(0x04).map_err(drop)?;
+            }
+            AsymmetricAlgorithm::Ed255 => tlv(&mut algorithm, OBJECT_IDENTIFIER, OID_ED25519)?,
+            AsymmetricAlgorithm::X255 => tlv(&mut algorithm, OBJECT_IDENTIFIER, OID_X25519)?,
+        }
+        public_key.extend_from_slice(self.public_key)?;
+
+        let mut content = Vec::<u8, 128>::new();
+        tlv(&mut content, SEQUENCE, &algorithm)?;
+        tlv(&mut content, BIT_STRING, &public_key)?;
+
+        let mut spki = Vec::new();
+        tlv(&mut spki, SEQUENCE, &content)?;
+        Ok(spki)
+    }
+
+    fn extensions(&self) -> Result<Vec<u8, 128>> {
+        let mut serial = Vec::<u8, 8>::new();
+        let device_serial = self.device_serial.to_be_bytes();
+        let first = device_serial.iter().position(|&b| b != 0).unwrap_or(3);
+        if device_serial[first] & 0x80 != 0 {
+            serial.push(0).map_err(drop)?;
+        }
+        serial.extend_from_slice(&device_serial[first..])?;
+        let mut encoded_serial = Vec::<u8, 8>::new();
+        tlv(&mut encoded_serial, INTEGER, &serial)?;
+
+        let policy = [
+            u8::from(self.metadata.pin_policy),
+            u8::from(self.metadata.touch_policy),
+        ];
+
+        let mut extensions = Vec::<u8, 128>::new();
+        for (oid, value) in [
+            (OID_YUBICO_FIRMWARE_VERSION, &self.firmware_version[..]),
+            (OID_YUBICO_SERIAL, &encoded_serial[..]),
+            (OID_YUBICO_POLICY, &policy[..]),
+        ].iter() {
+            let mut extension = Vec::<u8, 32>::new();
+            tlv(&mut extension, OBJECT_IDENTIFIER, oid)?;
+            tlv(&mut extension, OCTET_STRING, value)?;
+            tlv(&mut extensions, SEQUENCE, &extension)?;
+        }
+
+        let mut sequence = Vec::<u8, 128>::new();
+        tlv(&mut sequence, SEQUENCE, &extensions)?;
+        let mut explicit = Vec::new();
+        tlv(&mut explicit, 0xa3, &sequence)?;
+        Ok(explicit)
+    }
+
+    /// The DER encoded TBSCertificate, to be signed by the attestation key.
+    pub fn tbs_certificate(&self) -> Result<TbsCertificate> {
+        let mut content = TbsCertificate::new();
+
+        // version: v3
+        content.extend_from_slice(&hex!("A003 020102"))?;
+        tlv(&mut content, INTEGER, self.serial_number)?;
+
+        let mut signature_algorithm = Vec::<u8, 16>::new();
+        tlv(&mut signature_algorithm, OBJECT_IDENTIFIER, OID_ECDSA_WITH_SHA256)?;
+        tlv(&mut content, SEQUENCE, &signature_algorithm)?;
+
+        content.extend_from_slice(self.issuer)?;
+
+        let mut validity = Vec::<u8, 32>::new();
+        tlv(&mut validity, UTC_TIME, NOT_BEFORE)?;
+        tlv(&mut validity, GENERALIZED_TIME, NOT_AFTER)?;
+        tlv(&mut content, SEQUENCE, &validity)?;
+
+        // subject: CN=PIV Attestation <slot>
+        let mut common_name = Vec::<u8, 32>::new();
+        common_name.extend_from_slice(b"PIV Attestation ")?;
+        for nibble in [self.slot >> 4, self.slot & 0xf].iter() {
+            common_name.push(b"0123456789abcdef"[*nibble as usize]).map_err(drop)?;
+        }
+        let mut attribute = Vec::<u8, 48>::new();
+        tlv(&mut attribute, OBJECT_IDENTIFIER, OID_COMMON_NAME)?;
+        tlv(&mut attribute, UTF8_STRING, &common_name)?;
+        let mut relative_distinguished_name = Vec::<u8, 48>::new();
+        tlv(&mut relative_distinguished_name, SEQUENCE, &attribute)?;
+        let mut name = Vec::<u8, 48>::new();
+        tlv(&mut name, SET, &relative_distinguished_name)?;
+        tlv(&mut content, SEQUENCE, &name)?;
+
+        content.extend_from_slice(&self.subject_public_key_info()?)?;
+        content.extend_from_slice(&self.extensions()?)?;
+
+        let mut tbs_certificate = TbsCertificate::new();
+        tlv(&mut tbs_certificate, SEQUENCE, &content)?;
+        Ok(tbs_certificate)
+    }
+}
+
+/// Assemble the DER encoded certificate from its signed parts.
+pub fn certificate<const N: usize>(tbs_certificate: &[u8], signature: &[u8], out: &mut Vec<u8, N>) -> Result<()> {
+    let mut signature_algorithm = Vec::<u8, 16>::new();
+    tlv(&mut signature_algorithm, OBJECT_IDENTIFIER, OID_ECDSA_WITH_SHA256)?;
+
+    let mut signature_value = Vec::<u8, 80>::new();
+    // unused bits
+    signature_value.push(0).map_err(drop)?;
+    signature_value.extend_from_slice(signature)?;
+
+    let mut content = Vec::<u8, { MAX_TBS_CERTIFICATE_LENGTH + 128 }>::new();
+    content.extend_from_slice(tbs_certificate)?;
+    tlv(&mut content, SEQUENCE, &signature_algorithm)?;
+    tlv(&mut content, BIT_STRING, &signature_value)?;
+
+    tlv(out, SEQUENCE, &content)
+}
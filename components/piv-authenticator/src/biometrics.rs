@@ -0,0 +1,48 @@
+//! On-card biometric comparison (OCC), SP 800-73-4, Part 2, Section 3.2.1
+//!
+//! The capture and matching happens on the device itself, e.g. with a fingerprint reader
+//! attached to the MCU. The PIV application only ever learns whether the presented finger
+//! matched an enrolled template, so the hook is provided by the platform.
+
+use crate::commands::VerifyKeyReference;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Finger {
+    /// key reference 0x96
+    Primary,
+    /// key reference 0x97
+    Secondary,
+}
+
+impl Finger {
+    pub fn key_reference(self) -> u8 {
+        match self {
+            Self::Primary => 0x96,
+            Self::Secondary => 0x97,
+        }
+    }
+}
+
+impl core::convert::TryFrom<VerifyKeyReference> for Finger {
+    type Error = ();
+    fn try_from(key_reference: VerifyKeyReference) -> Result<Self, Self::Error> {
+        match key_reference {
+            VerifyKeyReference::PrimaryFingerOcc => Ok(Self::Primary),
+            VerifyKeyReference::SecondaryFingerOcc => Ok(Self::Secondary),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Platform hook for on-card biometric comparison.
+///
+/// Enrollment is out of scope for PIV, it is up to the platform (e.g. a button gesture).
+pub trait Biometrics {
+    /// Whether there is a template for this finger.
+    fn is_enrolled(&mut self, finger: Finger) -> bool;
+
+    /// Capture a finger and compare it with the enrolled template.
+    ///
+    /// Blocks until either a finger is presented, or the platform gives up.
+    fn verify(&mut self, finger: Finger) -> bool;
+}
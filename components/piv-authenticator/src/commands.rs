@@ -9,6 +9,9 @@ use core::convert::{TryFrom, TryInto};
 use iso7816::{Instruction, Status};
 
 pub use crate::{container as containers, piv_types, Pin, Puk};
+use crate::constants::YubicoPivExtension;
+use crate::derp;
+use crate::state::{PinPolicy, TouchPolicy};
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Command<'l> {
@@ -36,6 +39,11 @@ pub enum Command<'l> {
     /// Store a data object / container.
     PutData(PutData),
     GenerateAsymmetric(GenerateAsymmetric),
+    /// Yubico extension: store an externally generated private key in a slot.
+    ImportAsymmetricKey(ImportAsymmetricKey<'l>),
+    /// The remaining Yubico extensions, see
+    /// <https://developers.yubico.com/PIV/Introduction/Yubico_extensions.html>
+    YubicoExtension(YubicoPivExtension),
 }
 
 impl<'l> Command<'l> {
@@ -143,6 +151,10 @@ pub struct VerifyArguments<'l> {
 pub enum VerifyLogin {
     PivPin(Pin),
     GlobalPin([u8; 8]),
+    /// Eight ASCII digits, only accepted over secure messaging
+    PairingCode([u8; 8]),
+    /// On-card biometric comparison, the finger is captured by the device itself
+    Finger(crate::biometrics::Finger),
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -156,6 +168,25 @@ impl TryFrom<VerifyArguments<'_>> for Verify {
     type Error = Status;
     fn try_from(arguments: VerifyArguments<'_>) -> Result<Self, Self::Error> {
         let VerifyArguments { key_reference, logout, data } = arguments;
+        if key_reference == VerifyKeyReference::PairingCode {
+            return Ok(match (logout.0, data.len()) {
+                (false, 0) => Verify::Status(key_reference),
+                (false, 8) if data.iter().all(u8::is_ascii_digit) =>
+                    Verify::Login(VerifyLogin::PairingCode(data.try_into().unwrap())),
+                (false, _) => return Err(Status::IncorrectDataParameter),
+                (true, _) => return Err(Status::IncorrectP1OrP2Parameter),
+            });
+        }
+        if let Ok(finger) = crate::biometrics::Finger::try_from(key_reference) {
+            return Ok(match (logout.0, data) {
+                (false, []) => Verify::Status(key_reference),
+                // empty biometric data template, as OCC data is captured on-card
+                (false, [0x7f, 0x2e, 0x00]) => Verify::Login(VerifyLogin::Finger(finger)),
+                (false, _) => return Err(Status::IncorrectDataParameter),
+                (true, []) => Verify::Logout(key_reference),
+                (true, _) => return Err(Status::IncorrectDataParameter),
+            });
+        }
         if key_reference != VerifyKeyReference::PivPin {
             return Err(Status::FunctionNotSupported);
         }
@@ -206,6 +237,10 @@ impl TryFrom<ChangeReferenceArguments<'_>> for ChangeReference {
     fn try_from(arguments: ChangeReferenceArguments<'_>) -> Result<Self, Self::Error> {
         let ChangeReferenceArguments { key_reference, data } = arguments;
 
+        if data.len() != 16 {
+            return Err(Status::IncorrectDataParameter);
+        }
+
         use ChangeReferenceKeyReference::*;
         Ok(match (key_reference, data) {
             (GlobalPin, _) => return Err(Status::FunctionNotSupported),
@@ -226,10 +261,11 @@ impl TryFrom<ChangeReferenceArguments<'_>> for ChangeReference {
     }
 }
 
+/// The data field contains the PUK followed by the new PIN (SP 800-73-4, Part 2, Section 3.2.3)
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct ResetPinRetries {
-    pub padded_pin: [u8; 8],
-    pub puk: [u8;  8],
+    pub puk: Puk,
+    pub new_pin: Pin,
 }
 
 impl TryFrom<&[u8]> for ResetPinRetries {
@@ -239,8 +275,8 @@ impl TryFrom<&[u8]> for ResetPinRetries {
             return Err(Status::IncorrectDataParameter);
         }
         Ok(Self {
-            padded_pin: data[..8].try_into().unwrap(),
-            puk: data[8..].try_into().unwrap(),
+            puk: Puk::try_from(&data[..8]).map_err(|_| Status::IncorrectDataParameter)?,
+            new_pin: Pin::try_from(&data[8..]).map_err(|_| Status::IncorrectDataParameter)?,
         })
     }
 }
@@ -320,25 +356,37 @@ pub struct AuthenticateArguments<'l> {
     pub data: &'l [u8],
 }
 
+/// The dynamic authentication template in the data is interpreted by the authenticator,
+/// as its meaning depends on the key in the referenced slot.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-pub enum Authenticate {
+pub struct Authenticate {
+    pub unparsed_algorithm: u8,
+    pub key_reference: AuthenticateKeyReference,
 }
 
 impl TryFrom<AuthenticateArguments<'_>> for Authenticate {
     type Error = Status;
-    fn try_from(_arguments: AuthenticateArguments<'_>) -> Result<Self, Self::Error> {
-        todo!();
+    fn try_from(arguments: AuthenticateArguments<'_>) -> Result<Self, Self::Error> {
+        let AuthenticateArguments { unparsed_algorithm, key_reference, data } = arguments;
+        if data.is_empty() {
+            return Err(Status::IncorrectDataParameter);
+        }
+        Ok(Self { unparsed_algorithm, key_reference })
     }
 }
 
+/// The data objects ('5C' tag list, '53' data) are interpreted by the authenticator.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct PutData {
 }
 
 impl TryFrom<&[u8]> for PutData {
     type Error = Status;
-    fn try_from(_data: &[u8]) -> Result<Self, Self::Error> {
-        todo!();
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.is_empty() {
+            return Err(Status::IncorrectDataParameter);
+        }
+        Ok(Self {})
     }
 }
 
@@ -350,17 +398,57 @@ pub enum GenerateAsymmetricKeyReference {
     Signature = 0x9c,
     Management = 0x9d,
     CardAuthentication = 0x9e,
+    Retired01 = 0x82,
+    Retired02 = 0x83,
+    Retired03 = 0x84,
+    Retired04 = 0x85,
+    Retired05 = 0x86,
+    Retired06 = 0x87,
+    Retired07 = 0x88,
+    Retired08 = 0x89,
+    Retired09 = 0x8A,
+    Retired10 = 0x8B,
+    Retired11 = 0x8C,
+    Retired12 = 0x8D,
+    Retired13 = 0x8E,
+    Retired14 = 0x8F,
+    Retired15 = 0x90,
+    Retired16 = 0x91,
+    Retired17 = 0x92,
+    Retired18 = 0x93,
+    Retired19 = 0x94,
+    Retired20 = 0x95,
 }
 
 impl TryFrom<u8> for GenerateAsymmetricKeyReference {
     type Error = Status;
     fn try_from(p2: u8) -> Result<Self, Self::Error> {
         match p2 {
-            0x04 => Err(Status::FunctionNotSupported),
+            0x04 => Ok(Self::SecureMessaging),
             0x9a => Ok(Self::Authentication),
             0x9c => Ok(Self::Signature),
             0x9d => Ok(Self::Management),
             0x9e => Ok(Self::CardAuthentication),
+            0x82 => Ok(Self::Retired01),
+            0x83 => Ok(Self::Retired02),
+            0x84 => Ok(Self::Retired03),
+            0x85 => Ok(Self::Retired04),
+            0x86 => Ok(Self::Retired05),
+            0x87 => Ok(Self::Retired06),
+            0x88 => Ok(Self::Retired07),
+            0x89 => Ok(Self::Retired08),
+            0x8A => Ok(Self::Retired09),
+            0x8B => Ok(Self::Retired10),
+            0x8C => Ok(Self::Retired11),
+            0x8D => Ok(Self::Retired12),
+            0x8E => Ok(Self::Retired13),
+            0x8F => Ok(Self::Retired14),
+            0x90 => Ok(Self::Retired15),
+            0x91 => Ok(Self::Retired16),
+            0x92 => Ok(Self::Retired17),
+            0x93 => Ok(Self::Retired18),
+            0x94 => Ok(Self::Retired19),
+            0x95 => Ok(Self::Retired20),
             _ => Err(Status::KeyReferenceNotFound),
         }
     }
@@ -372,14 +460,125 @@ pub struct GenerateAsymmetricArguments<'l> {
     pub data: &'l [u8],
 }
 
+/// The control reference template ('AC') is interpreted by the authenticator.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-pub enum GenerateAsymmetric {
+pub struct GenerateAsymmetric {
+    pub key_reference: GenerateAsymmetricKeyReference,
 }
 
 impl TryFrom<GenerateAsymmetricArguments<'_>> for GenerateAsymmetric {
     type Error = Status;
-    fn try_from(_arguments: GenerateAsymmetricArguments<'_>) -> Result<Self, Self::Error> {
-        todo!();
+    fn try_from(arguments: GenerateAsymmetricArguments<'_>) -> Result<Self, Self::Error> {
+        let GenerateAsymmetricArguments { key_reference, data } = arguments;
+        if data.is_empty() {
+            return Err(Status::IncorrectDataParameter);
+        }
+        Ok(Self { key_reference })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ImportAsymmetricKeyArguments<'l> {
+    pub unparsed_algorithm: u8,
+    pub key_reference: AuthenticateKeyReference,
+    pub data: &'l [u8],
+}
+
+/// Private key material, as sent by `ykman piv keys import` and `yubico-piv-tool -a import-key`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ImportedKey<'l> {
+    /// Tags 01-05: the CRT components
+    Rsa { p: &'l [u8], q: &'l [u8], dp: &'l [u8], dq: &'l [u8], qinv: &'l [u8] },
+    /// Tag 06: the 32 byte private scalar
+    P256(&'l [u8]),
+    /// Tag 07: the 32 byte seed
+    Ed255(&'l [u8]),
+    /// Tag 08: the 32 byte secret
+    X255(&'l [u8]),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ImportAsymmetricKey<'l> {
+    pub key_reference: AuthenticateKeyReference,
+    pub key: ImportedKey<'l>,
+    /// None if not sent, or sent as 0x00 ("default")
+    pub pin_policy: Option<PinPolicy>,
+    /// None if not sent, or sent as 0x00 ("default")
+    pub touch_policy: Option<TouchPolicy>,
+}
+
+impl<'l> TryFrom<ImportAsymmetricKeyArguments<'l>> for ImportAsymmetricKey<'l> {
+    type Error = Status;
+    fn try_from(arguments: ImportAsymmetricKeyArguments<'l>) -> Result<Self, Self::Error> {
+        let ImportAsymmetricKeyArguments { unparsed_algorithm, key_reference, data } = arguments;
+
+        use AuthenticateKeyReference::*;
+        if let SecureMessaging | Administration = key_reference {
+            return Err(Status::KeyReferenceNotFound);
+        }
+
+        // example: 00 FE 11 9A 28
+        //   # P256 private scalar
+        //   06 20 <32 bytes>
+        //   # PinPolicy, 0x2 = once
+        //   AA 01 02
+        //   # TouchPolicy, 0x1 = never
+        //   AB 01 01
+        let mut rsa_components: [Option<&'l [u8]>; 5] = [None; 5];
+        let mut secret: Option<(u8, &'l [u8])> = None;
+        let mut pin_policy = None;
+        let mut touch_policy = None;
+
+        derp::Input::from(data).read_all(derp::Error::Read, |input| {
+            while !input.at_end() {
+                let (tag, value) = derp::read_tag_and_get_value(input)?;
+                let value = value.as_slice_less_safe();
+                match tag {
+                    0x01..=0x05 => rsa_components[tag as usize - 1] = Some(value),
+                    0x06..=0x08 => secret = Some((tag, value)),
+                    0xaa => pin_policy = match value {
+                        [0x00] => None,
+                        &[policy] => Some(PinPolicy::try_from(policy).map_err(|_| derp::Error::WrongValue)?),
+                        _ => return Err(derp::Error::WrongValue),
+                    },
+                    0xab => touch_policy = match value {
+                        [0x00] => None,
+                        &[policy] => Some(TouchPolicy::try_from(policy).map_err(|_| derp::Error::WrongValue)?),
+                        _ => return Err(derp::Error::WrongValue),
+                    },
+                    _ => return Err(derp::Error::WrongTag),
+                }
+            }
+            Ok(())
+        }).map_err(|_e| {
+            info_now!("error parsing ImportAsymmetricKey: {:?}", &_e);
+            Status::IncorrectDataParameter
+        })?;
+
+        let expect_secret = |expected_tag: u8| match secret {
+            Some((tag, value)) if tag == expected_tag && value.len() == 32 => Ok(value),
+            _ => Err(Status::IncorrectDataParameter),
+        };
+
+        // see `piv_types::Algorithms`
+        let key = match unparsed_algorithm {
+            // RSA 3072, RSA 1024, RSA 2048, RSA 4096
+            0x05 | 0x06 | 0x07 | 0x16 => match rsa_components {
+                [Some(p), Some(q), Some(dp), Some(dq), Some(qinv)] if secret.is_none() =>
+                    ImportedKey::Rsa { p, q, dp, dq, qinv },
+                _ => return Err(Status::IncorrectDataParameter),
+            },
+            0x11 => ImportedKey::P256(expect_secret(0x06)?),
+            0xe0 => ImportedKey::Ed255(expect_secret(0x07)?),
+            0xe1 => ImportedKey::X255(expect_secret(0x08)?),
+            _ => return Err(Status::IncorrectP1OrP2Parameter),
+        };
+
+        if !matches!(key, ImportedKey::Rsa { .. }) && rsa_components.iter().any(Option::is_some) {
+            return Err(Status::IncorrectDataParameter);
+        }
+
+        Ok(Self { key_reference, key, pin_policy, touch_policy })
     }
 }
 
@@ -445,6 +644,16 @@ impl<'l, const C: usize> TryFrom<&'l iso7816::Command<C>> for Command<'l> {
                 Self::GenerateAsymmetric(GenerateAsymmetric::try_from(GenerateAsymmetricArguments { key_reference, data })?)
             }
 
+            (0x00, Instruction::Unknown(0xfe), p1, p2) => {
+                let unparsed_algorithm = p1;
+                let key_reference = AuthenticateKeyReference::try_from(p2)?;
+                Self::ImportAsymmetricKey(ImportAsymmetricKey::try_from(ImportAsymmetricKeyArguments { unparsed_algorithm, key_reference, data })?)
+            }
+
+            (0x00, Instruction::Unknown(ins), _, _) => {
+                Self::YubicoExtension(YubicoPivExtension::try_from(ins).map_err(|_| Status::FunctionNotSupported)?)
+            }
+
             _ => return Err(Status::FunctionNotSupported),
         })
     }
@@ -1,5 +1,7 @@
 // https://developers.yubico.com/PIV/Introduction/Yubico_extensions.html
 
+use trussed::types::{CertId, KeyId};
+
 pub const RID_LENGTH: usize = 5;
 
 // top nibble of first byte is "category", here "A" = International
@@ -280,6 +282,8 @@ pub enum YubicoPivExtension {
     Attest = 0xf9,
     GetSerial = 0xf8, // also used via 0x01
     GetMetadata = 0xf7,
+    // also used to delete keys, with P1 = 0xff
+    MoveKey = 0xf6,
 }
 
 impl core::convert::TryFrom<u8> for YubicoPivExtension {
@@ -298,6 +302,7 @@ impl core::convert::TryFrom<u8> for YubicoPivExtension {
             // (0x00, 0xf8, 0x00, 0x00)
             0xf8 => YubicoPivExtension::GetSerial,
             0xf7 => YubicoPivExtension::GetMetadata,
+            0xf6 => YubicoPivExtension::MoveKey,
             _ => return Err(()),
         })
     }
@@ -379,45 +384,6 @@ pub const YUBICO_ATTESTATION_CERTIFICATE: &'static [u8; 754] = &[
     0xa0, 0x85,
 ];
 
-pub const YUBICO_ATTESTATION_CERTIFICATE_FOR_9A: &'static [u8; 584] = &[
-    0x30, 0x82, 0x02, 0x44, 0x30, 0x82, 0x01, 0x2c, 0xa0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x11, 0x00,
-    0xc6, 0x36, 0xe7, 0xb3, 0xa5, 0xa5, 0xa4, 0x98, 0x5d, 0x13, 0x6e, 0x43, 0x36, 0x2d, 0x13, 0xf7,
-    0x30, 0x0d, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b, 0x05, 0x00, 0x30,
-    0x21, 0x31, 0x1f, 0x30, 0x1d, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0c, 0x16, 0x59, 0x75, 0x62, 0x69,
-    0x63, 0x6f, 0x20, 0x50, 0x49, 0x56, 0x20, 0x41, 0x74, 0x74, 0x65, 0x73, 0x74, 0x61, 0x74, 0x69,
-    0x6f, 0x6e, 0x30, 0x20, 0x17, 0x0d, 0x31, 0x36, 0x30, 0x33, 0x31, 0x34, 0x30, 0x30, 0x30, 0x30,
-    0x30, 0x30, 0x5a, 0x18, 0x0f, 0x32, 0x30, 0x35, 0x32, 0x30, 0x34, 0x31, 0x37, 0x30, 0x30, 0x30,
-    0x30, 0x30, 0x30, 0x5a, 0x30, 0x25, 0x31, 0x23, 0x30, 0x21, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0c,
-    0x1a, 0x59, 0x75, 0x62, 0x69, 0x4b, 0x65, 0x79, 0x20, 0x50, 0x49, 0x56, 0x20, 0x41, 0x74, 0x74,
-    0x65, 0x73, 0x74, 0x61, 0x74, 0x69, 0x6f, 0x6e, 0x20, 0x39, 0x61, 0x30, 0x59, 0x30, 0x13, 0x06,
-    0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03,
-    0x01, 0x07, 0x03, 0x42, 0x00, 0x04, 0x83, 0x2a, 0x92, 0x47, 0x8b, 0x4e, 0xb5, 0x7a, 0x46, 0x1b,
-    0x2a, 0x5e, 0x0e, 0x44, 0x25, 0x03, 0x9b, 0xfb, 0x27, 0x94, 0x56, 0x78, 0xed, 0x48, 0x2b, 0x1c,
-    0xf2, 0x21, 0x61, 0x6d, 0xda, 0xbd, 0x3d, 0x8f, 0xb6, 0x2b, 0x75, 0xc6, 0xac, 0x3f, 0x83, 0x4a,
-    0x59, 0x4e, 0x5a, 0xdf, 0xed, 0xe7, 0x3a, 0xe4, 0x99, 0x1a, 0xe7, 0x33, 0x2f, 0x61, 0x2b, 0xcf,
-    0x6c, 0x0e, 0xd6, 0x78, 0x72, 0xeb, 0xa3, 0x3c, 0x30, 0x3a, 0x30, 0x11, 0x06, 0x0a, 0x2b, 0x06,
-    0x01, 0x04, 0x01, 0x82, 0xc4, 0x0a, 0x03, 0x03, 0x04, 0x03, 0x04, 0x03, 0x04, 0x30, 0x13, 0x06,
-    0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0xc4, 0x0a, 0x03, 0x07, 0x04, 0x05, 0x02, 0x03, 0x52,
-    0xf7, 0x43, 0x30, 0x10, 0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0xc4, 0x0a, 0x03, 0x08,
-    0x04, 0x02, 0x02, 0x02, 0x30, 0x0d, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01,
-    0x0b, 0x05, 0x00, 0x03, 0x82, 0x01, 0x01, 0x00, 0x02, 0x17, 0x38, 0xa8, 0xf6, 0x1d, 0x17, 0x35,
-    0xe1, 0x30, 0x9d, 0xd2, 0xd5, 0xc4, 0xd4, 0xd0, 0x0d, 0xe1, 0x9f, 0x37, 0x9a, 0xbe, 0xcf, 0x63,
-    0x6a, 0x0e, 0x2b, 0xd0, 0xd7, 0xa4, 0x04, 0x5c, 0x40, 0x7d, 0xf7, 0x43, 0x9b, 0xe4, 0xee, 0x7d,
-    0x96, 0x55, 0xd2, 0x91, 0xdc, 0x32, 0x82, 0x54, 0xfe, 0x2d, 0x9f, 0x19, 0x23, 0x54, 0xbb, 0xdd,
-    0x7d, 0x6b, 0xe9, 0x61, 0x2a, 0x1d, 0xc8, 0x13, 0x65, 0xe2, 0x04, 0x9f, 0xa2, 0x87, 0xde, 0x61,
-    0x92, 0xd5, 0xde, 0x46, 0xd4, 0xa4, 0xc2, 0xa6, 0xb4, 0x80, 0x5d, 0x4a, 0xa4, 0xd1, 0x1b, 0xa7,
-    0x34, 0xf2, 0x97, 0x7b, 0x7a, 0x5a, 0xad, 0x9a, 0xa8, 0x5d, 0x2a, 0xd4, 0x7f, 0xb1, 0x57, 0xbf,
-    0x26, 0x1d, 0x3d, 0xa6, 0xb3, 0xea, 0x3d, 0x3d, 0xf7, 0x94, 0xcd, 0x16, 0x36, 0x40, 0x24, 0xcd,
-    0x7c, 0x8e, 0x7a, 0xdb, 0x2d, 0xf9, 0x22, 0xda, 0x26, 0xb3, 0xc1, 0xc8, 0x00, 0xa3, 0x47, 0x97,
-    0x52, 0x10, 0x12, 0x73, 0x4b, 0xaf, 0x12, 0xfe, 0xb7, 0x0d, 0x9e, 0x91, 0x30, 0xa7, 0x52, 0xcf,
-    0x12, 0xd8, 0x2b, 0xdf, 0x12, 0x6a, 0xb6, 0x2f, 0x39, 0x24, 0xc6, 0x04, 0xa2, 0x6f, 0xed, 0x70,
-    0xb5, 0xf2, 0x0d, 0x2a, 0x73, 0xe3, 0x38, 0xa9, 0x9c, 0xfe, 0x35, 0x3e, 0xdc, 0x17, 0x40, 0x55,
-    0xd5, 0x95, 0x7f, 0x05, 0x8e, 0x24, 0xc2, 0xb3, 0xb1, 0x05, 0x2d, 0x69, 0x0c, 0xcf, 0x5b, 0xf7,
-    0x06, 0x40, 0x17, 0x36, 0x0a, 0xc3, 0xa5, 0xdb, 0x3c, 0xda, 0x62, 0xf8, 0x53, 0x2d, 0xf1, 0x3f,
-    0x04, 0x55, 0x70, 0x0c, 0x43, 0x7b, 0x1f, 0xa3, 0x63, 0xb1, 0xa0, 0x5e, 0x89, 0x28, 0x5b, 0x4f,
-    0x76, 0xa7, 0x05, 0xe1, 0x4c, 0x45, 0x55, 0x14, 0xff, 0x10, 0x10, 0x89, 0x69, 0x6a, 0x13, 0x3d,
-    0x89, 0xf2, 0xca, 0xfd, 0x14, 0x9a, 0xc4, 0xd0,
-];
 // pub const YUBICO_DEFAULT_MANAGEMENT_KEY: &'static [u8; 24] = b"123456781234567812345678";
 pub const YUBICO_DEFAULT_MANAGEMENT_KEY: &'static [u8; 24] = &[
     0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
@@ -425,8 +391,40 @@ pub const YUBICO_DEFAULT_MANAGEMENT_KEY: &'static [u8; 24] = &[
     0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
 ];
 
+// the all-zero block, encrypted with the default 3DES management key
+pub const YUBICO_DEFAULT_MANAGEMENT_KEY_KAT: [u8; 8] = [0xb0, 0x73, 0xdc, 0x3f, 0xb2, 0x09, 0x53, 0x6d];
+
 // stolen from le yubico
 pub const DISCOVERY_OBJECT: &'static [u8; 20] = b"~\x12O\x0b\xa0\x00\x00\x03\x08\x00\x00\x10\x00\x01\x00_/\x02@\x00";
 
+// first byte of the PIN usage policy in the discovery object
+pub const PIN_USAGE_POLICY_VCI: u8 = 0x08;
+pub const PIN_USAGE_POLICY_OCC: u8 = 0x10;
+pub const PIN_USAGE_POLICY_VCI_WITHOUT_PAIRING_CODE: u8 = 0x04;
+
+// biometric header template of a BIT, Table 7, Part 2, SP 800-73-4:
+// fingerprint, with templates in the INCITS 378 format
+pub const BIOMETRIC_HEADER_TEMPLATE: &[u8] = &[
+    0xa1, 0x0b,
+    0x81, 0x01, 0x08,
+    0x87, 0x02, 0x01, 0x01,
+    0x88, 0x02, 0x00, 0x07,
+];
+
+// vendor data object for the card verifiable certificate C_ICC of the secure messaging key (04)
+pub const SECURE_MESSAGING_CVC_TAG: &[u8] = &[0x5f, 0xff, 0x10];
+pub const SECURE_MESSAGING_CVC_FILENAME: &[u8] = b"secure-messaging.cvc";
+pub const SECURE_MESSAGING_SIGNER_FILENAME: &[u8] = b"secure-messaging-signer";
+
+// the cached touch policy accepts a touch for 15 seconds, as on YubiKeys
+pub const TOUCH_CACHE_DURATION: core::time::Duration = core::time::Duration::from_secs(15);
+
+// make up a version, be >= 5.0.0
+pub const YUBICO_FIRMWARE_VERSION: [u8; 3] = [0x06, 0x06, 0x06];
+
+// slot F9, injected by the provisioner app (as /piv/sec/00 and /piv/x5c/00)
+pub const ATTESTATION_CERT_ID: CertId = CertId::from_special(0);
+pub const ATTESTATION_KEY_ID: KeyId = KeyId::from_special(0);
+
 // import secrets; secrets.token_bytes(16)
 pub const GUID: &'static [u8; 16] = b"\x0c\x92\xc9\x04\xd0\xdeL\xd9\xf6\xd1\xa2\x9fE3\xca\xeb";
@@ -14,7 +14,7 @@ where
 
     fn deselect(&mut self) { self.deselect() }
 
-    fn call(&mut self, _: iso7816::Interface, apdu: &Command, reply: &mut response::Data) -> Result {
-        self.respond(apdu, reply)
+    fn call(&mut self, interface: iso7816::Interface, apdu: &Command, reply: &mut response::Data) -> Result {
+        self.respond_over(interface, apdu, reply)
     }
 }
@@ -7,6 +7,7 @@ generate_macros!();
 #[macro_use(hex)]
 extern crate hex_literal;
 
+mod attestation;
 pub mod commands;
 pub use commands::Command;
 pub mod constants;
@@ -14,12 +15,17 @@ pub mod container;
 #[cfg(feature = "apdu-dispatch")]
 mod dispatch;
 pub mod state;
+pub mod biometrics;
+pub use biometrics::{Biometrics, Finger};
 pub mod derp;
 pub mod piv_types;
+mod secure_messaging;
 pub use piv_types::{Pin, Puk};
 
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-use core::convert::TryInto;
+
+use core::convert::{TryFrom, TryInto};
 
 use flexiber::EncodableHeapless;
 use iso7816::{Data, Status};
@@ -30,6 +36,24 @@ use constants::*;
 
 pub type Result = iso7816::Result<()>;
 
+/// Access over the contactless interface, beyond the free-read data objects and card
+/// authentication, via the "virtual contact interface" (VCI) of SP 800-73-4, Part 1, Section 3.3.2.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VciPolicy {
+    /// No VCI, even with secure messaging
+    Disabled,
+    /// Secure messaging establishes the VCI
+    SecureMessaging,
+    /// Secure messaging, followed by VERIFY with the pairing code, establishes the VCI
+    SecureMessagingAndPairingCode,
+}
+
+impl Default for VciPolicy {
+    fn default() -> Self {
+        Self::SecureMessaging
+    }
+}
+
 /// PIV authenticator Trussed app.
 ///
 /// The `C` parameter is necessary, as PIV includes command sequences,
@@ -39,6 +63,9 @@ pub struct Authenticator<T, const C: usize>
 {
     state: state::State<C>,
     trussed: T,
+    uuid: [u8; 16],
+    vci_policy: VciPolicy,
+    biometrics: Option<&'static mut (dyn Biometrics + Send)>,
 }
 
 impl<T, const C: usize> iso7816::App for Authenticator<T, C>
@@ -54,6 +81,7 @@ where
 {
     pub fn new(
         trussed: T,
+        uuid: [u8; 16],
     )
         -> Self
     {
@@ -64,12 +92,55 @@ where
             // state: state::State::new(trussed.clone()),
             state: Default::default(),
             trussed,
+            uuid,
+            vci_policy: Default::default(),
+            biometrics: None,
+        }
+    }
+
+    pub fn with_vci_policy(mut self, vci_policy: VciPolicy) -> Self {
+        self.vci_policy = vci_policy;
+        self
+    }
+
+    /// Enable on-card biometric comparison (VERIFY with key references 96 and 97).
+    pub fn with_biometrics(mut self, biometrics: &'static mut (dyn Biometrics + Send)) -> Self {
+        self.biometrics = Some(biometrics);
+        self
+    }
+
+    fn is_enrolled(&mut self, finger: Finger) -> bool {
+        self.biometrics.as_mut().map(|biometrics| biometrics.is_enrolled(finger)).unwrap_or(false)
+    }
+
+    fn enrolled_fingers(&mut self) -> heapless::Vec<Finger, 2> {
+        [Finger::Primary, Finger::Secondary].iter()
+            .copied()
+            .filter(|&finger| self.is_enrolled(finger))
+            .collect()
+    }
+
+    /// The 4 byte serial reported via GetSerial and in attestation certificates,
+    /// obtained by folding the device UUID.
+    pub fn serial(&self) -> u32 {
+        let mut serial = [0u8; 4];
+        for (i, byte) in self.uuid.iter().enumerate() {
+            serial[i % 4] ^= byte;
         }
+        u32::from_be_bytes(serial)
     }
 
     // TODO: we'd like to listen on multiple AIDs.
     // The way apdu-dispatch currently works, this would deselect, resetting security indicators.
     pub fn deselect(&mut self) {
+        self.terminate_secure_messaging();
+    }
+
+    fn terminate_secure_messaging(&mut self) {
+        if self.state.runtime.secure_messaging.take().is_some() {
+            // whatever was verified over the VCI must not persist outside of it
+            self.state.runtime.app_security_status = Default::default();
+        }
     }
 
     pub fn select<const R: usize>(&mut self, _apdu: &iso7816::Command<C>, reply: &mut Data<R>) -> Result
@@ -96,6 +167,53 @@ where
     }
 
     pub fn respond<const R: usize>(&mut self, command: &iso7816::Command<C>, reply: &mut Data<R>) -> Result
+    {
+        self.respond_over(iso7816::Interface::Contact, command, reply)
+    }
+
+    /// Respond to a command received over the given interface.
+    ///
+    /// Commands with secure messaging are unwrapped, and their responses wrapped.
+    /// Over the contactless interface, the VCI policy applies.
+    pub fn respond_over<const R: usize>(&mut self, interface: iso7816::Interface, command: &iso7816::Command<C>, reply: &mut Data<R>) -> Result
+    {
+        if command.class().secure_messaging().none() {
+            // a plain command ends the secure messaging session
+            self.terminate_secure_messaging();
+            return self.respond_plain(interface, false, command, reply);
+        }
+
+        let mut session = self.state.runtime.secure_messaging.take()
+            .ok_or(Status::SecureMessagingNotSupported)?;
+        let plain_command = session.unwrap_command(command)
+            .and_then(|raw| iso7816::Command::<C>::try_from(raw.as_slice()).map_err(|_| Status::SecureMessagingDataObjectsIncorrect));
+        let plain_command = match plain_command {
+            Ok(plain_command) => plain_command,
+            Err(status) => {
+                // `session` is dropped, as if terminated by a plain command
+                self.state.runtime.app_security_status = Default::default();
+                return Err(status);
+            }
+        };
+
+        let status = match self.respond_plain(interface, true, &plain_command, reply) {
+            Ok(()) => [0x90, 0x00],
+            Err(status) => {
+                reply.clear();
+                u16::from(status).to_be_bytes()
+            }
+        };
+        let data = core::mem::take(reply);
+        session.wrap_response(&data, status, reply).map_err(|_| Status::NotEnoughMemory)?;
+
+        // unless it was replaced by key establishment
+        if self.state.runtime.secure_messaging.is_none() {
+            self.state.runtime.secure_messaging = Some(session);
+        }
+        Ok(())
+    }
+
+    fn respond_plain<const R: usize>(&mut self, interface: iso7816::Interface, secure_messaging: bool, command: &iso7816::Command<C>, reply: &mut Data<R>) -> Result
     {
         // need to implement Debug on iso7816::Command
         // info_now!("PIV responding to {:?}", command);
@@ -131,38 +249,161 @@ where
         let command: Command = (&entire_command).try_into()?;
         info_now!("parsed: {:?}", &command);
 
+        self.check_access(interface, secure_messaging, &command)?;
+
         match command {
             Command::Verify(verify) => self.verify(verify),
             Command::ChangeReference(change_reference) => self.change_reference(change_reference),
             Command::GetData(container) => self.get_data(container, reply),
-            _ => todo!(),
+            Command::Authenticate(_) => self.general_authenticate(&entire_command, reply),
+            Command::ImportAsymmetricKey(import) => self.import_asymmetric_key(import),
+            Command::YubicoExtension(instruction) => self.yubico_piv_extension(&entire_command, instruction, reply),
+            Command::ResetPinRetries(reset) => self.reset_retry_counter(reset),
+            Command::PutData(_) => self.put_data(&entire_command),
+            Command::GenerateAsymmetric(_) => self.generate_asymmetric_keypair(&entire_command, reply),
+            Command::Select(_) => self.select(&entire_command, reply),
+        }
+    }
+
+    fn check_access(&self, interface: iso7816::Interface, secure_messaging: bool, command: &Command) -> Result {
+        use commands::{Verify, VerifyKeyReference, VerifyLogin};
+
+        // the pairing code is only of use to establish the VCI
+        if let Command::Verify(Verify::Login(VerifyLogin::PairingCode(_)))
+            | Command::Verify(Verify::Status(VerifyKeyReference::PairingCode)) = command
+        {
+            return match secure_messaging {
+                true => Ok(()),
+                false => Err(Status::ConditionsOfUseNotSatisfied),
+            };
+        }
+
+        if !matches!(interface, iso7816::Interface::Contactless) {
+            return Ok(());
+        }
+
+        let virtual_contact_interface = secure_messaging && match self.vci_policy {
+            VciPolicy::Disabled => false,
+            VciPolicy::SecureMessaging => true,
+            VciPolicy::SecureMessagingAndPairingCode => self.state.runtime.app_security_status.pairing_code_verified,
+        };
+
+        if virtual_contact_interface || Self::contactless_without_vci(command) {
+            Ok(())
+        } else {
+            Err(Status::SecurityStatusNotSatisfied)
+        }
+    }
+
+    /// Table 2, Part 1, and Section 3.3.2, Part 1, SP 800-73-4
+    fn contactless_without_vci(command: &Command) -> bool {
+        use commands::{AuthenticateKeyReference, Verify, VerifyKeyReference, VerifyLogin};
+        use container::Container::*;
+
+        match command {
+            Command::Select(_) => true,
+            Command::GetData(container) => matches!(container,
+                CardCapabilityContainer
+                | CardHolderUniqueIdentifier
+                | X509CertificateFor9E
+                | SecurityObject
+                | DiscoveryObject
+                | BiometricInformationTemplatesGroupTemplate
+                | SecureMessagingCertificateSigner
+            ),
+            // OCC is what makes the contactless interface usable for PIN-once keys
+            Command::Verify(Verify::Login(VerifyLogin::Finger(_))) => true,
+            Command::Verify(Verify::Status(key_reference) | Verify::Logout(key_reference)) => matches!(key_reference,
+                VerifyKeyReference::PrimaryFingerOcc | VerifyKeyReference::SecondaryFingerOcc
+            ),
+            Command::Authenticate(authenticate) => matches!(authenticate.key_reference,
+                AuthenticateKeyReference::CardAuthentication
+                | AuthenticateKeyReference::SecureMessaging
+            ),
+            _ => false,
         }
     }
 
-    // maybe reserve this for the case VerifyLogin::PivPin?
+    /// Unblock the PIN, and set it to a new value, using the PUK.
+    pub fn reset_retry_counter(&mut self, command: commands::ResetPinRetries) -> Result {
+        let mut persistent_state = self.state.persistent(&mut self.trussed)?;
+        if persistent_state.remaining_puk_retries() == 0 {
+            return Err(Status::OperationBlocked);
+        }
+
+        if !persistent_state.verify_puk(&command.puk) {
+            let remaining = persistent_state.increment_consecutive_puk_mismatches();
+            self.state.runtime.app_security_status.puk_verified = false;
+            return Err(Status::RemainingRetries(remaining));
+        }
+
+        persistent_state.reset_consecutive_puk_mismatches();
+        persistent_state.set_pin(command.new_pin);
+        persistent_state.reset_consecutive_pin_mismatches();
+        // the new PIN still needs to be verified
+        self.state.runtime.app_security_status.pin_verified = false;
+        Ok(())
+    }
+
     pub fn login(&mut self, login: commands::VerifyLogin) -> Result {
-        if let commands::VerifyLogin::PivPin(pin) = login {
+        match login {
+            commands::VerifyLogin::PivPin(pin) => {
+                // the actual PIN verification
+                let mut persistent_state = self.state.persistent(&mut self.trussed)?;
+
+                if persistent_state.remaining_pin_retries() == 0 {
+                    return Err(Status::OperationBlocked);
+                }
 
-            // the actual PIN verification
-            let mut persistent_state = self.state.persistent(&mut self.trussed);
+                if persistent_state.verify_pin(&pin) {
+                    persistent_state.reset_consecutive_pin_mismatches();
+                    // the PIN also unblocks on-card biometric comparison
+                    persistent_state.reset_consecutive_occ_mismatches();
+                    self.state.runtime.app_security_status.pin_verified = true;
+                    Ok(())
 
-            if persistent_state.remaining_pin_retries() == 0 {
-                return Err(Status::OperationBlocked);
+                } else {
+                    let remaining = persistent_state.increment_consecutive_pin_mismatches();
+                    // should we logout here?
+                    self.state.runtime.app_security_status.pin_verified = false;
+                    Err(Status::RemainingRetries(remaining))
+                }
             }
 
-            if persistent_state.verify_pin(&pin) {
-                persistent_state.reset_consecutive_pin_mismatches();
-                self.state.runtime.app_security_status.pin_verified = true;
-                Ok(())
+            commands::VerifyLogin::PairingCode(pairing_code) => {
+                let verified = self.state.persistent(&mut self.trussed)?.verify_pairing_code(&pairing_code);
+                self.state.runtime.app_security_status.pairing_code_verified = verified;
+                match verified {
+                    true => Ok(()),
+                    false => Err(Status::SecurityStatusNotSatisfied),
+                }
+            }
 
-            } else {
-                let remaining = persistent_state.increment_consecutive_pin_mismatches();
-                // should we logout here?
-                self.state.runtime.app_security_status.pin_verified = false;
-                Err(Status::RemainingRetries(remaining))
+            commands::VerifyLogin::Finger(finger) => {
+                if !self.is_enrolled(finger) {
+                    return Err(Status::KeyReferenceNotFound);
+                }
+
+                let mut persistent_state = self.state.persistent(&mut self.trussed)?;
+                if persistent_state.remaining_occ_retries() == 0 {
+                    return Err(Status::OperationBlocked);
+                }
+
+                // is_enrolled made sure there is a hook
+                let matched = self.biometrics.as_mut().map(|biometrics| biometrics.verify(finger)).unwrap_or(false);
+                let mut persistent_state = self.state.persistent(&mut self.trussed)?;
+                if matched {
+                    persistent_state.reset_consecutive_occ_mismatches();
+                    self.state.runtime.app_security_status.occ_verified = true;
+                    Ok(())
+                } else {
+                    let remaining = persistent_state.increment_consecutive_occ_mismatches();
+                    self.state.runtime.app_security_status.occ_verified = false;
+                    Err(Status::RemainingRetries(remaining))
+                }
             }
-        } else {
-            Err(Status::FunctionNotSupported)
+
+            _ => Err(Status::FunctionNotSupported),
         }
     }
 
@@ -171,11 +412,37 @@ where
         match command {
             Verify::Login(login) => self.login(login),
 
+            Verify::Logout(commands::VerifyKeyReference::PrimaryFingerOcc)
+                | Verify::Logout(commands::VerifyKeyReference::SecondaryFingerOcc) => {
+                self.state.runtime.app_security_status.occ_verified = false;
+                Ok(())
+            }
+
             Verify::Logout(_) => {
                 self.state.runtime.app_security_status.pin_verified = false;
                 Ok(())
             }
 
+            Verify::Status(key_reference @ commands::VerifyKeyReference::PrimaryFingerOcc)
+                | Verify::Status(key_reference @ commands::VerifyKeyReference::SecondaryFingerOcc) => {
+                if !self.is_enrolled(Finger::try_from(key_reference).unwrap()) {
+                    return Err(Status::KeyReferenceNotFound);
+                }
+                if self.state.runtime.app_security_status.occ_verified {
+                    Ok(())
+                } else {
+                    let retries = self.state.persistent(&mut self.trussed)?.remaining_occ_retries();
+                    Err(Status::RemainingRetries(retries))
+                }
+            }
+
+            Verify::Status(commands::VerifyKeyReference::PairingCode) => {
+                match self.state.runtime.app_security_status.pairing_code_verified {
+                    true => Ok(()),
+                    false => Err(Status::SecurityStatusNotSatisfied),
+                }
+            }
+
             Verify::Status(key_reference) => {
                 if key_reference != commands::VerifyKeyReference::PivPin {
                     return Err(Status::FunctionNotSupported);
@@ -183,7 +450,7 @@ where
                 if self.state.runtime.app_security_status.pin_verified {
                     return Ok(())
                 } else {
-                    let retries = self.state.persistent(&mut self.trussed).remaining_pin_retries();
+                    let retries = self.state.persistent(&mut self.trussed)?.remaining_pin_retries();
                     return Err(Status::RemainingRetries(retries));
                 }
             }
@@ -199,7 +466,7 @@ where
     }
 
     pub fn change_pin(&mut self, old_pin: commands::Pin, new_pin: commands::Pin) -> Result {
-        let mut persistent_state = self.state.persistent(&mut self.trussed);
+        let mut persistent_state = self.state.persistent(&mut self.trussed)?;
         if persistent_state.remaining_pin_retries() == 0 {
             return Err(Status::OperationBlocked);
         }
@@ -217,7 +484,7 @@ where
     }
 
     pub fn change_puk(&mut self, old_puk: commands::Puk, new_puk: commands::Puk) -> Result {
-        let mut persistent_state = self.state.persistent(&mut self.trussed);
+        let mut persistent_state = self.state.persistent(&mut self.trussed)?;
         if persistent_state.remaining_puk_retries() == 0 {
             return Err(Status::OperationBlocked);
         }
@@ -234,6 +501,47 @@ where
         return Ok(Default::default());
     }
 
+    pub fn import_asymmetric_key(&mut self, import: commands::ImportAsymmetricKey<'_>) -> Result {
+        use commands::ImportedKey;
+        use state::{AsymmetricAlgorithm, KeyMetadata, KeyOrigin, SlotName, TouchPolicy};
+
+        if !self.state.runtime.app_security_status.management_verified {
+            return Err(Status::SecurityStatusNotSatisfied);
+        }
+
+        let slot = SlotName::try_from(import.key_reference as u8)
+            .map_err(|_| Status::KeyReferenceNotFound)?;
+
+        let (algorithm, raw_key) = match import.key {
+            // Trussed has no RSA implementation
+            ImportedKey::Rsa { .. } => return Err(Status::FunctionNotSupported),
+            ImportedKey::P256(scalar) => (AsymmetricAlgorithm::P256, scalar),
+            ImportedKey::Ed255(seed) => (AsymmetricAlgorithm::Ed255, seed),
+            ImportedKey::X255(secret) => (AsymmetricAlgorithm::X255, secret),
+        };
+
+        let key = try_syscall!(self.trussed.unsafe_inject_key(
+            algorithm.mechanism(),
+            raw_key,
+            trussed::types::Location::Internal,
+        )).map_err(|_error| {
+            // e.g., P256 scalar out of range
+            debug_now!("{:?}", &_error);
+            Status::IncorrectDataParameter
+        })?.key;
+
+        let metadata = KeyMetadata {
+            algorithm,
+            pin_policy: import.pin_policy.unwrap_or_else(|| slot.default_pin_policy()),
+            touch_policy: import.touch_policy.unwrap_or(TouchPolicy::Never),
+            origin: KeyOrigin::Imported,
+        };
+
+        self.state.persistent(&mut self.trussed)?
+            .set_asymmetric_key(slot, key, metadata)
+            .map_err(|_| Status::KeyReferenceNotFound)
+    }
+
     // pub fn old_respond(&mut self, command: &iso7816::Command<C>, reply: &mut Data<R>) -> Result {
 
     //     // TEMP
@@ -362,31 +670,17 @@ where
         //             95 AE 21 F9 5E 00 01 E6 23 27 F4 FD A5 05 F1 F5 B7 95 0F 11 75 BC 4D A2 06 B1 00 6B DA 90 C3 3A
         //
         // expected response: "7C L1 82 L2 SEQ(INT r, INT s)"
+        //
+        // For ECDH (A.5, Part 2), the challenge '81' is replaced by the exponentiation '85',
+        // containing the other party's public key, and the response is the shared secret.
 
-        let _alg = command.p1;
-        let _slot = command.p2;
-        let mut data = command.data().as_slice();
-
-        // refine as we gain more capability
-        if data.len() < 2 {
-            return Err(Status::IncorrectDataParameter);
+        if command.p2 == 0x04 {
+            return self.establish_secure_messaging(command, reply);
         }
 
-        let tag = data[0];
-        if tag != 0x7c {
-            return Err(Status::IncorrectDataParameter);
-        }
-
-        if data[1] > 0x81 {
-            panic!("unhandled >1B lengths");
-        }
-        if data[1] == 0x81 {
-            data[2] as usize;
-            data = &data[3..];
-        } else {
-            data[1] as usize; // ~158 for ssh ed25519 signatures (which have a ~150B commitment)
-            data = &data[2..];
-        };
+        let mut data = derp::Input::from(command.data().as_slice()).read_all(derp::Error::Read, |input| {
+            derp::expect_tag_and_get_value(input, 0x7c).map(|template| template.as_slice_less_safe())
+        }).map_err(|_| Status::IncorrectDataParameter)?;
 
         // step 1 of piv-go/ykAuthenticate
         // https://github.com/go-piv/piv-go/blob/d5ec95eb3bec9c20d60611fb77b7caeed7d886b6/piv/piv.go#L359-L384
@@ -399,58 +693,179 @@ where
 
         // step 2 of piv-go/ykAuthenticate
         // https://github.com/go-piv/piv-go/blob/d5ec95eb3bec9c20d60611fb77b7caeed7d886b6/piv/piv.go#L415-L420
-        if data.starts_with(&[0x80, 0x08]) {
+        //
+        // '80 08' for 3DES, '80 10' for AES management keys
+        if data.len() >= 2 && data[0] == 0x80 {
             data = &data[2..];
             return self.request_for_challenge(command, data, reply);
         }
 
-        // expect '82 00'
-        if !data.starts_with(&[0x82, 0x00]) {
-            return Err(Status::IncorrectDataParameter);
+        // '82 00' and either '81' or '85', in any order
+        let (tag, value) = derp::Input::from(data).read_all(derp::Error::Read, |input| {
+            let mut request = None;
+            let mut response_requested = false;
+            while !input.at_end() {
+                let (tag, value) = derp::read_tag_and_get_value(input)?;
+                match tag {
+                    0x82 if value.is_empty() => response_requested = true,
+                    0x81 | 0x85 if request.is_none() => request = Some((tag, value.as_slice_less_safe())),
+                    _ => return Err(derp::Error::WrongTag),
+                }
+            }
+            match (response_requested, request) {
+                (true, Some(request)) => Ok(request),
+                _ => Err(derp::Error::WrongValue),
+            }
+        }).map_err(|_| Status::IncorrectDataParameter)?;
+
+        info_now!("looking for keyreference");
+        let slot = state::SlotName::try_from(command.p2).map_err(|_| Status::KeyReferenceNotFound)?;
+        let (key, metadata) = self.state.persistent(&mut self.trussed)?.state.keys
+            .asymmetric_key(slot)
+            .ok_or(Status::KeyReferenceNotFound)?;
+
+        let algorithm = state::AsymmetricAlgorithm::try_from(command.p1)
+            .map_err(|_| Status::IncorrectP1OrP2Parameter)?;
+        if algorithm != metadata.algorithm {
+            return Err(Status::IncorrectP1OrP2Parameter);
         }
-        data = &data[2..];
 
-        // // expect '81 20'
-        // if !data.starts_with(&[0x81, 0x20]) {
-        //     return Err(Status::IncorrectDataParameter);
-        // }
-        // data = &data[2..];
+        self.check_pin_policy(metadata.pin_policy)?;
+        self.check_touch_policy(metadata.touch_policy)?;
 
-        // expect '81 81 96'
-        // if !data.starts_with(&[0x81, 0x81, 0x96]) {
-        if !data.starts_with(&[0x81, 0x81]) {
-            return Err(Status::IncorrectDataParameter);
+        let encoded = match tag {
+            0x81 => {
+                let signature = self.sign(key, algorithm, value)?;
+                piv_types::DynamicAuthenticationTemplate::with_response(&signature)
+                    .encode_to_heapless_vec(reply)
+            }
+            _ => {
+                let shared_secret = self.agree(key, algorithm, value)?;
+                piv_types::DynamicAuthenticationTemplate::with_response(&shared_secret)
+                    .encode_to_heapless_vec(reply)
+            }
+        };
+        encoded.map_err(|_| Status::NotEnoughMemory)
+    }
+
+    fn check_pin_policy(&mut self, pin_policy: state::PinPolicy) -> Result {
+        use state::PinPolicy;
+        let status = &self.state.runtime.app_security_status;
+        match pin_policy {
+            PinPolicy::Never => Ok(()),
+            // a biometric match stands in for the PIN, except where every use needs the PIN
+            PinPolicy::Once if status.pin_verified || status.occ_verified => Ok(()),
+            _ if !status.pin_verified => Err(Status::SecurityStatusNotSatisfied),
+            PinPolicy::Once => Ok(()),
+            PinPolicy::Always => {
+                // the next use needs another VERIFY
+                self.state.runtime.app_security_status.pin_verified = false;
+                Ok(())
+            }
         }
-        let len = data[2] as usize;
-        data = &data[3..];
+    }
 
-        // if data.len() != 32 {
-        //     return Err(Status::IncorrectDataParameter);
-        // }
-        if data.len() != len {
-            return Err(Status::IncorrectDataParameter);
+    fn check_touch_policy(&mut self, touch_policy: state::TouchPolicy) -> Result {
+        use state::TouchPolicy;
+        let now = match touch_policy {
+            TouchPolicy::Never => return Ok(()),
+            _ => syscall!(self.trussed.uptime()).uptime,
+        };
+        let touched_at = self.state.runtime.app_security_status.touched_at;
+        if touch_policy == TouchPolicy::Cached
+            && touched_at.map_or(false, |touched_at| now.saturating_sub(touched_at) < TOUCH_CACHE_DURATION)
+        {
+            return Ok(());
         }
+        if syscall!(self.trussed.confirm_user_present(15_000)).result.is_err() {
+            return Err(Status::SecurityStatusNotSatisfied);
+        }
+        self.state.runtime.app_security_status.touched_at = Some(syscall!(self.trussed.uptime()).uptime);
+        Ok(())
+    }
 
-        info_now!("looking for keyreference");
-        let key_handle = match self.state.persistent(&mut self.trussed).state.keys.authentication_key {
-            Some(key) => key,
-            None => return Err(Status::KeyReferenceNotFound),
+    fn sign(&mut self, key: trussed::types::KeyId, algorithm: state::AsymmetricAlgorithm, challenge: &[u8]) -> iso7816::Result<trussed::types::Signature>
+    {
+        use state::AsymmetricAlgorithm;
+        use trussed::types::{Mechanism, SignatureSerialization};
+
+        let (mechanism, serialization) = match algorithm {
+            // the client hashes and truncates, as for RSA
+            AsymmetricAlgorithm::P256 if challenge.len() == 32 => (Mechanism::P256Prehashed, SignatureSerialization::Asn1Der),
+            // the challenge is the message itself, e.g. ~150B for SSH
+            AsymmetricAlgorithm::Ed255 => (Mechanism::Ed255, SignatureSerialization::Raw),
+            _ => return Err(Status::IncorrectDataParameter),
         };
 
-        let commitment = data; // 32B of data // 150B for ed25519
-        let signature = try_syscall!(self.trussed.sign_ed255(key_handle, commitment))
+        Ok(try_syscall!(self.trussed.sign(mechanism, key, challenge, serialization))
             .map_err(|_error| {
                 // NoSuchKey
                 debug_now!("{:?}", &_error);
                 Status::UnspecifiedNonpersistentExecutionError }
             )?
-            .signature;
+            .signature)
+    }
 
-        piv_types::DynamicAuthenticationTemplate::with_response(&signature)
-            .encode_to_heapless_vec(reply)
-            // todo: come up with error handling (in this case, shouldn't fail)
-            .unwrap();
+    fn agree(&mut self, key: trussed::types::KeyId, algorithm: state::AsymmetricAlgorithm, public_key: &[u8]) -> iso7816::Result<trussed::types::SerializedKey>
+    {
+        use state::AsymmetricAlgorithm;
+        use trussed::types::{KeySerialization, Location, Mechanism, StorageAttributes};
+
+        let public_key = match (algorithm, public_key) {
+            // Trussed expects the concatenated coordinates
+            (AsymmetricAlgorithm::P256, [0x04, coordinates @ ..]) if coordinates.len() == 64 => coordinates,
+            (AsymmetricAlgorithm::X255, public_key) if public_key.len() == 32 => public_key,
+            _ => return Err(Status::IncorrectDataParameter),
+        };
+
+        let mechanism = algorithm.mechanism();
+        let public_key = try_syscall!(self.trussed.deserialize_key(
+            mechanism,
+            public_key,
+            KeySerialization::Raw,
+            StorageAttributes::new().set_persistence(Location::Volatile),
+        )).map_err(|_| Status::IncorrectDataParameter)?.key;
 
+        let shared_secret = try_syscall!(self.trussed.agree(
+            mechanism,
+            key,
+            public_key,
+            StorageAttributes::new().set_persistence(Location::Volatile),
+        ));
+        syscall!(self.trussed.delete(public_key));
+        let shared_secret = shared_secret.map_err(|_| Status::UnspecifiedNonpersistentExecutionError)?.shared_secret;
+
+        let serialized = try_syscall!(self.trussed.serialize_key(
+            Mechanism::SharedSecret,
+            shared_secret,
+            KeySerialization::Raw,
+        ));
+        syscall!(self.trussed.delete(shared_secret));
+        Ok(serialized.map_err(|_| Status::UnspecifiedNonpersistentExecutionError)?.serialized_key)
+    }
+
+    /// Key establishment for secure messaging, Section 4.1, Part 2, SP 800-73-4
+    fn establish_secure_messaging<const R: usize>(&mut self, command: &iso7816::Command<C>, reply: &mut Data<R>) -> Result
+    {
+        if command.p1 != secure_messaging::CIPHER_SUITE_2 {
+            return Err(Status::IncorrectP1OrP2Parameter);
+        }
+
+        let key = self.state.persistent(&mut self.trussed)?.secure_messaging_key()
+            .ok_or(Status::KeyReferenceNotFound)?;
+        let card_verifiable_certificate = try_syscall!(self.trussed.read_file(
+            trussed::types::Location::Internal,
+            trussed::types::PathBuf::from(SECURE_MESSAGING_CVC_FILENAME),
+        )).map_err(|_| Status::ConditionsOfUseNotSatisfied)?.data;
+        let nonce: secure_messaging::Block = syscall!(self.trussed.random_bytes(16))
+            .bytes.as_ref().try_into().unwrap();
+
+        // a new session starts from scratch
+        self.terminate_secure_messaging();
+        let request = secure_messaging::request(command.data())?;
+        let shared_secret = self.agree(key, state::AsymmetricAlgorithm::P256, request.ephemeral_public_key)?;
+        let session = secure_messaging::establish(&request, &shared_secret, &card_verifiable_certificate, &nonce, reply)?;
+        self.state.runtime.secure_messaging = Some(session);
         Ok(())
     }
 
@@ -460,44 +875,48 @@ where
         //     00 87 03 9B 16 7C 14 80 08 99 6D 71 40 E7 05 DF 7F 81 08 6E EF 9C 02 00 69 73 E8
         // - remaining data contains <decrypted challenge> 81 08 <encrypted counter challenge>
         // - we must a) verify the decrypted challenge, b) decrypt the counter challenge
+        //
+        // for AES management keys, the challenges are 16 bytes instead of 8
 
-        if command.p1 != 0x03 || command.p2 != 0x9b {
+        let mut persistent_state = self.state.persistent(&mut self.trussed)?;
+        let algorithm = persistent_state.management_key_algorithm();
+
+        if command.p1 != u8::from(algorithm) || command.p2 != 0x9b {
             return Err(Status::IncorrectP1OrP2Parameter);
         }
 
-        if remaining_data.len() != 8 + 2 + 8 {
+        let block_size = algorithm.block_size();
+        if remaining_data.len() != block_size + 2 + block_size {
             return Err(Status::IncorrectDataParameter);
         }
 
         // A) verify decrypted challenge
-        let (response, data) = remaining_data.split_at(8);
+        let (response, data) = remaining_data.split_at(block_size);
 
         use state::{AuthenticateManagement, CommandCache};
-        let our_challenge = match self.state.runtime.command_cache {
+        let our_challenge = match self.state.runtime.command_cache.take() {
             Some(CommandCache::AuthenticateManagement(AuthenticateManagement { challenge } ))
                 => challenge,
+            // no retries ;)
             _ => { return Err(Status::InstructionNotSupportedOrInvalid); }
         };
-        // no retries ;)
-        self.state.runtime.command_cache = None;
 
-        if &our_challenge != response {
+        if our_challenge.as_slice() != response {
             debug_now!("{:?}", &our_challenge);
             debug_now!("{:?}", &response);
             return Err(Status::IncorrectDataParameter);
         }
 
-        self.state.runtime.app_security_status.management_verified = true;
-
         // B) encrypt their challenge
         let (header, challenge) = data.split_at(2);
-        if header != &[0x81, 0x08] {
+        if header != &[0x81, block_size as u8] {
             return Err(Status::IncorrectDataParameter);
         }
 
-        let key = self.state.persistent(&mut self.trussed).state.keys.management_key;
+        let encrypted_challenge = persistent_state.encrypt_with_management_key(challenge)
+            .map_err(|_| Status::UnspecifiedNonpersistentExecutionError)?;
 
-        let encrypted_challenge = syscall!(self.trussed.encrypt_tdes(key, challenge)).ciphertext;
+        self.state.runtime.app_security_status.management_verified = true;
 
         piv_types::DynamicAuthenticationTemplate::with_response(&encrypted_challenge)
             .encode_to_heapless_vec(reply)
@@ -510,7 +929,9 @@ where
     {
         // invariants: parsed data was '7C L1 80 00' + remaining_data
 
-        if command.p1 != 0x03 || command.p2 != 0x9b {
+        let algorithm = self.state.persistent(&mut self.trussed)?.management_key_algorithm();
+
+        if command.p1 != u8::from(algorithm) || command.p2 != 0x9b {
             return Err(Status::IncorrectP1OrP2Parameter);
         }
 
@@ -518,14 +939,14 @@ where
             return Err(Status::IncorrectDataParameter);
         }
 
-        let key = self.state.persistent(&mut self.trussed).state.keys.management_key;
+        let challenge = syscall!(self.trussed.random_bytes(algorithm.block_size())).bytes;
+        let encrypted_challenge = self.state.persistent(&mut self.trussed)?
+            .encrypt_with_management_key(&challenge)
+            .map_err(|_| Status::UnspecifiedNonpersistentExecutionError)?;
 
-        let challenge = syscall!(self.trussed.random_bytes(8)).bytes;
-        let command_cache = state::AuthenticateManagement { challenge: challenge[..].try_into().unwrap() };
+        let command_cache = state::AuthenticateManagement { challenge: heapless::Vec::from_slice(&challenge).unwrap() };
         self.state.runtime.command_cache = Some(state::CommandCache::AuthenticateManagement(command_cache));
 
-        let encrypted_challenge = syscall!(self.trussed.encrypt_tdes(key, &challenge)).ciphertext;
-
         piv_types::DynamicAuthenticationTemplate::with_witness(&encrypted_challenge)
             .encode_to_heapless_vec(reply)
             .unwrap();
@@ -684,6 +1105,9 @@ where
 
     fn generate_asymmetric_keypair<const R: usize>(&mut self, command: &iso7816::Command<C>, reply: &mut Data<R>) -> Result
     {
+        use state::{AsymmetricAlgorithm, KeyMetadata, KeyOrigin, PinPolicy, SlotName, TouchPolicy};
+        use trussed::types::{Location, StorageAttributes};
+
         if !self.state.runtime.app_security_status.management_verified {
             return Err(Status::SecurityStatusNotSatisfied);
         }
@@ -692,11 +1116,12 @@ where
             return Err(Status::IncorrectP1OrP2Parameter);
         }
 
-        if command.p2 != 0x9a {
-            // TODO: make more general
-            return Err(Status::FunctionNotSupported);
+        if command.p2 == 0x04 {
+            return self.generate_secure_messaging_key(command, reply);
         }
 
+        let slot = SlotName::try_from(command.p2).map_err(|_| Status::KeyReferenceNotFound)?;
+
         // example: 00 47 00 9A 0B
         //   AC 09
         //      # P256
@@ -706,104 +1131,113 @@ where
         //      # 0xAB = Yubico extension (of course...), TouchPolicy, 0x2 =
         //      AB 01 02
         //
-        // var touchPolicyMap = map[TouchPolicy]byte{
-        //     TouchPolicyNever:  0x01,
-        //     TouchPolicyAlways: 0x02,
-        //     TouchPolicyCached: 0x03,
-        // }
-
-        // var pinPolicyMap = map[PINPolicy]byte{
-        //     PINPolicyNever:  0x01,
-        //     PINPolicyOnce:   0x02,
-        //     PINPolicyAlways: 0x03,
-        // }
-
-        // TODO: iterate on this, don't expect tags..
-        let input = derp::Input::from(&command.data());
-        // let (mechanism, parameter) = input.read_all(derp::Error::Read, |input| {
-        let (mechanism, _pin_policy, _touch_policy) = input.read_all(derp::Error::Read, |input| {
+        // the policies are optional, 0x00 means the default
+        let input = derp::Input::from(command.data().as_slice());
+        let (algorithm, pin_policy, touch_policy) = input.read_all(derp::Error::Read, |input| {
             derp::nested(input, 0xac, |input| {
-                let mechanism = derp::expect_tag_and_get_value(input, 0x80)?;
-                // let parameter = derp::expect_tag_and_get_value(input, 0x81)?;
-                let pin_policy = derp::expect_tag_and_get_value(input, 0xaa)?;
-                let touch_policy = derp::expect_tag_and_get_value(input, 0xab)?;
-                // Ok((mechanism.as_slice_less_safe(), parameter.as_slice_less_safe()))
-                Ok((
-                    mechanism.as_slice_less_safe(),
-                    pin_policy.as_slice_less_safe(),
-                    touch_policy.as_slice_less_safe(),
-                ))
+                let algorithm = match derp::expect_tag_and_get_value(input, 0x80)?.as_slice_less_safe() {
+                    &[algorithm] => AsymmetricAlgorithm::try_from(algorithm).map_err(|_| derp::Error::WrongValue)?,
+                    _ => return Err(derp::Error::WrongValue),
+                };
+                let (mut pin_policy, mut touch_policy) = (None, None);
+                while !input.at_end() {
+                    let (tag, value) = derp::read_tag_and_get_value(input)?;
+                    match (tag, value.as_slice_less_safe()) {
+                        (0xaa, [0x00]) | (0xab, [0x00]) => {}
+                        (0xaa, &[policy]) => pin_policy = Some(PinPolicy::try_from(policy).map_err(|_| derp::Error::WrongValue)?),
+                        (0xab, &[policy]) => touch_policy = Some(TouchPolicy::try_from(policy).map_err(|_| derp::Error::WrongValue)?),
+                        _ => return Err(derp::Error::WrongTag),
+                    }
+                }
+                Ok((algorithm, pin_policy, touch_policy))
             })
         }).map_err(|_e| {
                 info_now!("error parsing GenerateAsymmetricKeypair: {:?}", &_e);
                 Status::IncorrectDataParameter
         })?;
 
-        // if mechanism != &[0x11] {
-        // HA! patch in Ed255
-        if mechanism != &[0x22] {
-            return Err(Status::InstructionNotSupportedOrInvalid);
-        }
-
-        // ble policy
+        let mechanism = algorithm.mechanism();
+        let key = syscall!(self.trussed.generate_key(
+            mechanism,
+            StorageAttributes::new().set_persistence(Location::Internal),
+        )).key;
 
-        if let Some(key) = self.state.persistent(&mut self.trussed).state.keys.authentication_key {
-            syscall!(self.trussed.delete(key));
-        }
+        let metadata = KeyMetadata {
+            algorithm,
+            pin_policy: pin_policy.unwrap_or_else(|| slot.default_pin_policy()),
+            touch_policy: touch_policy.unwrap_or(TouchPolicy::Never),
+            origin: KeyOrigin::Generated,
+        };
+        self.state.persistent(&mut self.trussed)?
+            .set_asymmetric_key(slot, key, metadata)
+            .map_err(|_| Status::KeyReferenceNotFound)?;
 
-        // let key = syscall!(self.trussed.generate_p256_private_key(
-        // let key = syscall!(self.trussed.generate_p256_private_key(
-        let key = syscall!(self.trussed.generate_ed255_private_key(
-            trussed::types::Location::Internal,
-        )).key;
+        let serialized_public_key = self.derive_public_key(key, algorithm);
+        reply.extend_from_slice(&[0x7f, 0x49]).unwrap();
+        Self::encode_public_key(algorithm, &serialized_public_key, reply)
+    }
 
+    fn derive_public_key(&mut self, key: trussed::types::KeyId, algorithm: state::AsymmetricAlgorithm) -> trussed::types::SerializedKey
+    {
+        use trussed::types::{KeySerialization, Location, StorageAttributes};
 
-        // // TEMP
-        // let mechanism = trussed::types::Mechanism::P256Prehashed;
-        // let mechanism = trussed::types::Mechanism::P256;
-        // let commitment = &[37u8; 32];
-        // // blocking::dbg!(commitment);
-        // let serialization = trussed::types::SignatureSerialization::Asn1Der;
-        // // blocking::dbg!(&key);
-        // let signature = block!(self.trussed.sign(mechanism, key.clone(), commitment, serialization).map_err(|e| {
-        //     blocking::dbg!(e);
-        //     e
-        // }).unwrap())
-        //     .map_err(|error| {
-        //         // NoSuchKey
-        //         blocking::dbg!(error);
-        //         Status::UnspecifiedNonpersistentExecutionError }
-        //     )?
-        //     .signature;
-        // blocking::dbg!(&signature);
-
-        self.state.persistent(&mut self.trussed).state.keys.authentication_key = Some(key);
-        self.state.persistent(&mut self.trussed).save();
-
-        // let public_key = syscall!(self.trussed.derive_p256_public_key(
-        let public_key = syscall!(self.trussed.derive_ed255_public_key(
+        let mechanism = algorithm.mechanism();
+        let public_key = syscall!(self.trussed.derive_key(
+            mechanism,
             key,
-            trussed::types::Location::Volatile,
+            None,
+            StorageAttributes::new().set_persistence(Location::Volatile),
         )).key;
-
         let serialized_public_key = syscall!(self.trussed.serialize_key(
-            // trussed::types::Mechanism::P256,
-            trussed::types::Mechanism::Ed255,
-            public_key.clone(),
-            trussed::types::KeySerialization::Raw,
+            mechanism,
+            public_key,
+            KeySerialization::Raw,
         )).serialized_key;
+        syscall!(self.trussed.delete(public_key));
+        serialized_public_key
+    }
+
+    /// The contents of the '7F49' template: for P256, the uncompressed point, tagged '86',
+    /// and for Ed25519 and X25519 (like YubiKey 5.7), the 32 byte key, also tagged '86'.
+    fn encode_public_key<const R: usize>(algorithm: state::AsymmetricAlgorithm, raw_public_key: &[u8], reply: &mut Data<R>) -> Result
+    {
+        // Trussed serializes P256 public keys as the concatenated coordinates
+        let point_prefix: &[u8] = match algorithm {
+            state::AsymmetricAlgorithm::P256 => &[0x04],
+            _ => &[],
+        };
+        let length = (point_prefix.len() + raw_public_key.len()) as u8;
 
-        // info_now!("supposed SEC1 pubkey, len {}: {:X?}", serialized_public_key.len(), &serialized_public_key);
+        reply.extend_from_slice(&[length + 2, 0x86, length]).map_err(|_| Status::NotEnoughMemory)?;
+        reply.extend_from_slice(point_prefix).map_err(|_| Status::NotEnoughMemory)?;
+        reply.extend_from_slice(raw_public_key).map_err(|_| Status::NotEnoughMemory)
+    }
 
-        // P256 SEC1 has 65 bytes, Ed255 pubkeys have 32
-        // let l2 = 65;
-        let l2 = 32;
-        let l1 = l2 + 2;
+    fn generate_secure_messaging_key<const R: usize>(&mut self, command: &iso7816::Command<C>, reply: &mut Data<R>) -> Result
+    {
+        // 00 47 00 04 05  AC 03 80 01 27
+        derp::Input::from(command.data().as_slice()).read_all(derp::Error::Read, |input| {
+            derp::nested(input, 0xac, |input| {
+                derp::expect_tag_and_value(input, 0x80, &[secure_messaging::CIPHER_SUITE_2])
+            })
+        }).map_err(|_| Status::IncorrectDataParameter)?;
 
-        reply.extend_from_slice(&[0x7f, 0x49, l1, 0x86, l2]).unwrap();
-        reply.extend_from_slice(&serialized_public_key).unwrap();
+        use trussed::types::{Location, Mechanism, StorageAttributes};
+        let key = syscall!(self.trussed.generate_key(
+            Mechanism::P256,
+            StorageAttributes::new().set_persistence(Location::Internal),
+        )).key;
+        let public_key = self.derive_public_key(key, state::AsymmetricAlgorithm::P256);
+        self.state.persistent(&mut self.trussed)?.set_secure_messaging_key(key);
 
-        Ok(())
+        // the card verifiable certificate has to be reissued for the new key
+        try_syscall!(self.trussed.remove_file(
+            trussed::types::Location::Internal,
+            trussed::types::PathBuf::from(SECURE_MESSAGING_CVC_FILENAME),
+        )).ok();
+
+        reply.extend_from_slice(&[0x7f, 0x49]).unwrap();
+        Self::encode_public_key(state::AsymmetricAlgorithm::P256, &public_key, reply)
     }
 
     fn put_data(&mut self, command: &iso7816::Command<C>) -> Result {
@@ -863,6 +1297,31 @@ where
             return Ok(());
         }
 
+        if data_object == SECURE_MESSAGING_CVC_TAG || data_object == &[0x5f, 0xc1, 0x22] {
+            // the card verifiable certificate C_ICC of the secure messaging key,
+            // respectively the "Secure Messaging Certificate Signer" data object
+            if !self.state.runtime.app_security_status.management_verified {
+                return Err(Status::SecurityStatusNotSatisfied);
+            }
+
+            if data.len() > 1024 {
+                return Err(Status::NotEnoughMemory);
+            }
+
+            let filename = match data_object == SECURE_MESSAGING_CVC_TAG {
+                true => SECURE_MESSAGING_CVC_FILENAME,
+                false => SECURE_MESSAGING_SIGNER_FILENAME,
+            };
+            try_syscall!(self.trussed.write_file(
+                trussed::types::Location::Internal,
+                trussed::types::PathBuf::from(filename),
+                trussed::types::Message::from_slice(data).unwrap(),
+                None,
+            )).map_err(|_| Status::NotEnoughMemory)?;
+
+            return Ok(());
+        }
+
         if data_object == &[0x5f, 0xc1, 0x05] {
             // "X.509 Certificate for PIV Authentication", supposedly
             // IOW, the cert for "authentication key"
@@ -910,14 +1369,88 @@ where
         use crate::container::Container;
         match container {
             Container::DiscoveryObject => {
-                // Err(Status::InstructionNotSupportedOrInvalid)
-                reply.extend_from_slice(DISCOVERY_OBJECT).ok();
-                // todo!("discovery object"),
+                // the first byte of the PIN usage policy, which comes last, reflects the VCI
+                let mut discovery_object = *DISCOVERY_OBJECT;
+                discovery_object[DISCOVERY_OBJECT.len() - 2] |= match self.vci_policy {
+                    VciPolicy::Disabled => 0,
+                    VciPolicy::SecureMessaging => PIN_USAGE_POLICY_VCI | PIN_USAGE_POLICY_VCI_WITHOUT_PAIRING_CODE,
+                    VciPolicy::SecureMessagingAndPairingCode => PIN_USAGE_POLICY_VCI,
+                };
+                if !self.enrolled_fingers().is_empty() {
+                    discovery_object[DISCOVERY_OBJECT.len() - 2] |= PIN_USAGE_POLICY_OCC;
+                }
+                reply.extend_from_slice(&discovery_object).ok();
+            }
+
+            // '5FC1 22'
+            Container::SecureMessagingCertificateSigner => {
+                let data = try_syscall!(self.trussed.read_file(
+                    trussed::types::Location::Internal,
+                    trussed::types::PathBuf::from(SECURE_MESSAGING_SIGNER_FILENAME),
+                )).map_err(|_| Status::NotFound)?.data;
+
+                let tag = flexiber::Tag::application(0x13); // 0x53
+                flexiber::TaggedSlice::from(tag, &data)
+                    .unwrap()
+                    .encode_to_heapless_vec(reply)
+                    .map_err(|_| Status::NotEnoughMemory)?;
             }
 
+            // '5FC1 23'
+            Container::PairingCodeReferenceDataContainer => {
+                if !self.state.runtime.app_security_status.pin_verified {
+                    return Err(Status::SecurityStatusNotSatisfied);
+                }
+                let pairing_code = self.state.persistent(&mut self.trussed)?.pairing_code();
+
+                let mut data = [0u8; 10];
+                data[..2].copy_from_slice(&[0x99, 0x08]);
+                data[2..].copy_from_slice(&pairing_code);
+                let tag = flexiber::Tag::application(0x13); // 0x53
+                flexiber::TaggedSlice::from(tag, &data)
+                    .unwrap()
+                    .encode_to_heapless_vec(reply)
+                    .unwrap();
+            }
+
+            // '7F61', Table 7, Part 2, SP 800-73-4
             Container::BiometricInformationTemplatesGroupTemplate => {
-                return Err(Status::InstructionNotSupportedOrInvalid)
-                // todo!("biometric information template"),
+                let fingers = self.enrolled_fingers();
+                if fingers.is_empty() {
+                    return Err(Status::NotFound);
+                }
+
+                // fits in a one byte length
+                let mut data = heapless::Vec::<u8, 64>::new();
+                data.extend_from_slice(&[0x7f, 0x61, 0x00]).unwrap();
+                // number of fingers
+                data.extend_from_slice(&[0x02, 0x01, fingers.len() as u8]).unwrap();
+                for finger in fingers {
+                    // one biometric information template per finger: key reference and
+                    // biometric header template
+                    data.extend_from_slice(&[0x7f, 0x60, 3 + BIOMETRIC_HEADER_TEMPLATE.len() as u8]).unwrap();
+                    data.extend_from_slice(&[0x83, 0x01, finger.key_reference()]).unwrap();
+                    data.extend_from_slice(BIOMETRIC_HEADER_TEMPLATE).unwrap();
+                }
+                data[2] = (data.len() - 3) as u8;
+                reply.extend_from_slice(&data).map_err(|_| Status::NotEnoughMemory)?;
+            }
+
+            // '5FC1 03'
+            Container::CardholderFingerprints => {
+                if !self.state.runtime.app_security_status.pin_verified {
+                    return Err(Status::SecurityStatusNotSatisfied);
+                }
+                if self.enrolled_fingers().is_empty() {
+                    return Err(Status::NotFound);
+                }
+                // the templates never leave the sensor, so the fingerprint I&A
+                // CBEFF record is empty, followed by an empty error detection code
+                let tag = flexiber::Tag::application(0x13); // 0x53
+                flexiber::TaggedSlice::from(tag, &[0xbc, 0x00, 0xfe, 0x00])
+                    .unwrap()
+                    .encode_to_heapless_vec(reply)
+                    .unwrap();
             }
 
             // '5FC1 07' (351B)
@@ -930,7 +1463,7 @@ where
 
             // '5FC1 02' (351B)
             Container::CardHolderUniqueIdentifier => {
-                let guid = self.state.persistent(&mut self.trussed).guid();
+                let guid = self.state.persistent(&mut self.trussed)?.guid();
                 piv_types::CardHolderUniqueIdentifier::default()
                     .with_guid(guid)
                     .encode_to_heapless_vec(reply)
@@ -973,20 +1506,80 @@ where
         Ok(())
     }
 
+    // Yubico only attests keys generated on the device, so do we.
+    fn attest<const R: usize>(&mut self, slot: state::SlotName, reply: &mut Data<R>) -> Result
+    {
+        use trussed::types::{KeySerialization, Location, Mechanism, SignatureSerialization, StorageAttributes};
+
+        let (key, metadata) = self.state.persistent(&mut self.trussed)?.state.keys
+            .asymmetric_key(slot)
+            .ok_or(Status::KeyReferenceNotFound)?;
+        if metadata.origin != state::KeyOrigin::Generated {
+            return Err(Status::ConditionsOfUseNotSatisfied);
+        }
+
+        if !syscall!(self.trussed.exists(Mechanism::P256, ATTESTATION_KEY_ID)).exists {
+            info_now!("no attestation key provisioned");
+            return Err(Status::NotFound);
+        }
+        let attestation_certificate = try_syscall!(self.trussed.read_certificate(ATTESTATION_CERT_ID))
+            .map_err(|_| Status::NotFound)?
+            .der;
+        let issuer: heapless::Vec<u8, 256> = attestation::subject(&attestation_certificate)
+            .ok_or(Status::UnspecifiedPersistentExecutionError)?;
+
+        let mechanism = metadata.algorithm.mechanism();
+        let public_key = syscall!(self.trussed.derive_key(
+            mechanism,
+            key,
+            None,
+            StorageAttributes::new().set_persistence(Location::Volatile),
+        )).key;
+        let serialized_public_key = syscall!(self.trussed.serialize_key(
+            mechanism,
+            public_key,
+            KeySerialization::Raw,
+        )).serialized_key;
+        syscall!(self.trussed.delete(public_key));
+
+        let mut serial_number = syscall!(self.trussed.random_bytes(16)).bytes;
+        // positive, and no superfluous leading zero byte
+        serial_number[0] = (serial_number[0] & 0x7f) | 0x40;
+
+        let tbs_certificate = attestation::Attestation {
+            slot: slot.reference(),
+            metadata,
+            public_key: &serialized_public_key,
+            serial_number: &serial_number,
+            issuer: &issuer,
+            firmware_version: YUBICO_FIRMWARE_VERSION,
+            device_serial: self.serial(),
+        }.tbs_certificate().map_err(|_| Status::UnspecifiedNonpersistentExecutionError)?;
+
+        let signature = try_syscall!(self.trussed.sign(
+            Mechanism::P256,
+            ATTESTATION_KEY_ID,
+            &tbs_certificate,
+            SignatureSerialization::Asn1Der,
+        )).map_err(|_error| {
+            debug_now!("{:?}", &_error);
+            Status::UnspecifiedNonpersistentExecutionError
+        })?.signature;
+
+        attestation::certificate(&tbs_certificate, &signature, reply)
+            .map_err(|_| Status::NotEnoughMemory)
+    }
+
     fn yubico_piv_extension<const R: usize>(&mut self, command: &iso7816::Command<C>, instruction: YubicoPivExtension, reply: &mut Data<R>) -> Result
     {
         info_now!("yubico extension: {:?}", &instruction);
         match instruction {
             YubicoPivExtension::GetSerial => {
-                // make up a 4-byte serial
-                reply.extend_from_slice(
-                    &[0x00, 0x52, 0xf7, 0x43]).ok();
+                reply.extend_from_slice(&self.serial().to_be_bytes()).ok();
             }
 
             YubicoPivExtension::GetVersion => {
-                // make up a version, be >= 5.0.0
-                reply.extend_from_slice(
-                    &[0x06, 0x06, 0x06]).ok();
+                reply.extend_from_slice(&YUBICO_FIRMWARE_VERSION).ok();
             }
 
             YubicoPivExtension::Attest => {
@@ -994,14 +1587,9 @@ where
                     return Err(Status::IncorrectP1OrP2Parameter);
                 }
 
-                let slot = command.p1;
-
-                if slot == 0x9a {
-                    reply.extend_from_slice(YUBICO_ATTESTATION_CERTIFICATE_FOR_9A).ok();
-                } else {
-
-                    return Err(Status::FunctionNotSupported)
-                }
+                let slot = state::SlotName::try_from(command.p1)
+                    .map_err(|_| Status::IncorrectP1OrP2Parameter)?;
+                self.attest(slot, reply)?;
             }
 
             YubicoPivExtension::Reset => {
@@ -1009,10 +1597,22 @@ where
                     return Err(Status::IncorrectP1OrP2Parameter);
                 }
 
-                // TODO: find out what all needs resetting :)
-                self.state.persistent(&mut self.trussed).reset_pin();
-                self.state.persistent(&mut self.trussed).reset_puk();
-                self.state.persistent(&mut self.trussed).reset_management_key();
+                // the only way out of a state this firmware can't read, whose retries are unknown
+                let readable = self.state.persistent(&mut self.trussed).is_ok();
+                if !readable {
+                    state::Persistent::reinitialize(&mut self.trussed);
+                }
+                let mut persistent_state = self.state.persistent(&mut self.trussed)?;
+                // otherwise only once both PIN and PUK are blocked
+                if readable && (persistent_state.remaining_pin_retries() != 0
+                    || persistent_state.remaining_puk_retries() != 0)
+                {
+                    return Err(Status::ConditionsOfUseNotSatisfied);
+                }
+                persistent_state.reset_keys();
+                persistent_state.reset_pin();
+                persistent_state.reset_puk();
+                persistent_state.reset_management_key();
                 self.state.runtime.app_security_status.pin_verified = false;
                 self.state.runtime.app_security_status.puk_verified = false;
                 self.state.runtime.app_security_status.management_verified = false;
@@ -1038,25 +1638,72 @@ where
                 //         alg3DES, keyCardManagement, 24,
                 //     }, key[:]...),
                 // }
-                // TODO check we are authenticated with old management key
                 if command.p1 != 0xff || (command.p2 != 0xff && command.p2 != 0xfe) {
                     return Err(Status::IncorrectP1OrP2Parameter);
                 }
 
+                if !self.state.runtime.app_security_status.management_verified {
+                    return Err(Status::SecurityStatusNotSatisfied);
+                }
+
                 let data = &command.data();
 
                 // example:  03 9B 18
                 //      B0 20 7A 20 DC 39 0B 1B A5 56 CC EB 8D CE 7A 8A C8 23 E6 F5 0D 89 17 AA
-                if data.len() != 3 + 24 {
+                //
+                // or, for AES-192 (ykman's default): 0A 9B 18 <24 bytes>
+                if data.len() < 3 || data[1] != 0x9b || data[2] as usize != data.len() - 3 {
                     return Err(Status::IncorrectDataParameter);
                 }
-                let (prefix, new_management_key) = data.split_at(3);
-                if prefix != &[0x03, 0x9b, 0x18] {
-                    return Err(Status::IncorrectDataParameter);
+                let algorithm = state::ManagementKeyAlgorithm::try_from(data[0])
+                    .map_err(|_| Status::IncorrectDataParameter)?;
+                let new_management_key = &data[3..];
+                self.state.persistent(&mut self.trussed)?
+                    .set_management_key(algorithm, new_management_key)
+                    .map_err(|_| Status::IncorrectDataParameter)?;
+
+            }
+
+            YubicoPivExtension::GetMetadata => {
+                if command.p1 != 0x00 {
+                    return Err(Status::IncorrectP1OrP2Parameter);
+                }
+                self.get_metadata(command.p2, reply)?;
+            }
+
+            YubicoPivExtension::MoveKey => {
+                // P1 is the destination, 0xff to delete, P2 the source
+                if !self.state.runtime.app_security_status.management_verified {
+                    return Err(Status::SecurityStatusNotSatisfied);
                 }
-                let new_management_key: [u8; 24] = new_management_key.try_into().unwrap();
-                self.state.persistent(&mut self.trussed).set_management_key(&new_management_key);
 
+                let from = state::SlotName::try_from(command.p2)
+                    .map_err(|_| Status::IncorrectP1OrP2Parameter)?;
+                if from.index().is_none() || command.p1 == command.p2 {
+                    return Err(Status::IncorrectP1OrP2Parameter);
+                }
+
+                let mut persistent_state = self.state.persistent(&mut self.trussed)?;
+                if persistent_state.state.keys.asymmetric_key(from).is_none() {
+                    return Err(Status::KeyReferenceNotFound);
+                }
+
+                if command.p1 == 0xff {
+                    persistent_state.delete_asymmetric_key(from)
+                        .map_err(|_| Status::UnspecifiedPersistentExecutionError)?;
+                } else {
+                    let to = state::SlotName::try_from(command.p1)
+                        .map_err(|_| Status::IncorrectP1OrP2Parameter)?;
+                    if to.index().is_none() {
+                        return Err(Status::IncorrectP1OrP2Parameter);
+                    }
+                    // we don't silently overwrite keys
+                    if persistent_state.state.keys.asymmetric_key(to).is_some() {
+                        return Err(Status::ConditionsOfUseNotSatisfied);
+                    }
+                    persistent_state.move_asymmetric_key(from, to)
+                        .map_err(|_| Status::UnspecifiedPersistentExecutionError)?;
+                }
             }
 
             _ => return Err(Status::FunctionNotSupported),
@@ -1064,6 +1711,71 @@ where
         Ok(())
     }
 
+    /// Yubico's GET METADATA, for the PIN ('80'), the PUK ('81'),
+    /// the management key ('9B'), and the asymmetric keys.
+    fn get_metadata<const R: usize>(&mut self, reference: u8, reply: &mut Data<R>) -> Result
+    {
+        const ALGORITHM: u8 = 0x01;
+        const POLICY: u8 = 0x02;
+        const ORIGIN: u8 = 0x03;
+        const PUBLIC_KEY: u8 = 0x04;
+        const IS_DEFAULT: u8 = 0x05;
+        const RETRIES: u8 = 0x06;
+        // PIN and PUK have no algorithm
+        const NO_ALGORITHM: u8 = 0xff;
+
+        let mut persistent_state = self.state.persistent(&mut self.trussed)?;
+        match reference {
+            0x80 | 0x81 => {
+                let (is_default, total, remaining) = match reference {
+                    0x80 => (
+                        persistent_state.pin_is_default(),
+                        state::Persistent::<T>::PIN_RETRIES_DEFAULT,
+                        persistent_state.remaining_pin_retries(),
+                    ),
+                    _ => (
+                        persistent_state.puk_is_default(),
+                        state::Persistent::<T>::PUK_RETRIES_DEFAULT,
+                        persistent_state.remaining_puk_retries(),
+                    ),
+                };
+                reply.extend_from_slice(&[
+                    ALGORITHM, 1, NO_ALGORITHM,
+                    IS_DEFAULT, 1, is_default as u8,
+                    RETRIES, 2, total, remaining,
+                ]).map_err(|_| Status::NotEnoughMemory)?;
+            }
+
+            0x9b => {
+                let algorithm = persistent_state.management_key_algorithm();
+                let is_default = persistent_state.management_key_is_default();
+                reply.extend_from_slice(&[
+                    ALGORITHM, 1, algorithm.into(),
+                    // no PIN, touch never
+                    POLICY, 2, 0x00, u8::from(state::TouchPolicy::Never),
+                    IS_DEFAULT, 1, is_default as u8,
+                ]).map_err(|_| Status::NotEnoughMemory)?;
+            }
+
+            reference => {
+                let slot = state::SlotName::try_from(reference)
+                    .map_err(|_| Status::KeyReferenceNotFound)?;
+                let (key, metadata) = persistent_state.state.keys.asymmetric_key(slot)
+                    .ok_or(Status::KeyReferenceNotFound)?;
+
+                reply.extend_from_slice(&[
+                    ALGORITHM, 1, metadata.algorithm.into(),
+                    POLICY, 2, metadata.pin_policy.into(), metadata.touch_policy.into(),
+                    ORIGIN, 1, metadata.origin.into(),
+                    PUBLIC_KEY,
+                ]).map_err(|_| Status::NotEnoughMemory)?;
+                let public_key = self.derive_public_key(key, metadata.algorithm);
+                Self::encode_public_key(metadata.algorithm, &public_key, reply)?;
+            }
+        }
+        Ok(())
+    }
+
 }
 
 
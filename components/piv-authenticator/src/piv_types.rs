@@ -72,11 +72,12 @@ pub enum Algorithms {
 
     // https://globalplatform.org/wp-content/uploads/2014/03/GPC_ISO_Framework_v1.0.pdf#page=15
     P521 = 0x15,
+    // non-standard! as in YubiKey 5.7
+    Rsa3k = 0x05,
+    Rsa4k = 0x16,
+    Ed255 = 0xE0,
+    X255 = 0xE1,
     // non-standard!
-    Rsa3k = 0xE0,
-    Rsa4k = 0xE1,
-    Ed255 = 0xE2,
-    X255 = 0xE3,
     Ed448 = 0xE4,
     X448 = 0xE5,
 
@@ -0,0 +1,331 @@
+//! PIV secure messaging, cipher suite 2 (SP 800-73-4, Part 2, Section 4).
+//!
+//! Key establishment follows the Opacity ZKM protocol: the client sends an ephemeral P-256
+//! public key, which we combine with the static secure messaging key (key reference '04')
+//! to derive four AES-128 session keys. Afterwards, commands and responses are protected
+//! with AES-128-CBC and AES-CMAC, as in ISO 7816-4.
+//!
+//! The static key is in the keystore, and Trussed computes the shared secret. Trussed implements
+//! neither AES-128 nor CMAC, so the rest is done in the app itself, with the RustCrypto
+//! implementations.
+
+use core::convert::TryInto;
+
+use aes::{Aes128, BlockDecrypt, BlockEncrypt, NewBlockCipher};
+use cmac::{Cmac, Mac, NewMac};
+use heapless::Vec;
+use iso7816::Status;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use crate::derp;
+
+/// Algorithm identifier of cipher suite 2 (ECCP256 + AES-128)
+pub const CIPHER_SUITE_2: u8 = 0x27;
+
+const BLOCK_SIZE: usize = 16;
+// the four session keys are AES-128
+const ALGORITHM_ID: [u8; 4] = [0x09, 0x09, 0x09, 0x09];
+// no persistent binding
+const CONTROL_BYTE: u8 = 0x00;
+
+const UNCOMPRESSED_POINT_LENGTH: usize = 65;
+const HOST_ID_LENGTH: usize = 8;
+const MAC_LENGTH: usize = 8;
+
+pub type Block = [u8; BLOCK_SIZE];
+
+/// A raw APDU, large enough for the commands of the authenticator.
+pub type RawCommand<const C: usize> = Vec<u8, C>;
+
+/// Session keys and counters of an established secure messaging channel.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Session {
+    encryption_key: Block,
+    mac_key: Block,
+    response_mac_key: Block,
+    counter: Block,
+    mac_chaining_value: Block,
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.encryption_key = [0; BLOCK_SIZE];
+        self.mac_key = [0; BLOCK_SIZE];
+        self.response_mac_key = [0; BLOCK_SIZE];
+    }
+}
+
+fn cmac(key: &Block, parts: &[&[u8]]) -> Block {
+    let mut mac = Cmac::<Aes128>::new_from_slice(key).unwrap();
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+fn encrypt_block(key: &Block, block: &Block) -> Block {
+    let mut block = aes::Block::clone_from_slice(block);
+    Aes128::new_from_slice(key).unwrap().encrypt_block(&mut block);
+    block.into()
+}
+
+/// Append the TLV, the lengths here are always less than 64K.
+fn tlv<const N: usize>(out: &mut Vec<u8, N>, tag: u8, value: &[u8]) -> Result<(), ()> {
+    out.push(tag).map_err(drop)?;
+    match value.len() {
+        len @ 0..=0x7f => out.push(len as u8).map_err(drop)?,
+        len @ 0x80..=0xff => out.extend_from_slice(&[0x81, len as u8])?,
+        len => out.extend_from_slice(&[0x82, (len >> 8) as u8, len as u8])?,
+    }
+    out.extend_from_slice(value)
+}
+
+/// ISO/IEC 9797-1 padding method 2
+fn pad<const N: usize>(data: &mut Vec<u8, N>) -> Result<(), ()> {
+    data.push(0x80).map_err(drop)?;
+    while data.len() % BLOCK_SIZE != 0 {
+        data.push(0).map_err(drop)?;
+    }
+    Ok(())
+}
+
+fn unpad(data: &[u8]) -> Option<&[u8]> {
+    let end = data.iter().rposition(|&byte| byte != 0)?;
+    if data[end] != 0x80 {
+        return None;
+    }
+    Some(&data[..end])
+}
+
+/// The client's part of key establishment, the GENERAL AUTHENTICATE with key reference '04'
+/// and algorithm '27'.
+pub struct Request<'a> {
+    host_id: &'a [u8],
+    /// Q_eH, as uncompressed point
+    pub ephemeral_public_key: &'a [u8],
+}
+
+/// Parse the data field `7C L 81 L <CB_H | ID_sH | Q_eH> 82 00`.
+pub fn request(data: &[u8]) -> Result<Request<'_>, Status> {
+    let client_data = derp::Input::from(data).read_all(derp::Error::Read, |input| {
+        derp::nested(input, 0x7c, |input| {
+            let client_data = derp::expect_tag_and_get_value(input, 0x81)?;
+            derp::expect_tag_and_value(input, 0x82, &[])?;
+            Ok(client_data.as_slice_less_safe())
+        })
+    }).map_err(|_| Status::IncorrectDataParameter)?;
+
+    if client_data.len() != 1 + HOST_ID_LENGTH + UNCOMPRESSED_POINT_LENGTH || client_data[0] != CONTROL_BYTE {
+        return Err(Status::IncorrectDataParameter);
+    }
+    let host_id = &client_data[1..][..HOST_ID_LENGTH];
+    let ephemeral_public_key = &client_data[1 + HOST_ID_LENGTH..];
+
+    // also validates the point is on the curve
+    p256::PublicKey::from_sec1_bytes(ephemeral_public_key)
+        .map_err(|_| Status::IncorrectDataParameter)?;
+    Ok(Request { host_id, ephemeral_public_key })
+}
+
+/// Key establishment, given the shared secret of the static key and Q_eH.
+///
+/// The response is `7C L 82 L <CB_ICC | N_ICC | AuthCryptogram_ICC | C_ICC>`.
+pub fn establish<const R: usize>(
+    request: &Request,
+    shared_secret: &[u8],
+    card_verifiable_certificate: &[u8],
+    nonce: &Block,
+    reply: &mut Vec<u8, R>,
+) -> Result<Session, Status> {
+    let Request { host_id, ephemeral_public_key } = *request;
+
+    // ID_sICC: leftmost 8 bytes of the hash of C_ICC
+    let card_id = Sha256::digest(card_verifiable_certificate);
+    let card_id = &card_id[..8];
+
+    // leftmost 16 bytes of the x-coordinate of Q_eH
+    let truncated_public_key = &ephemeral_public_key[1..][..16];
+
+    // single-step KDF of SP 800-56A, with SHA-256
+    let mut key_material = [0u8; 2 * 32];
+    for (counter, chunk) in key_material.chunks_mut(32).enumerate() {
+        let mut hash = Sha256::new();
+        hash.update(&(counter as u32 + 1).to_be_bytes());
+        hash.update(shared_secret);
+        hash.update(&[ALGORITHM_ID.len() as u8]);
+        hash.update(&ALGORITHM_ID);
+        hash.update(&[HOST_ID_LENGTH as u8]);
+        hash.update(host_id);
+        hash.update(&[1, CONTROL_BYTE]);
+        hash.update(&[truncated_public_key.len() as u8]);
+        hash.update(truncated_public_key);
+        hash.update(&[card_id.len() as u8]);
+        hash.update(card_id);
+        hash.update(&[nonce.len() as u8]);
+        hash.update(nonce);
+        hash.update(&[1, CONTROL_BYTE]);
+        chunk.copy_from_slice(&hash.finalize());
+    }
+
+    let block = |i: usize| -> Block { key_material[i * BLOCK_SIZE..][..BLOCK_SIZE].try_into().unwrap() };
+    let confirmation_key = block(0);
+    let session = Session {
+        mac_key: block(1),
+        encryption_key: block(2),
+        response_mac_key: block(3),
+        counter: {
+            let mut counter = [0u8; BLOCK_SIZE];
+            counter[BLOCK_SIZE - 1] = 1;
+            counter
+        },
+        mac_chaining_value: [0u8; BLOCK_SIZE],
+    };
+
+    let authentication_cryptogram = cmac(&confirmation_key, &[
+        b"KC_1_V",
+        card_id,
+        host_id,
+        ephemeral_public_key,
+    ]);
+
+    let mut response = Vec::<u8, 1024>::new();
+    response.push(CONTROL_BYTE).map_err(|_| Status::NotEnoughMemory)?;
+    response.extend_from_slice(nonce).map_err(|_| Status::NotEnoughMemory)?;
+    response.extend_from_slice(&authentication_cryptogram).map_err(|_| Status::NotEnoughMemory)?;
+    response.extend_from_slice(card_verifiable_certificate).map_err(|_| Status::NotEnoughMemory)?;
+
+    let mut template = Vec::<u8, 1030>::new();
+    tlv(&mut template, 0x82, &response).map_err(|_| Status::NotEnoughMemory)?;
+    tlv(reply, 0x7c, &template).map_err(|_| Status::NotEnoughMemory)?;
+
+    Ok(session)
+}
+
+impl Session {
+    fn increment_counter(&mut self) {
+        for byte in self.counter.iter_mut().rev() {
+            let (incremented, overflow) = byte.overflowing_add(1);
+            *byte = incremented;
+            if !overflow {
+                break;
+            }
+        }
+    }
+
+    /// Verify and decrypt a command, returning the equivalent plain APDU.
+    ///
+    /// Any failure here must terminate the session.
+    pub fn unwrap_command<const C: usize>(&mut self, command: &iso7816::Command<C>) -> Result<RawCommand<C>, Status> {
+        let header = [command.class().into_inner(), command.instruction().into(), command.p1, command.p2];
+
+        let (mut cryptogram, mut expected, mut mac) = (None, None, None);
+        derp::Input::from(command.data()).read_all(derp::Error::Read, |input| {
+            while !input.at_end() {
+                let (tag, value) = derp::read_tag_and_get_value(input)?;
+                let value = value.as_slice_less_safe();
+                match tag {
+                    0x87 => cryptogram = Some(value),
+                    0x97 => expected = Some(value),
+                    // the MAC must come last
+                    0x8e if input.at_end() && value.len() == MAC_LENGTH => mac = Some(value),
+                    _ => return Err(derp::Error::WrongTag),
+                }
+            }
+            Ok(())
+        }).map_err(|_| Status::SecureMessagingDataObjectsIncorrect)?;
+        let mac = mac.ok_or(Status::SecureMessagingDataObjectsMissing)?;
+
+        // MAC over MCV | padded header | data objects (everything but '8E')
+        let data_objects = &command.data()[..command.data().len() - 2 - MAC_LENGTH];
+        let mut padded_header = Vec::<u8, BLOCK_SIZE>::from_slice(&header).unwrap();
+        pad(&mut padded_header).unwrap();
+        let full_mac = cmac(&self.mac_key, &[&self.mac_chaining_value, &padded_header, data_objects]);
+        if !bool::from(mac.ct_eq(&full_mac[..MAC_LENGTH])) {
+            return Err(Status::SecureMessagingDataObjectsIncorrect);
+        }
+        self.mac_chaining_value = full_mac;
+
+        let mut plain = RawCommand::<C>::new();
+        // strip the SM indication from CLA
+        plain.extend_from_slice(&[header[0] & !0x0c, header[1], header[2], header[3]]).unwrap();
+
+        let mut data = RawCommand::<C>::new();
+        if let Some(cryptogram) = cryptogram {
+            // padding content indicator
+            let (&indicator, cryptogram) = cryptogram.split_first().ok_or(Status::SecureMessagingDataObjectsIncorrect)?;
+            if indicator != 0x01 || cryptogram.is_empty() || cryptogram.len() % BLOCK_SIZE != 0 {
+                return Err(Status::SecureMessagingDataObjectsIncorrect);
+            }
+
+            let cipher = Aes128::new_from_slice(&self.encryption_key).unwrap();
+            let mut chaining_block = encrypt_block(&self.encryption_key, &self.counter);
+            for chunk in cryptogram.chunks(BLOCK_SIZE) {
+                let mut block = aes::Block::clone_from_slice(chunk);
+                cipher.decrypt_block(&mut block);
+                for (byte, chained) in block.iter_mut().zip(chaining_block.iter()) {
+                    *byte ^= chained;
+                }
+                data.extend_from_slice(&block).map_err(|_| Status::WrongLength)?;
+                chaining_block.copy_from_slice(chunk);
+            }
+            let unpadded_length = unpad(&data).ok_or(Status::SecureMessagingDataObjectsIncorrect)?.len();
+            data.truncate(unpadded_length);
+        }
+
+        // extended length if necessary
+        let extended = data.len() > 255;
+        if !data.is_empty() {
+            if extended {
+                plain.extend_from_slice(&[0, (data.len() >> 8) as u8, data.len() as u8]).map_err(|_| Status::WrongLength)?;
+            } else {
+                plain.push(data.len() as u8).map_err(|_| Status::WrongLength)?;
+            }
+            plain.extend_from_slice(&data).map_err(|_| Status::WrongLength)?;
+        }
+        if let Some(expected) = expected {
+            if extended && expected.len() == 1 {
+                plain.extend_from_slice(&[0, expected[0]]).map_err(|_| Status::WrongLength)?;
+            } else {
+                plain.extend_from_slice(expected).map_err(|_| Status::WrongLength)?;
+            }
+        }
+
+        Ok(plain)
+    }
+
+    /// Encrypt and MAC the response to the previously unwrapped command.
+    pub fn wrap_response<const R: usize>(&mut self, data: &[u8], status: [u8; 2], reply: &mut Vec<u8, R>) -> Result<(), ()> {
+        reply.clear();
+
+        if !data.is_empty() {
+            // response IV: the counter, with most significant byte set to 0x80
+            let mut iv_input = self.counter;
+            iv_input[0] = 0x80;
+            let mut chaining_block = encrypt_block(&self.encryption_key, &iv_input);
+
+            let mut padded = Vec::<u8, R>::from_slice(data)?;
+            pad(&mut padded)?;
+            let mut cryptogram = Vec::<u8, R>::new();
+            cryptogram.push(0x01).map_err(drop)?;
+            let cipher = Aes128::new_from_slice(&self.encryption_key).unwrap();
+            for chunk in padded.chunks(BLOCK_SIZE) {
+                let mut block = aes::Block::clone_from_slice(chunk);
+                for (byte, chained) in block.iter_mut().zip(chaining_block.iter()) {
+                    *byte ^= chained;
+                }
+                cipher.encrypt_block(&mut block);
+                chaining_block.copy_from_slice(&block);
+                cryptogram.extend_from_slice(&block)?;
+            }
+            tlv(reply, 0x87, &cryptogram)?;
+        }
+        tlv(reply, 0x99, &status)?;
+
+        let full_mac = cmac(&self.response_mac_key, &[&self.mac_chaining_value, reply]);
+        tlv(reply, 0x8e, &full_mac[..MAC_LENGTH])?;
+
+        self.increment_counter();
+        Ok(())
+    }
+}
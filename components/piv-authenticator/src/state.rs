@@ -11,6 +11,8 @@ use crate::constants::*;
 
 use crate::{Pin, Puk};
 
+pub mod migrations;
+
 pub type Result<T> = core::result::Result<T, ()>;
 
 pub enum Key {
@@ -18,17 +20,140 @@ pub enum Key {
     P256(KeyId),
     X255(KeyId),
 }
+
+/// Algorithm of an asymmetric key held in one of the slots.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum AsymmetricAlgorithm {
+    P256,
+    Ed255,
+    X255,
+}
+
+impl AsymmetricAlgorithm {
+    pub fn mechanism(&self) -> trussed::types::Mechanism {
+        use trussed::types::Mechanism;
+        match *self {
+            Self::P256 => Mechanism::P256,
+            Self::Ed255 => Mechanism::Ed255,
+            Self::X255 => Mechanism::X255,
+        }
+    }
+}
+
+/// The algorithm identifiers of `piv_types::Algorithms`, for Ed25519 and X25519
+/// the non-standard ones of YubiKey 5.7.
+impl TryFrom<u8> for AsymmetricAlgorithm {
+    type Error = ();
+    fn try_from(algorithm: u8) -> core::result::Result<Self, Self::Error> {
+        use crate::piv_types::Algorithms;
+        Ok(match algorithm {
+            x if x == Algorithms::P256 as u8 => Self::P256,
+            x if x == Algorithms::Ed255 as u8 => Self::Ed255,
+            x if x == Algorithms::X255 as u8 => Self::X255,
+            // what we used before, in piv-go's fork
+            0x22 => Self::Ed255,
+            _ => return Err(()),
+        })
+    }
+}
+
+impl From<AsymmetricAlgorithm> for u8 {
+    fn from(algorithm: AsymmetricAlgorithm) -> Self {
+        use crate::piv_types::Algorithms;
+        match algorithm {
+            AsymmetricAlgorithm::P256 => Algorithms::P256 as u8,
+            AsymmetricAlgorithm::Ed255 => Algorithms::Ed255 as u8,
+            AsymmetricAlgorithm::X255 => Algorithms::X255 as u8,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
 pub enum PinPolicy {
     Never,
     Once,
     Always,
 }
+
+/// Yubico encoding (tag 0xAA), the "default" value 0x00 is not accepted here.
+impl TryFrom<u8> for PinPolicy {
+    type Error = u8;
+    fn try_from(policy: u8) -> core::result::Result<Self, Self::Error> {
+        Ok(match policy {
+            0x01 => Self::Never,
+            0x02 => Self::Once,
+            0x03 => Self::Always,
+            _ => return Err(policy),
+        })
+    }
+}
+
+impl From<PinPolicy> for u8 {
+    fn from(policy: PinPolicy) -> Self {
+        match policy {
+            PinPolicy::Never => 0x01,
+            PinPolicy::Once => 0x02,
+            PinPolicy::Always => 0x03,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
 pub enum TouchPolicy {
     Never,
     Always,
     Cached,
 }
 
+/// Yubico encoding (tag 0xAB), the "default" value 0x00 is not accepted here.
+impl TryFrom<u8> for TouchPolicy {
+    type Error = u8;
+    fn try_from(policy: u8) -> core::result::Result<Self, Self::Error> {
+        Ok(match policy {
+            0x01 => Self::Never,
+            0x02 => Self::Always,
+            0x03 => Self::Cached,
+            _ => return Err(policy),
+        })
+    }
+}
+
+impl From<TouchPolicy> for u8 {
+    fn from(policy: TouchPolicy) -> Self {
+        match policy {
+            TouchPolicy::Never => 0x01,
+            TouchPolicy::Always => 0x02,
+            TouchPolicy::Cached => 0x03,
+        }
+    }
+}
+
+/// Whether a key was generated on the device, or imported from outside.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum KeyOrigin {
+    Generated,
+    Imported,
+}
+
+/// Yubico encoding, as in GET METADATA
+impl From<KeyOrigin> for u8 {
+    fn from(origin: KeyOrigin) -> Self {
+        match origin {
+            KeyOrigin::Generated => 0x01,
+            KeyOrigin::Imported => 0x02,
+        }
+    }
+}
+
+/// What we need to remember about an asymmetric key, besides its `KeyId`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct KeyMetadata {
+    pub algorithm: AsymmetricAlgorithm,
+    pub pin_policy: PinPolicy,
+    pub touch_policy: TouchPolicy,
+    pub origin: KeyOrigin,
+}
+
 pub struct Slot {
     pub key: Option<KeyId>,
     pub pin_policy: PinPolicy,
@@ -54,6 +179,7 @@ impl Slot {
     }
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct RetiredSlotIndex(u8);
 
 impl core::convert::TryFrom<u8> for RetiredSlotIndex {
@@ -66,6 +192,7 @@ impl core::convert::TryFrom<u8> for RetiredSlotIndex {
         }
     }
 }
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum SlotName {
     Identity,
     Management,  // Personalization? Administration?
@@ -103,6 +230,19 @@ impl SlotName {
             Attestation => 0xf9,
         }
     }
+    /// Position in `Keys::metadata`, for the slots that can hold an asymmetric key.
+    pub(crate) fn index(&self) -> Option<usize> {
+        use SlotName::*;
+        match *self {
+            Identity => Some(0),
+            Signature => Some(1),
+            Decryption => Some(2),
+            Pinless => Some(3),
+            Retired(RetiredSlotIndex(i)) => Some(3 + i as usize),
+            Management | Attestation => None,
+        }
+    }
+
     pub fn tag(&self) -> u32 {
         use SlotName::*;
         match *self {
@@ -117,13 +257,94 @@ impl SlotName {
     }
 }
 
+impl TryFrom<u8> for SlotName {
+    type Error = u8;
+    fn try_from(reference: u8) -> core::result::Result<Self, Self::Error> {
+        use SlotName::*;
+        Ok(match reference {
+            0x9a => Identity,
+            0x9b => Management,
+            0x9c => Signature,
+            0x9d => Decryption,
+            0x9e => Pinless,
+            0x82..=0x95 => Retired(RetiredSlotIndex(reference - 0x81)),
+            0xf9 => Attestation,
+            _ => return Err(reference),
+        })
+    }
+}
+
+/// Algorithm of the management key (9B), with its NIST SP 800-78-4 identifier.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum ManagementKeyAlgorithm {
+    Tdes,
+    Aes128,
+    Aes192,
+    Aes256,
+}
+
+impl Default for ManagementKeyAlgorithm {
+    fn default() -> Self {
+        Self::Tdes
+    }
+}
+
+impl ManagementKeyAlgorithm {
+    pub fn key_length(&self) -> usize {
+        use ManagementKeyAlgorithm::*;
+        match *self {
+            Tdes => 24,
+            Aes128 => 16,
+            Aes192 => 24,
+            Aes256 => 32,
+        }
+    }
+
+    /// Also the length of witnesses and challenges during authentication.
+    pub fn block_size(&self) -> usize {
+        match *self {
+            Self::Tdes => 8,
+            _ => 16,
+        }
+    }
+}
+
+impl TryFrom<u8> for ManagementKeyAlgorithm {
+    type Error = u8;
+    fn try_from(algorithm: u8) -> core::result::Result<Self, Self::Error> {
+        use ManagementKeyAlgorithm::*;
+        Ok(match algorithm {
+            0x03 => Tdes,
+            0x08 => Aes128,
+            0x0a => Aes192,
+            0x0c => Aes256,
+            _ => return Err(algorithm),
+        })
+    }
+}
+
+impl From<ManagementKeyAlgorithm> for u8 {
+    fn from(algorithm: ManagementKeyAlgorithm) -> u8 {
+        use ManagementKeyAlgorithm::*;
+        match algorithm {
+            Tdes => 0x03,
+            Aes128 => 0x08,
+            Aes192 => 0x0a,
+            Aes256 => 0x0c,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
 pub struct Keys {
     // 9a "PIV Authentication Key" (YK: PIV Authentication)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub authentication_key: Option<KeyId>,
     // 9b "PIV Card Application Administration Key" (YK: PIV Management)
+    // a shared secret of the key length of `management_key_algorithm`
     pub management_key: KeyId,
+    #[serde(default)]
+    pub management_key_algorithm: ManagementKeyAlgorithm,
     // 9c "Digital Signature Key" (YK: Digital Signature)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub signature_key: Option<KeyId>,
@@ -135,6 +356,43 @@ pub struct Keys {
     pub pinless_authentication_key: Option<KeyId>,
     // 0x82..=0x95 (130-149)
     pub retired_keys: [Option<KeyId>; 20],
+    // algorithm, policies and origin of the asymmetric keys above, see `SlotName::index`
+    #[serde(default)]
+    pub metadata: [Option<KeyMetadata>; 24],
+    // 04 "Secure Messaging Key", a P256 key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secure_messaging_key: Option<KeyId>,
+}
+
+impl Keys {
+    fn asymmetric_key_mut(&mut self, slot: SlotName) -> Option<&mut Option<KeyId>> {
+        use SlotName::*;
+        match slot {
+            Identity => Some(&mut self.authentication_key),
+            Signature => Some(&mut self.signature_key),
+            Decryption => Some(&mut self.encryption_key),
+            Pinless => Some(&mut self.pinless_authentication_key),
+            Retired(RetiredSlotIndex(i)) => Some(&mut self.retired_keys[i as usize - 1]),
+            Management | Attestation => None,
+        }
+    }
+
+    /// The asymmetric key in the slot, if any, together with its metadata.
+    ///
+    /// Keys from before metadata was recorded get theirs in `migrations`.
+    pub fn asymmetric_key(&self, slot: SlotName) -> Option<(KeyId, KeyMetadata)> {
+        use SlotName::*;
+        let metadata = self.metadata[slot.index()?]?;
+        let key = match slot {
+            Identity => self.authentication_key,
+            Signature => self.signature_key,
+            Decryption => self.encryption_key,
+            Pinless => self.pinless_authentication_key,
+            Retired(RetiredSlotIndex(i)) => self.retired_keys[i as usize - 1],
+            Management | Attestation => None,
+        }?;
+        Some((key, metadata))
+    }
 }
 
 
@@ -155,11 +413,13 @@ impl<const C: usize> State<C> {
     //
     // TODO: it is really not good to overwrite user data on failure to decode old state.
     // To fix this, need a flag to detect if we're "fresh", and/or initialize state in factory.
-    pub fn persistent<'t, T>(&mut self, trussed: &'t mut T) -> Persistent<'t, T>
+    /// Fails if there is a state, but this firmware can't read it (see `migrations`).
+    pub fn persistent<'t, T>(&mut self, trussed: &'t mut T) -> core::result::Result<Persistent<'t, T>, iso7816::Status>
     where T: TrussedClient
         + trussed::client::Tdes
     {
         Persistent::load_or_initialize(trussed)
+            .map_err(|_| iso7816::Status::UnspecifiedPersistentExecutionError)
     }
 }
 
@@ -219,6 +479,12 @@ pub struct PersistentState {
     timestamp: u32,
     // must be a valid RFC 4122 UUID 1, 2 or 4
     guid: [u8; 16],
+    // eight ASCII digits, to establish the virtual contact interface
+    #[serde(default)]
+    pairing_code: Option<[u8; 8]>,
+    // shared by both fingers, like the PIN retries by PIN and global PIN
+    #[serde(default)]
+    consecutive_occ_mismatches: u8,
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -243,6 +509,7 @@ pub struct Runtime<const C: usize> {
     pub app_security_status: AppSecurityStatus,
     pub command_cache: Option<CommandCache>,
     pub chained_command: Option<iso7816::Command<C>>,
+    pub secure_messaging: Option<crate::secure_messaging::Session>,
 }
 
 // pub trait Aid {
@@ -320,6 +587,11 @@ pub struct AppSecurityStatus {
     pub pin_verified: bool,
     pub puk_verified: bool,
     pub management_verified: bool,
+    pub pairing_code_verified: bool,
+    /// on-card biometric comparison, satisfies the PIN-once policy
+    pub occ_verified: bool,
+    /// uptime of the last confirmed user presence, for the cached touch policy
+    pub touched_at: Option<core::time::Duration>,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -335,7 +607,7 @@ pub struct GetData {
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct AuthenticateManagement {
-    pub challenge: [u8; 8],
+    pub challenge: heapless::Vec<u8, 16>,
 }
 
 impl<'t, T> Persistent<'t, T>
@@ -346,7 +618,9 @@ where
     pub const PIN_RETRIES_DEFAULT: u8 = 3;
     // hmm...!
     pub const PUK_RETRIES_DEFAULT: u8 = 5;
+    pub const OCC_RETRIES_DEFAULT: u8 = 5;
     const FILENAME: &'static [u8] = b"persistent-state.cbor";
+    const BACKUP_FILENAME: &'static [u8] = b"persistent-state.cbor.bak";
     const DEFAULT_PIN: &'static [u8] = b"123456\xff\xff";
     const DEFAULT_PUK: &'static [u8] = b"12345678";
 
@@ -370,6 +644,10 @@ where
         }
     }
 
+    pub fn remaining_occ_retries(&self) -> u8 {
+        Self::OCC_RETRIES_DEFAULT.saturating_sub(self.state.consecutive_occ_mismatches)
+    }
+
     pub fn verify_pin(&self, other_pin: &Pin) -> bool {
         // hprintln!("verifying pin {:?} against {:?}", other_pin, &self.pin).ok();
         self.state.pin == *other_pin
@@ -400,6 +678,14 @@ where
         self.reset_consecutive_puk_mismatches();
     }
 
+    pub fn pin_is_default(&self) -> bool {
+        self.state.pin == Pin::try_from(Self::DEFAULT_PIN).unwrap()
+    }
+
+    pub fn puk_is_default(&self) -> bool {
+        self.state.puk == Puk::try_from(Self::DEFAULT_PUK).unwrap()
+    }
+
     pub fn increment_consecutive_pin_mismatches(&mut self) -> u8 {
         if self.state.consecutive_pin_mismatches >= Self::PIN_RETRIES_DEFAULT {
             return 0;
@@ -420,6 +706,16 @@ where
         Self::PUK_RETRIES_DEFAULT - self.state.consecutive_puk_mismatches
     }
 
+    pub fn increment_consecutive_occ_mismatches(&mut self) -> u8 {
+        if self.state.consecutive_occ_mismatches >= Self::OCC_RETRIES_DEFAULT {
+            return 0;
+        }
+
+        self.state.consecutive_occ_mismatches += 1;
+        self.save();
+        Self::OCC_RETRIES_DEFAULT - self.state.consecutive_occ_mismatches
+    }
+
     pub fn reset_consecutive_pin_mismatches(&mut self) -> u8 {
         if self.state.consecutive_pin_mismatches != 0 {
             self.state.consecutive_pin_mismatches = 0;
@@ -438,11 +734,25 @@ where
         Self::PUK_RETRIES_DEFAULT
     }
 
+    pub fn reset_consecutive_occ_mismatches(&mut self) -> u8 {
+        if self.state.consecutive_occ_mismatches != 0 {
+            self.state.consecutive_occ_mismatches = 0;
+            self.save();
+        }
+
+        Self::OCC_RETRIES_DEFAULT
+    }
+
     pub fn reset_management_key(&mut self) {
-        self.set_management_key(YUBICO_DEFAULT_MANAGEMENT_KEY);
+        self.set_management_key(ManagementKeyAlgorithm::Tdes, YUBICO_DEFAULT_MANAGEMENT_KEY)
+            .unwrap();
     }
 
-    pub fn set_management_key(&mut self, management_key: &[u8; 24]) {
+    pub fn set_management_key(&mut self, algorithm: ManagementKeyAlgorithm, management_key: &[u8]) -> Result<()> {
+        if management_key.len() != algorithm.key_length() {
+            return Err(());
+        }
+
         // let new_management_key = syscall!(self.trussed.unsafe_inject_tdes_key(
         let new_management_key = syscall!(self.trussed.unsafe_inject_shared_key(
             management_key,
@@ -450,8 +760,163 @@ where
         )).key;
         let old_management_key = self.state.keys.management_key;
         self.state.keys.management_key = new_management_key;
+        self.state.keys.management_key_algorithm = algorithm;
         self.save();
         syscall!(self.trussed.delete(old_management_key));
+        Ok(())
+    }
+
+    /// The raw AES management key, Trussed has no AES-128 and AES-192.
+    fn aes_management_key(&mut self) -> Result<trussed::types::SerializedKey> {
+        use trussed::types::{KeySerialization, Mechanism};
+        Ok(try_syscall!(self.trussed.serialize_key(
+            Mechanism::SharedSecret,
+            self.state.keys.management_key,
+            KeySerialization::Raw,
+        )).map_err(drop)?.serialized_key)
+    }
+
+    pub fn management_key_is_default(&mut self) -> bool {
+        match self.state.keys.management_key_algorithm {
+            // compare a known answer, as Trussed uses the 3DES key itself
+            ManagementKeyAlgorithm::Tdes => self.encrypt_with_management_key(&[0u8; 8])
+                .map(|ciphertext| ciphertext.as_slice() == &YUBICO_DEFAULT_MANAGEMENT_KEY_KAT[..])
+                .unwrap_or(false),
+            // the default key has 24 bytes, so AES-128 and AES-256 keys never are
+            ManagementKeyAlgorithm::Aes192 => self.aes_management_key()
+                .map(|key| key.as_slice() == &YUBICO_DEFAULT_MANAGEMENT_KEY[..])
+                .unwrap_or(false),
+            ManagementKeyAlgorithm::Aes128 | ManagementKeyAlgorithm::Aes256 => false,
+        }
+    }
+
+    pub fn management_key_algorithm(&self) -> ManagementKeyAlgorithm {
+        self.state.keys.management_key_algorithm
+    }
+
+    /// Encrypt a single block with the management key, as needed to authenticate it.
+    pub fn encrypt_with_management_key(&mut self, block: &[u8]) -> Result<heapless::Vec<u8, 16>> {
+        use aes::{BlockEncrypt, NewBlockCipher};
+        use ManagementKeyAlgorithm::*;
+
+        let algorithm = self.state.keys.management_key_algorithm;
+        if block.len() != algorithm.block_size() {
+            return Err(());
+        }
+
+        if let Tdes = algorithm {
+            let ciphertext = syscall!(self.trussed.encrypt_tdes(self.state.keys.management_key, block)).ciphertext;
+            return heapless::Vec::from_slice(&ciphertext);
+        }
+
+        let key = self.aes_management_key()?;
+        let key = key.as_slice();
+        let mut block = aes::Block::clone_from_slice(block);
+        match algorithm {
+            Aes128 => aes::Aes128::new_from_slice(key).map_err(drop)?.encrypt_block(&mut block),
+            Aes192 => aes::Aes192::new_from_slice(key).map_err(drop)?.encrypt_block(&mut block),
+            Aes256 => aes::Aes256::new_from_slice(key).map_err(drop)?.encrypt_block(&mut block),
+            Tdes => unreachable!(),
+        }
+        heapless::Vec::from_slice(&block)
+    }
+
+    /// The pairing code, generated on first use.
+    pub fn pairing_code(&mut self) -> [u8; 8] {
+        if let Some(pairing_code) = self.state.pairing_code {
+            return pairing_code;
+        }
+
+        // rejection sampling, to get uniformly distributed digits
+        let mut pairing_code = [0u8; 8];
+        let mut length = 0;
+        while length < pairing_code.len() {
+            let bytes = syscall!(self.trussed.random_bytes(16)).bytes;
+            for byte in bytes.iter().filter(|&&byte| byte < 250) {
+                if length < pairing_code.len() {
+                    pairing_code[length] = b'0' + byte % 10;
+                    length += 1;
+                }
+            }
+        }
+        self.state.pairing_code = Some(pairing_code);
+        self.save();
+        pairing_code
+    }
+
+    pub fn verify_pairing_code(&mut self, other_pairing_code: &[u8; 8]) -> bool {
+        let pairing_code = self.pairing_code();
+        // constant-time comparison
+        pairing_code.iter().zip(other_pairing_code.iter()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+    }
+
+    pub fn secure_messaging_key(&self) -> Option<KeyId> {
+        self.state.keys.secure_messaging_key
+    }
+
+    /// Store a new secure messaging key, deleting the key it replaces.
+    pub fn set_secure_messaging_key(&mut self, key: KeyId) {
+        let old_key = self.state.keys.secure_messaging_key.replace(key);
+        self.save();
+        if let Some(old_key) = old_key {
+            syscall!(self.trussed.delete(old_key));
+        }
+    }
+
+    /// Store a new asymmetric key in the slot, deleting the key it replaces.
+    pub fn set_asymmetric_key(&mut self, slot: SlotName, key: KeyId, metadata: KeyMetadata) -> Result<()> {
+        let index = slot.index().ok_or(())?;
+        let old_key = self.state.keys.asymmetric_key_mut(slot).ok_or(())?.replace(key);
+        self.state.keys.metadata[index] = Some(metadata);
+        self.save();
+        if let Some(old_key) = old_key {
+            syscall!(self.trussed.delete(old_key));
+        }
+        Ok(())
+    }
+
+    /// Remove the key from the slot, and delete it.
+    pub fn delete_asymmetric_key(&mut self, slot: SlotName) -> Result<()> {
+        let index = slot.index().ok_or(())?;
+        let key = self.state.keys.asymmetric_key_mut(slot).ok_or(())?.take().ok_or(())?;
+        self.state.keys.metadata[index] = None;
+        self.save();
+        syscall!(self.trussed.delete(key));
+        Ok(())
+    }
+
+    /// Move the key, with its metadata, to an empty slot.
+    pub fn move_asymmetric_key(&mut self, from: SlotName, to: SlotName) -> Result<()> {
+        let (from_index, to_index) = (from.index().ok_or(())?, to.index().ok_or(())?);
+        if self.state.keys.asymmetric_key(to).is_some() {
+            return Err(());
+        }
+        let (key, metadata) = self.state.keys.asymmetric_key(from).ok_or(())?;
+
+        *self.state.keys.asymmetric_key_mut(to).ok_or(())? = Some(key);
+        self.state.keys.metadata[to_index] = Some(metadata);
+        *self.state.keys.asymmetric_key_mut(from).ok_or(())? = None;
+        self.state.keys.metadata[from_index] = None;
+        self.save();
+        Ok(())
+    }
+
+    /// Delete the asymmetric keys, with their metadata, and the secure messaging key.
+    pub fn reset_keys(&mut self) {
+        let keys = &mut self.state.keys;
+        let old_keys = [
+            keys.authentication_key.take(),
+            keys.signature_key.take(),
+            keys.encryption_key.take(),
+            keys.pinless_authentication_key.take(),
+            keys.secure_messaging_key.take(),
+        ];
+        let old_retired_keys = core::mem::take(&mut keys.retired_keys);
+        keys.metadata = Default::default();
+        self.save();
+        for key in old_keys.iter().chain(old_retired_keys.iter()).flatten() {
+            try_syscall!(self.trussed.delete(*key)).ok();
+        }
     }
 
     pub fn initialize(trussed: &'t mut T) -> Self {
@@ -473,10 +938,13 @@ where
         let keys = Keys {
             authentication_key: None,
             management_key: management_key,
+            management_key_algorithm: ManagementKeyAlgorithm::Tdes,
             signature_key: None,
             encryption_key: None,
             pinless_authentication_key: None,
             retired_keys: Default::default(),
+            metadata: Default::default(),
+            secure_messaging_key: None,
         };
 
         let mut state = Self {
@@ -489,8 +957,11 @@ where
                 puk: Puk::try_from(Self::DEFAULT_PUK).unwrap(),
                 timestamp: 0,
                 guid,
+                pairing_code: None,
+                consecutive_occ_mismatches: 0,
             }
         };
+        state.pairing_code();
         state.save();
         state
     }
@@ -505,35 +976,48 @@ where
             drop(e)
         })?.data;
 
-        let previous_state: PersistentState = trussed::cbor_deserialize(&data).map_err(|e| {
-            info!("cbor deser error: {:?}", e);
-            info!("data: {:X?}", &data);
+        Self::from_data(trussed, &data)
+    }
+
+    /// Only initializes if there is no state at all, an unreadable one is kept as is.
+    pub fn load_or_initialize(trussed: &'t mut T) -> Result<Self> {
+        match try_syscall!(trussed.read_file(Location::Internal, PathBuf::from(Self::FILENAME))) {
+            Ok(reply) => Self::from_data(trussed, &reply.data),
+            Err(_) => Ok(Self::initialize(trussed)),
+        }
+    }
+
+    fn from_data(trussed: &'t mut T, data: &[u8]) -> Result<Self> {
+        let (previous_state, version) = migrations::deserialize(data).map_err(|e| {
+            info!("state deser error: {:?}", e);
+            info!("data: {:X?}", data);
             drop(e)
         })?;
-        // horrible deser bug to forget Ok here :)
-        Ok(Self { trussed, state: previous_state })
-    }
-
-    pub fn load_or_initialize(trussed: &'t mut T) -> Self {
-        // todo: can't seem to combine load + initialize without code repetition
-        let data = try_syscall!(trussed.read_file(Location::Internal, PathBuf::from(Self::FILENAME)));
-        if let Ok(data) = data {
-            let previous_state = trussed::cbor_deserialize(&data.data).map_err(|e| {
-                info!("cbor deser error: {:?}", e);
-                info!("data: {:X?}", &data);
-                drop(e)
-            });
-            if let Ok(state) = previous_state {
-            // horrible deser bug to forget Ok here :)
-                return Self { trussed, state }
-            }
+        let mut state = Self { trussed, state: previous_state };
+        if version != migrations::VERSION {
+            info_now!("migrated PIV state from version {}", version);
+            state.save();
         }
+        Ok(state)
+    }
 
+    /// Start over from an unreadable state, e.g. on a Yubico RESET.
+    ///
+    /// The old state is kept in a backup file, for a later firmware to recover the keys.
+    pub fn reinitialize(trussed: &'t mut T) -> Self {
+        if let Ok(old) = try_syscall!(trussed.read_file(Location::Internal, PathBuf::from(Self::FILENAME))) {
+            try_syscall!(trussed.write_file(
+                Location::Internal,
+                PathBuf::from(Self::BACKUP_FILENAME),
+                old.data,
+                None,
+            )).ok();
+        }
         Self::initialize(trussed)
     }
 
     pub fn save(&mut self) {
-        let data: trussed::types::Message = trussed::cbor_serialize_bytes(self.as_ref()).unwrap();
+        let data = migrations::serialize(self.as_ref()).unwrap();
 
         syscall!(self.trussed.write_file(
             Location::Internal,
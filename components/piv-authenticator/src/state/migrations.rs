@@ -0,0 +1,303 @@
+//! Versioned storage of the [`PersistentState`]
+//!
+//! The file is a CBOR sequence: the version as a one byte CBOR unsigned integer, followed
+//! by the state in the layout of that version. Files from before this envelope start
+//! directly with the CBOR map of the state, they are version 0.
+//!
+//! Loading decodes the state in the layout of its version, then applies all migrations
+//! from that version on. A state that can't be decoded, or is from a newer firmware, is
+//! an error, it is never replaced by a fresh one: that would lose the key handles.
+//!
+//! Changing the layout means:
+//! - bumping `VERSION`
+//! - decoding the previous layout (fields with `#[serde(default)]` don't need their own struct)
+//! - appending a migration to `MIGRATIONS`
+//! - adding a fixture of the previous layout to the tests
+
+use core::convert::TryFrom;
+
+use trussed::config::MAX_MESSAGE_LENGTH;
+use trussed::types::Message;
+
+use super::{AsymmetricAlgorithm, KeyMetadata, KeyOrigin, PersistentState, SlotName, TouchPolicy};
+
+/// The version this firmware writes.
+pub const VERSION: u8 = 1;
+
+// CBOR major type of maps
+const MAP: u8 = 5;
+
+/// Migrations, indexed by the version they migrate from.
+const MIGRATIONS: [fn(&mut PersistentState); VERSION as usize] = [
+    v0_key_metadata,
+];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// Not CBOR, or not the layout of its version
+    Malformed,
+    /// Written by a newer firmware
+    UnknownVersion(u8),
+    /// Does not fit in a Trussed message
+    TooLarge,
+}
+
+pub fn serialize(state: &PersistentState) -> Result<Message, Error> {
+    let mut buffer = [0u8; MAX_MESSAGE_LENGTH];
+    buffer[0] = VERSION;
+    let length = trussed::cbor_serialize(state, &mut buffer[1..])
+        .map_err(|_| Error::TooLarge)?
+        .len();
+    Message::from_slice(&buffer[..1 + length]).map_err(|_| Error::TooLarge)
+}
+
+/// The state, migrated to the current version, together with the version it was stored in.
+pub fn deserialize(data: &[u8]) -> Result<(PersistentState, u8), Error> {
+    let (version, encoded) = match data.first() {
+        // unsigned integers below 24 are encoded in the initial byte
+        Some(&version) if version < 24 => (version, &data[1..]),
+        Some(&initial_byte) if initial_byte >> 5 == MAP => (0, data),
+        _ => return Err(Error::Malformed),
+    };
+
+    let mut state: PersistentState = match version {
+        // the fields added in version 0 all have defaults
+        0 | VERSION => trussed::cbor_deserialize(encoded).map_err(|_| Error::Malformed)?,
+        _ => return Err(Error::UnknownVersion(version)),
+    };
+
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(&mut state);
+    }
+    Ok((state, version))
+}
+
+/// Keys from before metadata was recorded can only have been generated by
+/// the old Ed25519-only `generate_asymmetric_keypair`.
+fn v0_key_metadata(state: &mut PersistentState) {
+    let keys = &mut state.keys;
+    let slots = (0x9a..=0x9e).chain(0x82..=0x95).filter_map(|reference| SlotName::try_from(reference).ok());
+    for slot in slots {
+        let index = match slot.index() {
+            Some(index) => index,
+            None => continue,
+        };
+        let has_key = matches!(keys.asymmetric_key_mut(slot), Some(Some(_)));
+        if has_key && keys.metadata[index].is_none() {
+            keys.metadata[index] = Some(KeyMetadata {
+                algorithm: AsymmetricAlgorithm::Ed255,
+                pin_policy: slot.default_pin_policy(),
+                touch_policy: TouchPolicy::Never,
+                origin: KeyOrigin::Generated,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{Keys, ManagementKeyAlgorithm, RetiredSlotIndex};
+    use crate::{Pin, Puk};
+    use trussed::types::KeyId;
+
+    /// The unversioned layout, before key metadata, AES management keys, secure messaging
+    /// and on-card biometric comparison.
+    #[derive(serde::Serialize)]
+    struct KeysV0 {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        authentication_key: Option<KeyId>,
+        management_key: KeyId,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        signature_key: Option<KeyId>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        encryption_key: Option<KeyId>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pinless_authentication_key: Option<KeyId>,
+        retired_keys: [Option<KeyId>; 20],
+    }
+
+    #[derive(serde::Serialize)]
+    struct PersistentStateV0 {
+        keys: KeysV0,
+        consecutive_pin_mismatches: u8,
+        consecutive_puk_mismatches: u8,
+        pin: Pin,
+        puk: Puk,
+        timestamp: u32,
+        guid: [u8; 16],
+    }
+
+    const GUID: [u8; 16] = hex!("0102030405060708090a0b0c0d0e0f10");
+
+    // `persistent-state.cbor` in the layout of the baseline firmware (b63302a), as its
+    // Trussed (817a9e7, cbor-smol 0.4) encodes it: a map with the field names as keys,
+    // key IDs as 16 byte strings, arrays as arrays of integers.
+
+    /// The state of `v0_fixture`: keys in slots 9A and 83, PIN 12345678, PUK 87654321.
+    const BASELINE_STATE: [u8; 276] = hex!("
+        a7646b657973a37261757468656e7469636174696f6e5f6b6579500000000000
+        00000000000000000000016e6d616e6167656d656e745f6b6579500000000000
+        00000000000000000000026c726574697265645f6b65797394f6f65000000000
+        000000000000000000000003f6f6f6f6f6f6f6f6f6f6f6f6f6f6f6f6f6781a63
+        6f6e73656375746976655f70696e5f6d69736d61746368657301781a636f6e73
+        656375746976655f70756b5f6d69736d617463686573026370696ea26a706164
+        6465645f70696e8818311832183318341835183618371838636c656e08637075
+        6b88183818371836183518341833183218316974696d657374616d70182a6467
+        756964900102030405060708090a0b0c0d0e0f10
+    ");
+
+    /// A state as initialized: no asymmetric keys, default PIN and PUK.
+    const BASELINE_INITIAL_STATE: [u8; 239] = hex!("
+        a7646b657973a26e6d616e6167656d656e745f6b6579506b1f0e3a9c5d4e2f8a
+        7b6c5d4e3f2a1b6c726574697265645f6b65797394f6f6f6f6f6f6f6f6f6f6f6
+        f6f6f6f6f6f6f6f6f6781a636f6e73656375746976655f70696e5f6d69736d61
+        746368657300781a636f6e73656375746976655f70756b5f6d69736d61746368
+        6573006370696ea26a7061646465645f70696e88183118321833183418351836
+        18ff18ff636c656e066370756b88183118321833183418351836183718386974
+        696d657374616d7000646775696490183f184a182b18c118d0189e184b187f18
+        8a185c186e181d182f183a184b185c
+    ");
+
+    fn pin() -> Pin {
+        Pin::try_from(b"12345678".as_ref()).unwrap()
+    }
+
+    fn puk() -> Puk {
+        Puk::try_from(b"87654321".as_ref()).unwrap()
+    }
+
+    fn v0_fixture() -> Message {
+        let mut retired_keys: [Option<KeyId>; 20] = Default::default();
+        retired_keys[2] = Some(KeyId::from_special(3));
+        let state = PersistentStateV0 {
+            keys: KeysV0 {
+                authentication_key: Some(KeyId::from_special(1)),
+                management_key: KeyId::from_special(2),
+                signature_key: None,
+                encryption_key: None,
+                pinless_authentication_key: None,
+                retired_keys,
+            },
+            consecutive_pin_mismatches: 1,
+            consecutive_puk_mismatches: 2,
+            pin: pin(),
+            puk: puk(),
+            timestamp: 42,
+            guid: GUID,
+        };
+        trussed::cbor_serialize_bytes(&state).unwrap()
+    }
+
+    #[test]
+    fn baseline_layout() {
+        assert_eq!(v0_fixture().as_slice(), &BASELINE_STATE[..]);
+    }
+
+    #[test]
+    fn v0_keeps_keys() {
+        let (state, version) = deserialize(&BASELINE_STATE).unwrap();
+        assert_eq!(version, 0);
+
+        let keys = &state.keys;
+        assert_eq!(keys.management_key, KeyId::from_special(2));
+        assert_eq!(keys.management_key_algorithm, ManagementKeyAlgorithm::Tdes);
+        assert_eq!(keys.secure_messaging_key, None);
+
+        let legacy = |slot: SlotName| KeyMetadata {
+            algorithm: AsymmetricAlgorithm::Ed255,
+            pin_policy: slot.default_pin_policy(),
+            touch_policy: TouchPolicy::Never,
+            origin: KeyOrigin::Generated,
+        };
+        let retired = SlotName::Retired(RetiredSlotIndex::try_from(3).unwrap());
+        assert_eq!(keys.asymmetric_key(SlotName::Identity),
+            Some((KeyId::from_special(1), legacy(SlotName::Identity))));
+        assert_eq!(keys.asymmetric_key(retired), Some((KeyId::from_special(3), legacy(retired))));
+        assert_eq!(keys.asymmetric_key(SlotName::Signature), None);
+        assert_eq!(keys.metadata.iter().filter(|metadata| metadata.is_some()).count(), 2);
+
+        assert_eq!(state.consecutive_pin_mismatches, 1);
+        assert_eq!(state.consecutive_puk_mismatches, 2);
+        assert_eq!(state.pin, pin());
+        assert_eq!(state.puk, puk());
+        assert_eq!(state.timestamp, 42);
+        assert_eq!(state.guid, GUID);
+        assert_eq!(state.pairing_code, None);
+        assert_eq!(state.consecutive_occ_mismatches, 0);
+    }
+
+    #[test]
+    fn v0_initial_state() {
+        let (state, version) = deserialize(&BASELINE_INITIAL_STATE).unwrap();
+        assert_eq!(version, 0);
+
+        let keys = &state.keys;
+        let management_key: Message = trussed::cbor_serialize_bytes(&keys.management_key).unwrap();
+        assert_eq!(management_key.as_slice(), &hex!("50 6b1f0e3a9c5d4e2f8a7b6c5d4e3f2a1b"));
+        assert_eq!(keys.management_key_algorithm, ManagementKeyAlgorithm::Tdes);
+        assert_eq!(keys.authentication_key, None);
+        assert_eq!(keys.retired_keys, [None; 20]);
+        assert_eq!(keys.metadata, [None; 24]);
+
+        assert_eq!(state.consecutive_pin_mismatches, 0);
+        assert_eq!(state.consecutive_puk_mismatches, 0);
+        assert_eq!(state.pin, Pin::try_from(b"123456\xff\xff".as_ref()).unwrap());
+        assert_eq!(state.puk, Puk::try_from(b"12345678".as_ref()).unwrap());
+        assert_eq!(state.timestamp, 0);
+        assert_eq!(state.guid, hex!("3f4a2bc1d09e4b7f8a5c6e1d2f3a4b5c"));
+    }
+
+    #[test]
+    fn round_trip() {
+        for fixture in [&BASELINE_STATE[..], &BASELINE_INITIAL_STATE[..]].iter() {
+            let (state, _) = deserialize(fixture).unwrap();
+            let data = serialize(&state).unwrap();
+            assert_eq!(data[0], VERSION);
+            assert_eq!(deserialize(&data), Ok((state, VERSION)));
+        }
+    }
+
+    #[test]
+    fn current_version_round_trip() {
+        let mut metadata: [Option<KeyMetadata>; 24] = Default::default();
+        metadata[1] = Some(KeyMetadata {
+            algorithm: AsymmetricAlgorithm::P256,
+            pin_policy: crate::state::PinPolicy::Never,
+            touch_policy: TouchPolicy::Always,
+            origin: KeyOrigin::Imported,
+        });
+        let state = PersistentState {
+            keys: Keys {
+                authentication_key: None,
+                management_key: KeyId::from_special(2),
+                management_key_algorithm: ManagementKeyAlgorithm::Aes256,
+                signature_key: Some(KeyId::from_special(4)),
+                encryption_key: None,
+                pinless_authentication_key: None,
+                retired_keys: Default::default(),
+                metadata,
+                secure_messaging_key: Some(KeyId::from_special(5)),
+            },
+            consecutive_pin_mismatches: 0,
+            consecutive_puk_mismatches: 0,
+            pin: pin(),
+            puk: puk(),
+            timestamp: 7,
+            guid: GUID,
+            pairing_code: Some(*b"12345678"),
+            consecutive_occ_mismatches: 3,
+        };
+        let data = serialize(&state).unwrap();
+        assert_eq!(data[0], VERSION);
+        assert_eq!(deserialize(&data), Ok((state, VERSION)));
+    }
+
+    #[test]
+    fn refuse_unknown() {
+        assert_eq!(deserialize(&[]), Err(Error::Malformed));
+        assert_eq!(deserialize(&[0xff]), Err(Error::Malformed));
+        assert_eq!(deserialize(&[VERSION, 0xff]), Err(Error::Malformed));
+        assert_eq!(deserialize(&[VERSION + 1, 0xa0]), Err(Error::UnknownVersion(VERSION + 1)));
+    }
+}
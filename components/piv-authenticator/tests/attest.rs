@@ -0,0 +1,173 @@
+mod setup;
+
+use iso7816::Status::*;
+
+#[test]
+fn serial_is_derived_from_uuid() {
+    setup::piv(|piv| {
+        let mut response = iso7816::Data::<16>::default();
+        piv.respond(&cmd!("00 f8 00 00"), &mut response).unwrap();
+        assert_eq!(&[0x01, 0x02, 0x03, 0x04], response.as_slice());
+    });
+}
+
+#[test]
+fn attest_empty_slot() {
+    setup::piv(|piv| {
+        let mut response = iso7816::Data::<1024>::default();
+        assert_eq!(Err(KeyReferenceNotFound), piv.respond(&cmd!("00 f9 9a 00"), &mut response));
+        assert_eq!(Err(IncorrectP1OrP2Parameter), piv.respond(&cmd!("00 f9 42 00"), &mut response));
+    });
+}
+
+// the attestation key in slot F9, and its certificate
+const ATTESTATION_SCALAR: [u8; 32] = hex_literal::hex!("C9AFA9D845BA75166B5C215767B1D6934E50C3DB36E89B127B8A622B120F6721");
+const ATTESTATION_KEY_FILENAME: &[u8] = b"/test/sec/00";
+const ATTESTATION_CERT_FILENAME: &[u8] = b"/test/x5c/00";
+
+// 1.2.840.10045.4.3.2, 1.2.840.10045.2.1, 1.2.840.10045.3.1.7, 2.5.4.3
+const OID_ECDSA_WITH_SHA256: &[u8] = &hex_literal::hex!("2A8648CE3D040302");
+const OID_EC_PUBLIC_KEY: &[u8] = &hex_literal::hex!("2A8648CE3D0201");
+const OID_PRIME256V1: &[u8] = &hex_literal::hex!("2A8648CE3D030107");
+const OID_COMMON_NAME: &[u8] = &hex_literal::hex!("550403");
+// 1.3.6.1.4.1.41482.3.3, .3.7, .3.8
+const OID_YUBICO_FIRMWARE_VERSION: &[u8] = &hex_literal::hex!("2B0601040182C40A0303");
+const OID_YUBICO_SERIAL: &[u8] = &hex_literal::hex!("2B0601040182C40A0307");
+const OID_YUBICO_POLICY: &[u8] = &hex_literal::hex!("2B0601040182C40A0308");
+
+fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![tag];
+    match value.len() {
+        len @ 0..=0x7f => encoded.push(len as u8),
+        len @ 0x80..=0xff => encoded.extend_from_slice(&[0x81, len as u8]),
+        len => encoded.extend_from_slice(&[0x82, (len >> 8) as u8, len as u8]),
+    }
+    encoded.extend_from_slice(value);
+    encoded
+}
+
+fn name(common_name: &str) -> Vec<u8> {
+    let attribute = [tlv(0x06, OID_COMMON_NAME), tlv(0x0c, common_name.as_bytes())].concat();
+    tlv(0x30, &tlv(0x31, &tlv(0x30, &attribute)))
+}
+
+fn p256_public_key_info(point: &[u8]) -> Vec<u8> {
+    let algorithm = [tlv(0x06, OID_EC_PUBLIC_KEY), tlv(0x06, OID_PRIME256V1)].concat();
+    let public_key = [&[0u8][..], point].concat();
+    tlv(0x30, &[tlv(0x30, &algorithm), tlv(0x03, &public_key)].concat())
+}
+
+/// A self-signed certificate for the attestation key, as the provisioner would inject it.
+fn attestation_certificate(signing_key: &p256::ecdsa::SigningKey) -> Vec<u8> {
+    use p256::ecdsa::{signature::Signer, Signature, VerifyingKey};
+
+    let signature_algorithm = tlv(0x30, &tlv(0x06, OID_ECDSA_WITH_SHA256));
+    let point = VerifyingKey::from(signing_key).to_encoded_point(false);
+    let tbs_certificate = tlv(0x30, &[
+        hex_literal::hex!("A003 020102").to_vec(),
+        tlv(0x02, &[0x01]),
+        signature_algorithm.clone(),
+        name("Test PIV Attestation"),
+        tlv(0x30, &[tlv(0x17, b"210101000000Z"), tlv(0x17, b"491231235959Z")].concat()),
+        name("Test PIV Attestation"),
+        p256_public_key_info(point.as_bytes()),
+    ].concat());
+    let signature: Signature = signing_key.sign(&tbs_certificate);
+    let signature_value = [&[0u8][..], signature.to_der().as_bytes()].concat();
+    tlv(0x30, &[tbs_certificate, signature_algorithm, tlv(0x03, &signature_value)].concat())
+}
+
+/// The parts of an attestation certificate we check.
+struct Attestation<'a> {
+    tbs_certificate: &'a [u8],
+    issuer: &'a [u8],
+    subject_public_key_info: &'a [u8],
+    // (OID, value) of each extension
+    extensions: Vec<(&'a [u8], &'a [u8])>,
+    signature: &'a [u8],
+}
+
+fn parse(certificate: &[u8]) -> Attestation<'_> {
+    use piv_authenticator::derp;
+
+    derp::Input::from(certificate).read_all(derp::Error::Read, |input| {
+        derp::nested(input, 0x30, |certificate| {
+            let (tbs_certificate, (issuer, subject_public_key_info, extensions)) = certificate.read_partial(|tbs| {
+                derp::nested(tbs, 0x30, |tbs| {
+                    derp::expect_tag_and_value(tbs, 0xa0, &hex_literal::hex!("020102"))?;
+                    derp::expect_tag_and_get_value(tbs, 0x02)?;
+                    derp::expect_tag_and_value(tbs, 0x30, &tlv(0x06, OID_ECDSA_WITH_SHA256))?;
+                    let issuer = tbs.read_partial(|issuer| derp::expect_tag_and_get_value(issuer, 0x30))?.0;
+                    derp::expect_tag_and_get_value(tbs, 0x30)?;
+                    derp::expect_tag_and_get_value(tbs, 0x30)?;
+                    let subject_public_key_info = tbs.read_partial(|spki| derp::expect_tag_and_get_value(spki, 0x30))?.0;
+                    let extensions = derp::nested(tbs, 0xa3, |explicit| derp::nested(explicit, 0x30, |sequence| {
+                        let mut extensions = Vec::new();
+                        while !sequence.at_end() {
+                            extensions.push(derp::nested(sequence, 0x30, |extension| {
+                                let oid = derp::expect_tag_and_get_value(extension, 0x06)?;
+                                let value = derp::expect_tag_and_get_value(extension, 0x04)?;
+                                Ok((oid.as_slice_less_safe(), value.as_slice_less_safe()))
+                            })?);
+                        }
+                        Ok(extensions)
+                    }))?;
+                    Ok((issuer.as_slice_less_safe(), subject_public_key_info.as_slice_less_safe(), extensions))
+                })
+            })?;
+            derp::expect_tag_and_value(certificate, 0x30, &tlv(0x06, OID_ECDSA_WITH_SHA256))?;
+            let signature = derp::expect_tag_and_get_value(certificate, 0x03)?.as_slice_less_safe();
+            // no unused bits
+            let signature = signature.strip_prefix(&[0]).ok_or(derp::Error::WrongValue)?;
+            Ok(Attestation {
+                tbs_certificate: tbs_certificate.as_slice_less_safe(),
+                issuer,
+                subject_public_key_info,
+                extensions,
+                signature,
+            })
+        })
+    }).unwrap()
+}
+
+#[test]
+fn attest_generated_key() {
+    use p256::ecdsa::{signature::Verifier, Signature, SigningKey, VerifyingKey};
+
+    let signing_key = SigningKey::from_bytes(&ATTESTATION_SCALAR).unwrap();
+    let serialized_key = trussed::key::Key {
+        flags: trussed::key::Flags::LOCAL | trussed::key::Flags::SENSITIVE,
+        kind: trussed::key::Kind::P256,
+        material: heapless::Vec::from_slice(&ATTESTATION_SCALAR).unwrap(),
+    }.serialize();
+    let certificate = attestation_certificate(&signing_key);
+    let files: &[(&[u8], &[u8])] = &[
+        (ATTESTATION_KEY_FILENAME, &serialized_key[..]),
+        (ATTESTATION_CERT_FILENAME, &certificate[..]),
+    ];
+
+    setup::piv_with_files(files, |piv| piv, |piv| {
+        let mut response = iso7816::Data::<128>::default();
+        setup::authenticate_management(piv);
+        // PIN policy once, touch policy cached
+        piv.respond(&cmd!("00 47 00 9A 0B  AC 09  80 01 11  AA 01 02  AB 01 03"), &mut response).unwrap();
+        assert_eq!(&response[..6], &hex_literal::hex!("7F49 43 86 41 04"));
+        let point = response[5..].to_vec();
+
+        let mut response = iso7816::Data::<1024>::default();
+        piv.respond(&cmd!("00 f9 9a 00"), &mut response).unwrap();
+        let attestation = parse(&response);
+
+        assert_eq!(attestation.issuer, name("Test PIV Attestation").as_slice());
+        assert_eq!(attestation.subject_public_key_info, p256_public_key_info(&point).as_slice());
+        assert_eq!(attestation.extensions, vec![
+            (OID_YUBICO_FIRMWARE_VERSION, &[0x06, 0x06, 0x06][..]),
+            // the serial 0x01020304
+            (OID_YUBICO_SERIAL, &hex_literal::hex!("02 04 01020304")[..]),
+            (OID_YUBICO_POLICY, &[0x02, 0x03][..]),
+        ]);
+
+        let signature = Signature::from_der(attestation.signature).unwrap();
+        VerifyingKey::from(&signing_key).verify(attestation.tbs_certificate, &signature).unwrap();
+    });
+}
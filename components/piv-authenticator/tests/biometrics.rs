@@ -0,0 +1,101 @@
+mod setup;
+
+use iso7816::Status::*;
+use piv_authenticator::{Biometrics, Finger};
+
+/// A fingerprint reader with only the primary finger enrolled.
+struct Reader {
+    matches: bool,
+}
+
+impl Biometrics for Reader {
+    fn is_enrolled(&mut self, finger: Finger) -> bool {
+        finger == Finger::Primary
+    }
+
+    fn verify(&mut self, finger: Finger) -> bool {
+        finger == Finger::Primary && self.matches
+    }
+}
+
+fn reader(matches: bool) -> &'static mut Reader {
+    Box::leak(Box::new(Reader { matches }))
+}
+
+#[test]
+fn without_biometrics() {
+    setup::piv(|piv| {
+        let mut response = iso7816::Data::<64>::default();
+        assert_eq!(Err(KeyReferenceNotFound), piv.respond(&cmd!("00 20 00 96"), &mut response));
+        assert_eq!(Err(KeyReferenceNotFound), piv.respond(&cmd!("00 20 00 96 03  7F 2E 00"), &mut response));
+        assert_eq!(Err(NotFound), piv.respond(&cmd!("00 CB 3F FF 04  5C 02 7F 61"), &mut response));
+    });
+}
+
+#[test]
+fn biometric_information_templates() {
+    setup::piv_with(|piv| piv.with_biometrics(reader(true)), |piv| {
+        let mut response = iso7816::Data::<64>::default();
+        piv.respond(&cmd!("00 CB 3F FF 04  5C 02 7F 61"), &mut response).unwrap();
+        assert_eq!(&response[..], &hex_literal::hex!("7F61 16  020101
+            7F60 10  83 01 96  A1 0B 810108 87020101 88020007"));
+
+        // PIN usage policy: PIV PIN, OCC, VCI implemented, no pairing code required
+        response.clear();
+        piv.respond(&cmd!("00 CB 3F FF 03  5C 01 7E"), &mut response).unwrap();
+        assert_eq!(&response[response.len() - 4..], &hex_literal::hex!("5F2F 02 5C00"));
+
+        let get_fingerprints = cmd!("00 CB 3F FF 05  5C 03 5F C1 03");
+        assert_eq!(Err(SecurityStatusNotSatisfied), piv.respond(&get_fingerprints, &mut response));
+        piv.respond(&cmd!("00 20 00 80 08  31 32 33 34 35 36 FF FF"), &mut response).unwrap();
+        response.clear();
+        piv.respond(&get_fingerprints, &mut response).unwrap();
+        assert_eq!(&response[..], &hex_literal::hex!("53 04 BC 00 FE 00"));
+    });
+}
+
+#[test]
+fn match_satisfies_pin_once() {
+    setup::piv_with(|piv| piv.with_biometrics(reader(true)), |piv| {
+        let mut response = iso7816::Data::<128>::default();
+        setup::authenticate_management(piv);
+        // 9A defaults to PIN policy "once", 9C to "always"
+        piv.respond(&cmd!("00 47 00 9A 05  AC 03 80 01 E0"), &mut response).unwrap();
+        piv.respond(&cmd!("00 47 00 9C 05  AC 03 80 01 E0"), &mut response).unwrap();
+
+        let sign_9a = cmd!("00 87 E0 9A 0A  7C 08 82 00 81 04 01020304");
+        let sign_9c = cmd!("00 87 E0 9C 0A  7C 08 82 00 81 04 01020304");
+        assert_eq!(Err(SecurityStatusNotSatisfied), piv.respond(&sign_9a, &mut response));
+
+        assert_eq!(Err(RemainingRetries(5)), piv.respond(&cmd!("00 20 00 96"), &mut response));
+        // the secondary finger is not enrolled
+        assert_eq!(Err(KeyReferenceNotFound), piv.respond(&cmd!("00 20 00 97 03  7F 2E 00"), &mut response));
+        piv.respond(&cmd!("00 20 00 96 03  7F 2E 00"), &mut response).unwrap();
+        piv.respond(&cmd!("00 20 00 96"), &mut response).unwrap();
+
+        response.clear();
+        piv.respond(&sign_9a, &mut response).unwrap();
+        response.clear();
+        piv.respond(&sign_9a, &mut response).unwrap();
+        assert_eq!(Err(SecurityStatusNotSatisfied), piv.respond(&sign_9c, &mut response));
+
+        piv.respond(&cmd!("00 20 FF 96"), &mut response).unwrap();
+        assert_eq!(Err(SecurityStatusNotSatisfied), piv.respond(&sign_9a, &mut response));
+    });
+}
+
+#[test]
+fn mismatches_block() {
+    setup::piv_with(|piv| piv.with_biometrics(reader(false)), |piv| {
+        let mut response = iso7816::Data::<64>::default();
+        let verify_finger = cmd!("00 20 00 96 03  7F 2E 00");
+        for remaining in (0..5).rev() {
+            assert_eq!(Err(RemainingRetries(remaining)), piv.respond(&verify_finger, &mut response));
+        }
+        assert_eq!(Err(OperationBlocked), piv.respond(&verify_finger, &mut response));
+
+        // the PIN unblocks
+        piv.respond(&cmd!("00 20 00 80 08  31 32 33 34 35 36 FF FF"), &mut response).unwrap();
+        assert_eq!(Err(RemainingRetries(5)), piv.respond(&cmd!("00 20 00 96"), &mut response));
+    });
+}
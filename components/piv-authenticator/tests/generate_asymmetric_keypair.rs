@@ -15,11 +15,58 @@ use iso7816::Status::*;
 fn gen_keypair() {
     let cmd = cmd!("00 47 00 9A 0B  AC 09  80 01 11  AA 01 02  AB 01 02");
 
-    // without PIN, no key generation
+    // without management key, no key generation
     setup::piv(|piv| {
-        // not currently implemented
-        //
-        // let mut response = iso7816::Data::<16>::default();
-        // assert_eq!(Err(SecurityStatusNotSatisfied), piv.respond(&cmd, &mut response));
+        let mut response = iso7816::Data::<128>::default();
+        assert_eq!(Err(SecurityStatusNotSatisfied), piv.respond(&cmd, &mut response));
+
+        setup::authenticate_management(piv);
+        response.clear();
+        piv.respond(&cmd, &mut response).unwrap();
+        // uncompressed point
+        assert_eq!(&response[..6], &hex_literal::hex!("7F49 43 86 41 04"));
+        assert_eq!(response.len(), 5 + 65);
+    });
+}
+
+#[test]
+fn ed25519_signature() {
+    setup::piv(|piv| {
+        let mut response = iso7816::Data::<128>::default();
+        setup::authenticate_management(piv);
+
+        piv.respond(&cmd!("00 47 00 9C 05  AC 03 80 01 E0"), &mut response).unwrap();
+        assert_eq!(&response[..5], &hex_literal::hex!("7F49 22 86 20"));
+        assert_eq!(response.len(), 5 + 32);
+
+        // the signature slot has PIN policy "always"
+        let sign = cmd!("00 87 E0 9C 0A  7C 08 82 00 81 04 01020304");
+        let verify = cmd!("00 20 00 80 08  31 32 33 34 35 36 FF FF");
+        assert_eq!(Err(SecurityStatusNotSatisfied), piv.respond(&sign, &mut response));
+        piv.respond(&verify, &mut response).unwrap();
+        // wrong algorithm
+        assert_eq!(Err(IncorrectP1OrP2Parameter), piv.respond(&cmd!("00 87 11 9C 0A  7C 08 82 00 81 04 01020304"), &mut response));
+
+        response.clear();
+        piv.respond(&sign, &mut response).unwrap();
+        assert_eq!(&response[..4], &hex_literal::hex!("7C 42 82 40"));
+        assert_eq!(Err(SecurityStatusNotSatisfied), piv.respond(&sign, &mut response));
+    });
+}
+
+#[test]
+fn x25519_key_agreement() {
+    setup::piv(|piv| {
+        let mut response = iso7816::Data::<128>::default();
+        setup::authenticate_management(piv);
+
+        piv.respond(&cmd!("00 47 00 9D 08  AC 06 80 01 E1 AA 01 01"), &mut response).unwrap();
+        assert_eq!(&response[..5], &hex_literal::hex!("7F49 22 86 20"));
+
+        // PIN policy "never", the other party's public key is the base point
+        response.clear();
+        piv.respond(&cmd!("00 87 E1 9D 26  7C 24 82 00 85 20
+            0900000000000000 0000000000000000 0000000000000000 0000000000000000"), &mut response).unwrap();
+        assert_eq!(&response[..4], &hex_literal::hex!("7C 22 82 20"));
     });
 }
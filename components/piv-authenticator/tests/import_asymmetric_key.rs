@@ -0,0 +1,130 @@
+mod setup;
+
+use core::convert::TryFrom;
+use iso7816::Status::*;
+
+const P256_SCALAR: [u8; 32] = hex_literal::hex!("C9AFA9D845BA75166B5C215767B1D6934E50C3DB36E89B127B8A622B120F6721");
+// RFC 8032, 7.1, test 1
+const ED25519_SEED: [u8; 32] = hex_literal::hex!("9D61B19DEFFD5A60BA844AF492EC2CC44449C5697B326919703BAC031CAE7F60");
+const ED25519_PUBLIC_KEY: [u8; 32] = hex_literal::hex!("D75A980182B10AB7D54BFED3C964073A0EE172F3DAA62325AF021A68F707511A");
+
+fn command(header: [u8; 4], data: &[u8]) -> iso7816::Command<3072> {
+    let mut apdu = header.to_vec();
+    apdu.push(data.len() as u8);
+    apdu.extend_from_slice(data);
+    iso7816::Command::try_from(apdu.as_slice()).unwrap()
+}
+
+/// GENERAL AUTHENTICATE with a challenge, returning the response.
+fn sign(piv: &mut setup::Piv, algorithm: u8, slot: u8, challenge: &[u8]) -> Vec<u8> {
+    let mut data = vec![0x7c, 4 + challenge.len() as u8, 0x82, 0x00, 0x81, challenge.len() as u8];
+    data.extend_from_slice(challenge);
+    let mut response = iso7816::Data::<128>::default();
+    piv.respond(&command([0x00, 0x87, algorithm, slot], &data), &mut response).unwrap();
+    assert_eq!(&[0x7c, response[1], 0x82, response[3]], &response[..4]);
+    response[4..].to_vec()
+}
+
+// example: 00 FE 11 9A 28
+//   # P256 private scalar
+//   06 20 <32 bytes>
+//   # PinPolicy, 0x2 = once
+//   AA 01 02
+//   # TouchPolicy, 0x1 = never
+//   AB 01 01
+
+#[test]
+fn import_p256_requires_management_key() {
+    let cmd = cmd!("00 FE 11 9A 28
+        06 20 0102030405060708 0102030405060708 0102030405060708 0102030405060708
+        AA 01 02  AB 01 01");
+
+    setup::piv(|piv| {
+        let mut response = iso7816::Data::<16>::default();
+        assert_eq!(Err(SecurityStatusNotSatisfied), piv.respond(&cmd, &mut response));
+    });
+}
+
+#[test]
+fn import_malformed() {
+    setup::piv(|piv| {
+        let mut response = iso7816::Data::<16>::default();
+
+        // scalar too short
+        let cmd = cmd!("00 FE 11 9A 05  06 03 010203");
+        assert_eq!(Err(IncorrectDataParameter), piv.respond(&cmd, &mut response));
+
+        // Ed25519 seed for a P256 key
+        let cmd = cmd!("00 FE 11 9A 22
+            07 20 0102030405060708 0102030405060708 0102030405060708 0102030405060708");
+        assert_eq!(Err(IncorrectDataParameter), piv.respond(&cmd, &mut response));
+
+        // invalid PIN policy
+        let cmd = cmd!("00 FE E0 9A 25
+            07 20 0102030405060708 0102030405060708 0102030405060708 0102030405060708
+            AA 01 07");
+        assert_eq!(Err(IncorrectDataParameter), piv.respond(&cmd, &mut response));
+
+        // not an asymmetric slot
+        let cmd = cmd!("00 FE E0 9B 22
+            07 20 0102030405060708 0102030405060708 0102030405060708 0102030405060708");
+        assert_eq!(Err(KeyReferenceNotFound), piv.respond(&cmd, &mut response));
+    });
+}
+
+#[test]
+fn import_p256_and_sign() {
+    use p256::ecdsa::{signature::Verifier, Signature, SigningKey, VerifyingKey};
+    use sha2::Digest;
+
+    setup::piv(|piv| {
+        let mut response = iso7816::Data::<128>::default();
+        setup::authenticate_management(piv);
+
+        // PIN policy never, touch policy always
+        let mut data = vec![0x06, 0x20];
+        data.extend_from_slice(&P256_SCALAR);
+        data.extend_from_slice(&hex_literal::hex!("AA 01 01  AB 01 02"));
+        piv.respond(&command([0x00, 0xfe, 0x11, 0x9a], &data), &mut response).unwrap();
+
+        let verifying_key = VerifyingKey::from(&SigningKey::from_bytes(&P256_SCALAR).unwrap());
+        let public_key = verifying_key.to_encoded_point(false);
+
+        response.clear();
+        piv.respond(&cmd!("00 F7 00 9A"), &mut response).unwrap();
+        // imported, with the requested policies
+        assert_eq!(&response[..14], &hex_literal::hex!("01 01 11  02 02 01 02  03 01 02  04 43 86 41"));
+        assert_eq!(&response[14..], public_key.as_bytes());
+
+        // the client hashes
+        let message = b"imported P-256 key";
+        let signature = sign(piv, 0x11, 0x9a, &sha2::Sha256::digest(message));
+        let signature = Signature::from_der(&signature).unwrap();
+        verifying_key.verify(message, &signature).unwrap();
+    });
+}
+
+#[test]
+fn import_ed25519_and_sign() {
+    use ed25519_dalek::{PublicKey, Signature, Verifier};
+
+    setup::piv(|piv| {
+        let mut response = iso7816::Data::<128>::default();
+        setup::authenticate_management(piv);
+
+        let mut data = vec![0x07, 0x20];
+        data.extend_from_slice(&ED25519_SEED);
+        data.extend_from_slice(&hex_literal::hex!("AA 01 01"));
+        piv.respond(&command([0x00, 0xfe, 0xe0, 0x9e], &data), &mut response).unwrap();
+
+        response.clear();
+        piv.respond(&cmd!("00 F7 00 9E"), &mut response).unwrap();
+        assert_eq!(&response[..14], &hex_literal::hex!("01 01 E0  02 02 01 01  03 01 02  04 22 86 20"));
+        assert_eq!(&response[14..], &ED25519_PUBLIC_KEY);
+
+        let message = b"imported Ed25519 key";
+        let signature = sign(piv, 0xe0, 0x9e, message);
+        let signature = Signature::try_from(signature.as_slice()).unwrap();
+        PublicKey::from_bytes(&ED25519_PUBLIC_KEY).unwrap().verify(message, &signature).unwrap();
+    });
+}
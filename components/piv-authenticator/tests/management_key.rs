@@ -0,0 +1,106 @@
+mod setup;
+
+use core::convert::TryFrom;
+use iso7816::Status::*;
+
+fn command(header: [u8; 4], data: &[u8]) -> iso7816::Command<3072> {
+    let mut apdu = header.to_vec();
+    apdu.push(data.len() as u8);
+    apdu.extend_from_slice(data);
+    iso7816::Command::try_from(apdu.as_slice()).unwrap()
+}
+
+fn crypt<C>(key: &[u8], block: &[u8], encrypt: bool) -> Vec<u8>
+where
+    C: aes::NewBlockCipher + aes::BlockEncrypt + aes::BlockDecrypt,
+{
+    let cipher = C::new_from_slice(key).unwrap();
+    let mut block = aes::cipher::generic_array::GenericArray::clone_from_slice(block);
+    if encrypt {
+        cipher.encrypt_block(&mut block);
+    } else {
+        cipher.decrypt_block(&mut block);
+    }
+    block.to_vec()
+}
+
+fn aes(key: &[u8], block: &[u8], encrypt: bool) -> Vec<u8> {
+    match key.len() {
+        16 => crypt::<aes::Aes128>(key, block, encrypt),
+        24 => crypt::<aes::Aes192>(key, block, encrypt),
+        _ => crypt::<aes::Aes256>(key, block, encrypt),
+    }
+}
+
+/// Set an AES management key, then authenticate with it, mutually as ykman does.
+fn aes_round_trip(algorithm: u8, key: &[u8]) {
+    setup::piv(|piv| {
+        let mut response = iso7816::Data::<64>::default();
+        setup::authenticate_management(piv);
+        let mut data = vec![algorithm, 0x9b, key.len() as u8];
+        data.extend_from_slice(key);
+        piv.respond(&command([0x00, 0xff, 0xff, 0xff], &data), &mut response).unwrap();
+
+        // the 3DES key is gone
+        assert_eq!(Err(IncorrectP1OrP2Parameter), piv.respond(&cmd!("00 87 03 9B 04  7C 02 80 00"), &mut response));
+
+        response.clear();
+        piv.respond(&command([0x00, 0x87, algorithm, 0x9b], &hex_literal::hex!("7C 02 80 00")), &mut response).unwrap();
+        assert_eq!(&response[..4], &hex_literal::hex!("7C 12 80 10"));
+        let witness = aes(key, &response[4..], false);
+
+        let challenge = hex_literal::hex!("000102030405060708090A0B0C0D0E0F");
+        let mut data = hex_literal::hex!("7C 24 80 10").to_vec();
+        data.extend_from_slice(&witness);
+        data.extend_from_slice(&hex_literal::hex!("81 10"));
+        data.extend_from_slice(&challenge);
+        response.clear();
+        piv.respond(&command([0x00, 0x87, algorithm, 0x9b], &data), &mut response).unwrap();
+        assert_eq!(&response[..4], &hex_literal::hex!("7C 12 82 10"));
+        assert_eq!(&response[4..], aes(key, &challenge, true).as_slice());
+
+        // authenticated
+        response.clear();
+        piv.respond(&cmd!("00 47 00 9A 05  AC 03 80 01 E0"), &mut response).unwrap();
+    });
+}
+
+#[test]
+fn witness_for_default_3des_key() {
+    setup::piv(|piv| {
+        let mut response = iso7816::Data::<64>::default();
+
+        // AES-192 request while the default key is 3DES
+        assert_eq!(Err(IncorrectP1OrP2Parameter), piv.respond(&cmd!("00 87 0A 9B 04  7C 02 80 00"), &mut response));
+
+        piv.respond(&cmd!("00 87 03 9B 04  7C 02 80 00"), &mut response).unwrap();
+        // 7C 0A 80 08 <encrypted challenge>
+        assert_eq!(&[0x7c, 0x0a, 0x80, 0x08], &response[..4]);
+        assert_eq!(12, response.len());
+    });
+}
+
+#[test]
+fn set_management_key_requires_authentication() {
+    setup::piv(|piv| {
+        let mut response = iso7816::Data::<16>::default();
+        let cmd = cmd!("00 FF FF FF 1B  0A 9B 18
+            0102030405060708 0102030405060708 0102030405060708");
+        assert_eq!(Err(SecurityStatusNotSatisfied), piv.respond(&cmd, &mut response));
+    });
+}
+
+#[test]
+fn aes128_round_trip() {
+    aes_round_trip(0x08, &hex_literal::hex!("0102030405060708 090A0B0C0D0E0F10"));
+}
+
+#[test]
+fn aes192_round_trip() {
+    aes_round_trip(0x0a, &hex_literal::hex!("0102030405060708 090A0B0C0D0E0F10 1112131415161718"));
+}
+
+#[test]
+fn aes256_round_trip() {
+    aes_round_trip(0x0c, &hex_literal::hex!("0102030405060708 090A0B0C0D0E0F10 1112131415161718 191A1B1C1D1E1F20"));
+}
@@ -0,0 +1,98 @@
+mod setup;
+
+use iso7816::Status::*;
+
+#[test]
+fn pin_puk_and_management_key_metadata() {
+    setup::piv(|piv| {
+        let mut response = iso7816::Data::<128>::default();
+
+        piv.respond(&cmd!("00 F7 00 80"), &mut response).unwrap();
+        assert_eq!(response.as_slice(), &hex_literal::hex!("01 01 FF  05 01 01  06 02 03 03"));
+
+        // a wrong PUK
+        response.clear();
+        piv.respond(&cmd!("00 2C 00 80 10  38 37 36 35 34 33 32 31  36 35 34 33 32 31 FF FF"), &mut response).ok();
+        piv.respond(&cmd!("00 F7 00 81"), &mut response).unwrap();
+        assert_eq!(response.as_slice(), &hex_literal::hex!("01 01 FF  05 01 01  06 02 05 04"));
+
+        response.clear();
+        piv.respond(&cmd!("00 F7 00 9B"), &mut response).unwrap();
+        assert_eq!(response.as_slice(), &hex_literal::hex!("01 01 03  02 02 00 01  05 01 01"));
+    });
+}
+
+#[test]
+fn key_metadata_move_and_delete() {
+    setup::piv(|piv| {
+        let mut response = iso7816::Data::<128>::default();
+        assert_eq!(Err(KeyReferenceNotFound), piv.respond(&cmd!("00 F7 00 9A"), &mut response));
+
+        setup::authenticate_management(piv);
+        piv.respond(&cmd!("00 47 00 9A 05  AC 03 80 01 E0"), &mut response).unwrap();
+        let public_key = response[5..].to_vec();
+
+        response.clear();
+        piv.respond(&cmd!("00 F7 00 9A"), &mut response).unwrap();
+        assert_eq!(&response[..14], &hex_literal::hex!("01 01 E0  02 02 02 01  03 01 01  04 22 86 20"));
+        assert_eq!(&response[14..], public_key.as_slice());
+
+        // move to retired slot 1, which must be empty
+        piv.respond(&cmd!("00 F6 82 9A"), &mut response).unwrap();
+        assert_eq!(Err(KeyReferenceNotFound), piv.respond(&cmd!("00 F7 00 9A"), &mut response));
+        assert_eq!(Err(KeyReferenceNotFound), piv.respond(&cmd!("00 F6 83 9A"), &mut response));
+        response.clear();
+        piv.respond(&cmd!("00 F7 00 82"), &mut response).unwrap();
+        assert_eq!(&response[14..], public_key.as_slice());
+
+        piv.respond(&cmd!("00 47 00 9A 05  AC 03 80 01 E0"), &mut response).unwrap();
+        assert_eq!(Err(ConditionsOfUseNotSatisfied), piv.respond(&cmd!("00 F6 82 9A"), &mut response));
+
+        // delete
+        piv.respond(&cmd!("00 F6 FF 82"), &mut response).unwrap();
+        assert_eq!(Err(KeyReferenceNotFound), piv.respond(&cmd!("00 F7 00 82"), &mut response));
+    });
+}
+
+#[test]
+fn aes_management_key_metadata() {
+    setup::piv(|piv| {
+        let mut response = iso7816::Data::<128>::default();
+        setup::authenticate_management(piv);
+
+        // AES-256, the default key does not apply
+        piv.respond(&cmd!("00 FF FF FF 23  0C 9B 20
+            0102030405060708 0102030405060708 0102030405060708 0102030405060708"), &mut response).unwrap();
+        response.clear();
+        piv.respond(&cmd!("00 F7 00 9B"), &mut response).unwrap();
+        assert_eq!(response.as_slice(), &hex_literal::hex!("01 01 0C  02 02 00 01  05 01 00"));
+
+        // AES-192 with the bytes of the default key
+        piv.respond(&cmd!("00 FF FF FF 1B  0A 9B 18
+            0102030405060708 0102030405060708 0102030405060708"), &mut response).unwrap();
+        response.clear();
+        piv.respond(&cmd!("00 F7 00 9B"), &mut response).unwrap();
+        assert_eq!(response.as_slice(), &hex_literal::hex!("01 01 0A  02 02 00 01  05 01 01"));
+    });
+}
+
+#[test]
+fn move_key_slots() {
+    setup::piv(|piv| {
+        let mut response = iso7816::Data::<128>::default();
+        assert_eq!(Err(SecurityStatusNotSatisfied), piv.respond(&cmd!("00 F6 82 9A"), &mut response));
+
+        setup::authenticate_management(piv);
+        assert_eq!(Err(KeyReferenceNotFound), piv.respond(&cmd!("00 F6 82 9A"), &mut response));
+        piv.respond(&cmd!("00 47 00 9A 05  AC 03 80 01 E0"), &mut response).unwrap();
+
+        // the management key and attestation slots hold no asymmetric key to move
+        assert_eq!(Err(IncorrectP1OrP2Parameter), piv.respond(&cmd!("00 F6 9B 9A"), &mut response));
+        assert_eq!(Err(IncorrectP1OrP2Parameter), piv.respond(&cmd!("00 F6 82 9B"), &mut response));
+        assert_eq!(Err(IncorrectP1OrP2Parameter), piv.respond(&cmd!("00 F6 FF F9"), &mut response));
+        assert_eq!(Err(IncorrectP1OrP2Parameter), piv.respond(&cmd!("00 F6 9A 9A"), &mut response));
+
+        response.clear();
+        piv.respond(&cmd!("00 F7 00 9A"), &mut response).unwrap();
+    });
+}
@@ -0,0 +1,59 @@
+mod setup;
+
+use core::convert::TryFrom;
+use iso7816::Status::*;
+
+fn block_pin_and_puk(piv: &mut setup::Piv) {
+    let mut response = iso7816::Data::<16>::default();
+    let wrong_pin = cmd!("00 20 00 80 08  39 39 39 39 39 39 FF FF");
+    for remaining in (0..3).rev() {
+        assert_eq!(Err(RemainingRetries(remaining)), piv.respond(&wrong_pin, &mut response));
+    }
+    let wrong_puk = cmd!("00 2C 00 80 10  38 37 36 35 34 33 32 31  36 35 34 33 32 31 FF FF");
+    for remaining in (0..5).rev() {
+        assert_eq!(Err(RemainingRetries(remaining)), piv.respond(&wrong_puk, &mut response));
+    }
+}
+
+#[test]
+fn reset_deletes_keys() {
+    setup::piv(|piv| {
+        let mut response = iso7816::Data::<128>::default();
+        setup::authenticate_management(piv);
+        piv.respond(&cmd!("00 47 00 9A 05  AC 03 80 01 E0"), &mut response).unwrap();
+        piv.respond(&cmd!("00 F6 82 9A"), &mut response).unwrap();
+        piv.respond(&cmd!("00 47 00 9C 05  AC 03 80 01 E0"), &mut response).unwrap();
+
+        block_pin_and_puk(piv);
+        piv.respond(&cmd!("00 FB 00 00"), &mut response).unwrap();
+
+        for slot in [0x9a, 0x9c, 0x82].iter() {
+            let get_metadata = iso7816::Command::<3072>::try_from(&[0x00, 0xf7, 0x00, *slot][..]).unwrap();
+            assert_eq!(Err(KeyReferenceNotFound), piv.respond(&get_metadata, &mut response));
+        }
+        // the default PIN again
+        piv.respond(&cmd!("00 20 00 80 08  31 32 33 34 35 36 FF FF"), &mut response).unwrap();
+    });
+}
+
+#[test]
+fn reset_requires_blocked_pin_and_puk() {
+    setup::piv(|piv| {
+        let mut response = iso7816::Data::<128>::default();
+        setup::authenticate_management(piv);
+        piv.respond(&cmd!("00 47 00 9A 05  AC 03 80 01 E0"), &mut response).unwrap();
+
+        let reset = cmd!("00 FB 00 00");
+        assert_eq!(Err(ConditionsOfUseNotSatisfied), piv.respond(&reset, &mut response));
+
+        // a blocked PIN alone is not enough
+        let wrong_pin = cmd!("00 20 00 80 08  39 39 39 39 39 39 FF FF");
+        for _ in 0..3 {
+            piv.respond(&wrong_pin, &mut response).ok();
+        }
+        assert_eq!(Err(ConditionsOfUseNotSatisfied), piv.respond(&reset, &mut response));
+
+        response.clear();
+        piv.respond(&cmd!("00 F7 00 9A"), &mut response).unwrap();
+    });
+}
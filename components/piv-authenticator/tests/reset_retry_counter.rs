@@ -0,0 +1,51 @@
+mod setup;
+
+use iso7816::Status::*;
+
+#[test]
+fn unblock_pin_with_puk() {
+    setup::piv(|piv| {
+        let mut response = iso7816::Data::<16>::default();
+
+        // block the PIN
+        let wrong_pin = cmd!("00 20 00 80 08  39 39 39 39 39 39 FF FF");
+        assert_eq!(Err(RemainingRetries(2)), piv.respond(&wrong_pin, &mut response));
+        assert_eq!(Err(RemainingRetries(1)), piv.respond(&wrong_pin, &mut response));
+        assert_eq!(Err(RemainingRetries(0)), piv.respond(&wrong_pin, &mut response));
+        let default_pin = cmd!("00 20 00 80 08  31 32 33 34 35 36 FF FF");
+        assert_eq!(Err(OperationBlocked), piv.respond(&default_pin, &mut response));
+
+        // wrong PUK, then the default PUK with new PIN "654321"
+        assert_eq!(Err(RemainingRetries(4)), piv.respond(
+            &cmd!("00 2C 00 80 10  38 37 36 35 34 33 32 31  36 35 34 33 32 31 FF FF"), &mut response));
+        piv.respond(&cmd!("00 2C 00 80 10  31 32 33 34 35 36 37 38  36 35 34 33 32 31 FF FF"), &mut response).unwrap();
+
+        assert_eq!(Err(RemainingRetries(2)), piv.respond(&default_pin, &mut response));
+        piv.respond(&cmd!("00 20 00 80 08  36 35 34 33 32 31 FF FF"), &mut response).unwrap();
+    });
+}
+
+#[test]
+fn block_puk() {
+    setup::piv(|piv| {
+        let mut response = iso7816::Data::<16>::default();
+
+        let wrong_puk = cmd!("00 2C 00 80 10  38 37 36 35 34 33 32 31  36 35 34 33 32 31 FF FF");
+        for remaining in (0..5).rev() {
+            assert_eq!(Err(RemainingRetries(remaining)), piv.respond(&wrong_puk, &mut response));
+        }
+        let default_puk = cmd!("00 2C 00 80 10  31 32 33 34 35 36 37 38  36 35 34 33 32 31 FF FF");
+        assert_eq!(Err(OperationBlocked), piv.respond(&default_puk, &mut response));
+    });
+}
+
+#[test]
+fn malformed_reset_retry_counter() {
+    setup::piv(|piv| {
+        let mut response = iso7816::Data::<16>::default();
+        assert_eq!(Err(IncorrectDataParameter), piv.respond(&cmd!("00 2C 00 80 08  31 32 33 34 35 36 37 38"), &mut response));
+        // new PIN too short
+        assert_eq!(Err(IncorrectDataParameter), piv.respond(
+            &cmd!("00 2C 00 80 10  31 32 33 34 35 36 37 38  31 32 33 FF FF FF FF FF"), &mut response));
+    });
+}
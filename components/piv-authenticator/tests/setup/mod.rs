@@ -6,6 +6,8 @@ trussed::platform!(Platform,
 
 const COMMAND_SIZE: usize = 3072;
 
+pub const UUID: [u8; 16] = hex_literal::hex!("01000000 00020000 00000300 00000004");
+
 #[macro_export]
 macro_rules! cmd {
     ($tt:tt) => { iso7816::Command::<3072>::try_from(&hex_literal::hex!($tt)).unwrap() }
@@ -15,16 +17,62 @@ pub type Piv<'service> = piv_authenticator::Authenticator<
     trussed::ClientImplementation<&'service mut trussed::service::Service<Platform>>, COMMAND_SIZE>;
 
 pub fn piv<R>(test: impl FnOnce(&mut Piv) -> R) -> R {
-    use trussed::Interchange as _;
+    piv_with(|piv| piv, test)
+}
+
+pub fn piv_with<R>(configure: impl FnOnce(Piv) -> Piv, test: impl FnOnce(&mut Piv) -> R) -> R {
+    piv_with_files(&[], configure, test)
+}
+
+/// Like `piv_with`, with files (path, contents) in internal storage, e.g. as written by the
+/// provisioner app, where the client ID is "test" instead of "piv".
+pub fn piv_with_files<R>(
+    files: &[(&[u8], &[u8])],
+    configure: impl FnOnce(Piv) -> Piv,
+    test: impl FnOnce(&mut Piv) -> R,
+) -> R {
+    use trussed::{platform::Platform as _, Interchange as _};
     unsafe { trussed::pipe::TrussedInterchange::reset_claims(); }
     let trussed_platform = init_platform();
+    for (path, contents) in files {
+        trussed::store::store(
+            trussed_platform.store(),
+            trussed::types::Location::Internal,
+            &trussed::types::PathBuf::from(*path),
+            contents,
+        ).unwrap();
+    }
     let mut trussed_service = trussed::service::Service::new(trussed_platform);
     let client_id = "test";
     let trussed_client = trussed_service.try_as_new_client(client_id).unwrap();
-    let mut piv_app = piv_authenticator::Authenticator::new(trussed_client);
+    let mut piv_app = configure(piv_authenticator::Authenticator::new(trussed_client, UUID));
     test(&mut piv_app)
 }
 
+/// Authenticate with the default management key, which is 3DES with three equal keys.
+pub fn authenticate_management(piv: &mut Piv) {
+    use core::convert::TryFrom;
+    use des::{BlockDecrypt, NewBlockCipher};
+
+    let mut response = iso7816::Data::<64>::default();
+    piv.respond(&cmd!("00 87 03 9B 04  7C 02 80 00"), &mut response).unwrap();
+    // 7C 0A 80 08 <encrypted witness>
+    let mut witness = des::cipher::generic_array::GenericArray::clone_from_slice(&response[4..12]);
+    des::TdesEde3::new_from_slice(&hex_literal::hex!("010203040506070801020304050607080102030405060708"))
+        .unwrap()
+        .decrypt_block(&mut witness);
+
+    let mut data = heapless::Vec::<u8, 32>::new();
+    data.extend_from_slice(&hex_literal::hex!("7C 14 80 08")).unwrap();
+    data.extend_from_slice(&witness).unwrap();
+    data.extend_from_slice(&hex_literal::hex!("81 08 0102030405060708")).unwrap();
+    let mut command = heapless::Vec::<u8, 32>::from_slice(&hex_literal::hex!("00 87 03 9B 16")).unwrap();
+    command.extend_from_slice(&data).unwrap();
+
+    response.clear();
+    piv.respond(&iso7816::Command::<COMMAND_SIZE>::try_from(command.as_slice()).unwrap(), &mut response).unwrap();
+}
+
 pub fn init_platform() -> Platform {
     let rng = rand_core::OsRng;
     let store = store::Store::format(
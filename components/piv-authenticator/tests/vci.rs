@@ -0,0 +1,53 @@
+mod setup;
+
+use iso7816::Interface::{Contact, Contactless};
+use iso7816::Status::*;
+
+#[test]
+fn contactless_without_vci() {
+    setup::piv(|piv| {
+        let mut response = iso7816::Data::<64>::default();
+
+        // free-read data objects are fine
+        piv.respond_over(Contactless, &cmd!("00 CB 3F FF 03  5C 01 7E"), &mut response).unwrap();
+        // PIN usage policy: PIV PIN, VCI implemented, no pairing code required
+        assert_eq!(&response[response.len() - 4..], &hex_literal::hex!("5F2F 02 4C00"));
+
+        // the PIN is not
+        let default_pin = cmd!("00 20 00 80 08  31 32 33 34 35 36 FF FF");
+        assert_eq!(Err(SecurityStatusNotSatisfied), piv.respond_over(Contactless, &default_pin, &mut response));
+        piv.respond_over(Contact, &default_pin, &mut response).unwrap();
+    });
+}
+
+#[test]
+fn secure_messaging_without_session() {
+    setup::piv(|piv| {
+        let mut response = iso7816::Data::<64>::default();
+
+        // no secure messaging key
+        assert_eq!(Err(KeyReferenceNotFound), piv.respond(
+            &cmd!("00 87 27 04 04  7C 02 82 00"), &mut response));
+        // no session
+        assert_eq!(Err(SecureMessagingNotSupported), piv.respond(
+            &cmd!("0C 20 00 80 0A  8E 08 00 00 00 00 00 00 00 00"), &mut response));
+        // the pairing code is only accepted over secure messaging
+        assert_eq!(Err(ConditionsOfUseNotSatisfied), piv.respond(
+            &cmd!("00 20 00 98 08  31 32 33 34 35 36 37 38"), &mut response));
+    });
+}
+
+#[test]
+fn pairing_code_requires_pin() {
+    setup::piv(|piv| {
+        let mut response = iso7816::Data::<64>::default();
+
+        let get_pairing_code = cmd!("00 CB 3F FF 05  5C 03 5F C1 23");
+        assert_eq!(Err(SecurityStatusNotSatisfied), piv.respond(&get_pairing_code, &mut response));
+        piv.respond(&cmd!("00 20 00 80 08  31 32 33 34 35 36 FF FF"), &mut response).unwrap();
+        response.clear();
+        piv.respond(&get_pairing_code, &mut response).unwrap();
+        assert_eq!(&response[..4], &hex_literal::hex!("53 0A 99 08"));
+        assert!(response[4..].iter().all(u8::is_ascii_digit));
+    });
+}
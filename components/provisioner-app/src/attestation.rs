@@ -0,0 +1,129 @@
+//! Checks on attestation certificates before they are saved.
+//!
+//! A certificate is only saved if it is for the key generated earlier, and if it is signed
+//! (Ed25519) by the T1 intermediate whose public key was saved with SaveT1IntermediatePublicKey.
+//!
+//! The FIDO batch certificate is not signed by T1, but if it has the FIDO AAGUID extension,
+//! it must agree with the AAGUID the authenticator reports.
+
+use apdu_dispatch::iso7816::Status;
+use core::convert::{TryFrom, TryInto};
+use littlefs2::path::PathBuf;
+use trussed::key::Key;
+use trussed::store::{self, Store};
+use trussed::types::{Location, Message};
+
+use crate::x509::{self, oid, Certificate};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Kind {
+    P256,
+    Ed255,
+    X255,
+}
+
+/// The raw key material stored at `path`: a seed, or the T1 public key.
+pub(crate) fn load_material<S: Store>(store: S, path: &[u8]) -> Result<[u8; 32], Status> {
+    let data: Message = store::read(store, Location::Internal, &PathBuf::from(path))
+        .map_err(|_| Status::ConditionsOfUseNotSatisfied)?;
+    let key = Key::try_deserialize(&data).map_err(|_| Status::UnspecifiedPersistentExecutionError)?;
+    key.material[..].try_into().map_err(|_| Status::UnspecifiedPersistentExecutionError)
+}
+
+/// Public key as it appears in a certificate's subjectPublicKey, i.e. SEC1 uncompressed for P-256.
+pub(crate) fn public_key(kind: Kind, seed: &[u8; 32]) -> heapless::Vec<u8, 65> {
+    let mut public = heapless::Vec::new();
+    match kind {
+        Kind::P256 => {
+            let keypair = nisty::Keypair::generate_patiently(seed);
+            public.push(0x04).ok();
+            public.extend_from_slice(keypair.public.as_bytes()).ok();
+        }
+        Kind::Ed255 => {
+            let keypair = salty::Keypair::from(seed);
+            public.extend_from_slice(keypair.public.as_bytes()).ok();
+        }
+        Kind::X255 => {
+            let secret = salty::agreement::SecretKey::from_seed(seed);
+            public.extend_from_slice(&salty::agreement::PublicKey::from(&secret).to_bytes()).ok();
+        }
+    }
+    public
+}
+
+fn has_algorithm(certificate: &Certificate, kind: Kind) -> bool {
+    match (kind, certificate.key_algorithm) {
+        (Kind::P256, (oid::EC_PUBLIC_KEY, Some(curve))) => {
+            x509::Reader::new(curve).expect(x509::tag::OID) == Ok(oid::PRIME256V1)
+        }
+        (Kind::Ed255, (oid::ED25519, None)) => true,
+        (Kind::X255, (oid::X25519, None)) => true,
+        _ => false,
+    }
+}
+
+/// Verifies `certificate` is for the key with `seed`.
+pub(crate) fn check_key(kind: Kind, seed: &[u8; 32], certificate: &Certificate) -> Result<(), Status> {
+    if !has_algorithm(certificate, kind) || certificate.public_key != &public_key(kind, seed)[..] {
+        info!("certificate is not for the generated key");
+        return Err(Status::IncorrectDataParameter);
+    }
+    Ok(())
+}
+
+/// Verifies `certificate` is signed by the T1 intermediate with public key `t1`.
+pub(crate) fn check_signature(t1: &[u8; 32], certificate: &Certificate) -> Result<(), Status> {
+    let t1 = salty::PublicKey::try_from(t1).map_err(|_| Status::IncorrectDataParameter)?;
+    let signature: &[u8; 64] = certificate.signature.try_into().map_err(|_| Status::IncorrectDataParameter)?;
+    if certificate.signature_algorithm != oid::ED25519
+        || t1.verify(certificate.tbs, &salty::Signature::from(signature)).is_err()
+    {
+        info!("certificate is not signed by the T1 intermediate");
+        return Err(Status::IncorrectDataParameter);
+    }
+    Ok(())
+}
+
+/// The saved FIDO AAGUID, if any.
+pub(crate) fn load_aaguid<S: Store>(store: S, path: &[u8]) -> Result<Option<[u8; 16]>, Status> {
+    let path = PathBuf::from(path);
+    if !path.exists(&store.ifs()) {
+        return Ok(None);
+    }
+    let data: Message = store::read(store, Location::Internal, &path)
+        .map_err(|_| Status::UnspecifiedPersistentExecutionError)?;
+    data[..].try_into().map(Some).map_err(|_| Status::UnspecifiedPersistentExecutionError)
+}
+
+/// The AAGUID in the FIDO extension (id-fido-gen-ce-aaguid) of `certificate`, if it has one.
+pub(crate) fn aaguid(certificate: &Certificate) -> Result<Option<[u8; 16]>, Status> {
+    let value = match certificate.extension(oid::FIDO_GEN_CE_AAGUID) {
+        Ok(Some(value)) => value,
+        Ok(None) => return Ok(None),
+        Err(()) => return Err(Status::IncorrectDataParameter),
+    };
+    let mut reader = x509::Reader::new(value);
+    let aaguid = reader.expect(x509::tag::OCTET_STRING).and_then(|aaguid| {
+        reader.finish()?;
+        aaguid.try_into().map_err(drop)
+    });
+    aaguid.map(Some).map_err(|_| Status::IncorrectDataParameter)
+}
+
+/// Verifies the AAGUID extension of `certificate`, if any, is `expected`.
+pub(crate) fn check_aaguid(certificate: &Certificate, expected: &[u8; 16]) -> Result<(), Status> {
+    match aaguid(certificate)? {
+        Some(aaguid) if aaguid != *expected => {
+            info!("certificate is for a different AAGUID");
+            Err(Status::IncorrectDataParameter)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Verifies `certificate` is for the key with `seed` and signed by the T1 intermediate.
+pub(crate) fn check<S: Store>(store: S, kind: Kind, seed: &[u8; 32], t1_path: &[u8], certificate: &[u8]) -> Result<(), Status> {
+    let certificate = Certificate::parse(certificate).map_err(|_| Status::IncorrectDataParameter)?;
+    check_key(kind, seed, &certificate)?;
+    check_signature(&load_material(store, t1_path)?, &certificate)
+}
@@ -4,7 +4,7 @@ pub fn uuid() -> [u8; 16] {
 	lpc55_hal::uuid()
 }
 
-pub fn boot_to_bootrom() {
+pub fn boot_to_bootrom() -> ! {
 	use lpc55_hal::traits::flash::WriteErase;
 	let flash = unsafe { lpc55_hal::peripherals::flash::Flash::steal() }.enabled(
 		&mut unsafe { lpc55_hal::peripherals::syscon::Syscon::steal()}
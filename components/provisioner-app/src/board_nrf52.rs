@@ -1,5 +1,11 @@
+//! Attestation material is kept in the internal filesystem like on the LPC55, never in the
+//! SE050, see "Not supported" in the nrf52840 runner's README.
+
 use nrf52840_pac;
 
+/// Bootloaders (Adafruit nRF52, UF2) stay in DFU mode if GPREGRET holds this after a reset.
+const DFU_MAGIC_UF2_RESET: u32 = 0x57;
+
 pub fn uuid() -> [u8; 16] {
 	let mut uuid: [u8; 16] = [0; 16];
 	let ficr = unsafe { nrf52840_pac::Peripherals::steal().FICR };
@@ -8,5 +14,30 @@ pub fn uuid() -> [u8; 16] {
 	uuid
 }
 
-pub fn boot_to_bootrom() {
+/// Also for the runner's firmware update reboot.
+///
+/// Without a bootloader, this is a plain reset.
+pub fn boot_to_bootrom() -> ! {
+	let power = unsafe { &*nrf52840_pac::POWER::ptr() };
+	power.gpregret.write(|w| unsafe { w.bits(DFU_MAGIC_UF2_RESET) });
+	nrf52840_pac::SCB::sys_reset()
+}
+
+/// Erases the running firmware's vector table, then boots to the bootloader.
+///
+/// The bootloader has no valid firmware to start until a new one is flashed, so an aborted
+/// update ends up back in DFU mode. Without a bootloader, only a debugger can flash one.
+pub fn boot_to_bootrom_destructive() -> ! {
+	const PAGE_SIZE: u32 = 4096;
+	let vector_table = unsafe { (*nrf52840_pac::SCB::ptr()).vtor.read() } & !(PAGE_SIZE - 1);
+	// first, so that a fault without the vector table also ends up in the bootloader
+	let power = unsafe { &*nrf52840_pac::POWER::ptr() };
+	power.gpregret.write(|w| unsafe { w.bits(DFU_MAGIC_UF2_RESET) });
+	let nvmc = unsafe { &*nrf52840_pac::NVMC::ptr() };
+	nvmc.config.write(|w| w.wen().een());
+	while nvmc.ready.read().ready().is_busy() {}
+	nvmc.erasepage().write(|w| unsafe { w.bits(vector_table) });
+	while nvmc.ready.read().ready().is_busy() {}
+	nvmc.config.write(|w| w.wen().ren());
+	nrf52840_pac::SCB::sys_reset()
 }
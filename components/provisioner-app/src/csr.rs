@@ -0,0 +1,169 @@
+//! PKCS#10 certification requests (RFC 2986) for freshly generated attestation keys.
+//!
+//! With P1 = 01, the Generate*Key instructions reply with a DER request instead of the raw
+//! public key. The subject is just the serialNumber, the device UUID as upper case hex.
+//!
+//! P-256 and Ed25519 requests are signed by the requested key itself. X25519 keys cannot sign,
+//! so for them the request is signed by the P-256 attestation key (ECDSA with SHA-256) as proof
+//! of possession: verify it with the public key of the P-256 request, generated before.
+
+use crate::attestation::Kind;
+use crate::x509::{self, encode, encode_bit_string, oid, tag, Der};
+
+pub(crate) enum Signer<'a> {
+    P256(&'a nisty::Keypair),
+    Ed255(&'a salty::Keypair),
+}
+
+fn hex(uuid: &[u8; 16]) -> [u8; 32] {
+    const DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+    let mut hex = [0u8; 32];
+    for (i, byte) in uuid.iter().enumerate() {
+        hex[2 * i] = DIGITS[(byte >> 4) as usize];
+        hex[2 * i + 1] = DIGITS[(byte & 0xf) as usize];
+    }
+    hex
+}
+
+fn key_info(kind: Kind, public_key: &[u8]) -> x509::Result<Der> {
+    let algorithm = match kind {
+        Kind::P256 => encode(tag::SEQUENCE, &[
+            &encode(tag::OID, &[oid::EC_PUBLIC_KEY])?,
+            &encode(tag::OID, &[oid::PRIME256V1])?,
+        ])?,
+        Kind::Ed255 => encode(tag::SEQUENCE, &[&encode(tag::OID, &[oid::ED25519])?])?,
+        Kind::X255 => encode(tag::SEQUENCE, &[&encode(tag::OID, &[oid::X25519])?])?,
+    };
+    encode(tag::SEQUENCE, &[&algorithm, &encode_bit_string(public_key)?])
+}
+
+/// `public_key` as in `attestation::public_key`.
+pub(crate) fn request(kind: Kind, public_key: &[u8], uuid: &[u8; 16], signer: Signer) -> x509::Result<Der> {
+    let serial_number = encode(tag::SEQUENCE, &[
+        &encode(tag::OID, &[oid::SERIAL_NUMBER])?,
+        &encode(tag::PRINTABLE_STRING, &[&hex(uuid)])?,
+    ])?;
+    let subject = encode(tag::SEQUENCE, &[&encode(tag::SET, &[&serial_number])?])?;
+
+    let info = encode(tag::SEQUENCE, &[
+        // version 1
+        &encode(tag::INTEGER, &[&[0]])?,
+        &subject,
+        &key_info(kind, public_key)?,
+        // no attributes
+        &encode(tag::CONTEXT_0, &[])?,
+    ])?;
+
+    let (algorithm, signature) = match signer {
+        Signer::P256(keypair) => {
+            let signature = keypair.sign(&info).to_asn1_der();
+            (oid::ECDSA_WITH_SHA256, encode_bit_string(&signature)?)
+        }
+        Signer::Ed255(keypair) => {
+            let signature = keypair.sign(&info).to_bytes();
+            (oid::ED25519, encode_bit_string(&signature)?)
+        }
+    };
+    let algorithm = encode(tag::SEQUENCE, &[&encode(tag::OID, &[algorithm])?])?;
+
+    encode(tag::SEQUENCE, &[&info, &algorithm, &signature])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attestation;
+    use crate::x509::Reader;
+    use hex_literal::hex;
+
+    const UUID: [u8; 16] = hex!("0123456789abcdef 0011223344556677");
+    const P256_SEED: [u8; 32] = [0x42; 32];
+    const ED25519_SEED: [u8; 32] = [0x17; 32];
+
+    struct Request<'a> {
+        /// The signed part, whole DER encoding
+        info: &'a [u8],
+        key_algorithm: (&'a [u8], Option<&'a [u8]>),
+        public_key: &'a [u8],
+        signature_algorithm: &'a [u8],
+        signature: &'a [u8],
+    }
+
+    /// Takes the CertificationRequest apart, checking the subject is just the serialNumber.
+    fn parse(der: &[u8]) -> Request {
+        let mut outer = Reader::new(der);
+        let mut request = Reader::new(outer.expect(tag::SEQUENCE).unwrap());
+        outer.finish().unwrap();
+        let info = request.expect_whole(tag::SEQUENCE).unwrap();
+        let (signature_algorithm, parameters) = request.algorithm().unwrap();
+        assert_eq!(parameters, None);
+        let signature = request.bit_string().unwrap();
+        request.finish().unwrap();
+
+        let mut fields = Reader::new(Reader::new(info).expect(tag::SEQUENCE).unwrap());
+        assert_eq!(fields.expect(tag::INTEGER), Ok(&[0][..]));
+        let mut subject = Reader::new(fields.expect(tag::SEQUENCE).unwrap());
+        let mut name = Reader::new(subject.expect(tag::SET).unwrap());
+        subject.finish().unwrap();
+        let mut attribute = Reader::new(name.expect(tag::SEQUENCE).unwrap());
+        name.finish().unwrap();
+        assert_eq!(attribute.expect(tag::OID), Ok(oid::SERIAL_NUMBER));
+        assert_eq!(attribute.expect(tag::PRINTABLE_STRING), Ok(&b"0123456789ABCDEF0011223344556677"[..]));
+        attribute.finish().unwrap();
+
+        let mut key_info = Reader::new(fields.expect(tag::SEQUENCE).unwrap());
+        let key_algorithm = key_info.algorithm().unwrap();
+        let public_key = key_info.bit_string().unwrap();
+        key_info.finish().unwrap();
+        // no attributes
+        assert_eq!(fields.expect(tag::CONTEXT_0), Ok(&[][..]));
+        fields.finish().unwrap();
+
+        Request { info, key_algorithm, public_key, signature_algorithm, signature }
+    }
+
+    fn verify_p256(public_key: &[u8], request: &Request) {
+        use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+        assert_eq!(request.signature_algorithm, oid::ECDSA_WITH_SHA256);
+        let signature = Signature::from_der(request.signature).unwrap();
+        VerifyingKey::from_sec1_bytes(public_key).unwrap().verify(request.info, &signature).unwrap();
+    }
+
+    #[test]
+    fn p256() {
+        let public_key = attestation::public_key(Kind::P256, &P256_SEED);
+        let keypair = nisty::Keypair::generate_patiently(&P256_SEED);
+        let der = request(Kind::P256, &public_key, &UUID, Signer::P256(&keypair)).unwrap();
+        let request = parse(&der);
+        assert_eq!(request.key_algorithm, (oid::EC_PUBLIC_KEY, Some(&hex!("06082a8648ce3d030107")[..])));
+        assert_eq!(request.public_key, &public_key[..]);
+        verify_p256(&public_key, &request);
+    }
+
+    #[test]
+    fn ed25519() {
+        use core::convert::TryFrom;
+        use ed25519_dalek::{PublicKey, Signature, Verifier};
+        let public_key = attestation::public_key(Kind::Ed255, &ED25519_SEED);
+        let keypair = salty::Keypair::from(&ED25519_SEED);
+        let der = request(Kind::Ed255, &public_key, &UUID, Signer::Ed255(&keypair)).unwrap();
+        let request = parse(&der);
+        assert_eq!(request.key_algorithm, (oid::ED25519, None));
+        assert_eq!(request.public_key, &public_key[..]);
+        assert_eq!(request.signature_algorithm, oid::ED25519);
+        let signature = Signature::try_from(request.signature).unwrap();
+        PublicKey::from_bytes(&public_key).unwrap().verify(request.info, &signature).unwrap();
+    }
+
+    #[test]
+    fn x25519_signed_by_p256() {
+        let public_key = attestation::public_key(Kind::X255, &ED25519_SEED);
+        let p256_public_key = attestation::public_key(Kind::P256, &P256_SEED);
+        let keypair = nisty::Keypair::generate_patiently(&P256_SEED);
+        let der = request(Kind::X255, &public_key, &UUID, Signer::P256(&keypair)).unwrap();
+        let request = parse(&der);
+        assert_eq!(request.key_algorithm, (oid::X25519, None));
+        assert_eq!(request.public_key, &public_key[..]);
+        verify_p256(&p256_public_key, &request);
+    }
+}
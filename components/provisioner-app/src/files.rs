@@ -0,0 +1,137 @@
+//! Read-back of provisioned files, so the factory line can verify what it wrote.
+//!
+//! Only certificates and public keys can be read, listed and hashed: a path must name a file
+//! directly in one of `READABLE`. Secret key files (`/attn/sec`, `/fido/sec`, ...) are never
+//! returned, whatever the path looks like.
+//!
+//! READ FILE takes the path as data and the offset as P1P2, and replies with as much of the
+//! file from there as fits; an empty reply means the offset is the end of the file.
+//! LIST DIRECTORY takes one of `READABLE` and replies per file with the name length (one byte),
+//! the name and the size (big endian `u32`). SHA-256 FILE replies with the file's hash.
+
+use apdu_dispatch::iso7816::Status;
+use apdu_dispatch::response;
+use littlefs2::fs::Filesystem;
+use littlefs2::io::{Read, Seek, SeekFrom};
+use littlefs2::path::PathBuf;
+use sha2::{Digest, Sha256};
+use trussed::types::LfsStorage;
+
+pub const READABLE: &[&str] = &[
+    "/attn/x5c",
+    "/attn/pub",
+    "/fido/x5c",
+    "/piv/x5c",
+];
+
+/// Longest file name accepted, our files are named like `00`.
+const MAX_NAME: usize = 32;
+
+fn is_name(name: &[u8]) -> bool {
+    !name.is_empty() && name.len() <= MAX_NAME && name != b"." && name != b".."
+        && name.iter().all(|c| c.is_ascii_alphanumeric() || b"._-".contains(c))
+}
+
+/// `path` if it is one of `READABLE`, a trailing slash is fine.
+pub fn readable_dir(path: &[u8]) -> Result<PathBuf, Status> {
+    let path = path.strip_suffix(b"/").unwrap_or(path);
+    READABLE.iter()
+        .find(|dir| dir.as_bytes() == path)
+        .map(|dir| PathBuf::from(*dir))
+        .ok_or(Status::SecurityStatusNotSatisfied)
+}
+
+/// `path` if it names a file directly in one of `READABLE`.
+pub fn readable_file(path: &[u8]) -> Result<PathBuf, Status> {
+    let split = path.iter().rposition(|&c| c == b'/').ok_or(Status::IncorrectDataParameter)?;
+    let (dir, name) = (&path[..split], &path[split + 1..]);
+    if !is_name(name) {
+        return Err(Status::IncorrectDataParameter);
+    }
+    readable_dir(dir)?;
+    Ok(PathBuf::from(path))
+}
+
+/// Appends as much of the file from `offset` on as fits into `reply`.
+pub fn read<S: LfsStorage>(
+    fs: &Filesystem<'static, S>,
+    path: &PathBuf,
+    offset: usize,
+    reply: &mut response::Data,
+) -> Result<(), Status> {
+    let start = reply.len();
+    reply.resize_default(reply.capacity()).ok();
+    let read = fs.open_file_and_then(path, |file| {
+        if offset > file.len()? {
+            return Ok(None);
+        }
+        file.seek(SeekFrom::Start(offset as u32))?;
+        let mut read = 0;
+        while start + read < reply.len() {
+            let chunk = file.read(&mut reply[start + read..])?;
+            if chunk == 0 {
+                break;
+            }
+            read += chunk;
+        }
+        Ok(Some(read))
+    });
+    let read = match read {
+        Ok(Some(read)) => read,
+        Ok(None) => {
+            reply.truncate(start);
+            return Err(Status::IncorrectP1OrP2Parameter);
+        }
+        Err(_) => {
+            reply.truncate(start);
+            return Err(Status::NotFound);
+        }
+    };
+    reply.truncate(start + read);
+    Ok(())
+}
+
+pub fn list<S: LfsStorage>(
+    fs: &Filesystem<'static, S>,
+    dir: &PathBuf,
+    reply: &mut response::Data,
+) -> Result<(), Status> {
+    let listed = fs.read_dir_and_then(dir, |entries| {
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let name: &str = entry.file_name().as_ref();
+            let size = entry.metadata().len() as u32;
+            if reply.push(name.len() as u8).is_err()
+                || reply.extend_from_slice(name.as_bytes()).is_err()
+                || reply.extend_from_slice(&size.to_be_bytes()).is_err()
+            {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    });
+    match listed {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(Status::NotEnoughMemory),
+        // not provisioned yet
+        Err(_) => Ok(()),
+    }
+}
+
+pub fn sha256<S: LfsStorage>(fs: &Filesystem<'static, S>, path: &PathBuf) -> Result<[u8; 32], Status> {
+    fs.open_file_and_then(path, |file| {
+        let mut hash = Sha256::new();
+        let mut buffer = [0u8; 128];
+        loop {
+            let chunk = file.read(&mut buffer)?;
+            if chunk == 0 {
+                break;
+            }
+            hash.update(&buffer[..chunk]);
+        }
+        Ok(hash.finalize().into())
+    }).map_err(|_| Status::NotFound)
+}
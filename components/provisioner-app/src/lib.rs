@@ -2,10 +2,14 @@
 //!
 //! This is a highly *non-portable* Trussed app.
 //!
-//! It allows injecting arbitrary binary files at arbitrary paths, e.g., to inject FIDO batch
-//! attestation keys.
+//! It allows injecting arbitrary binary files at arbitrary paths.
+//! The FIDO batch attestation key, certificate and AAGUID have their own instructions, which
+//! check that they fit together.
 //! It allows generating Trussed device attestation keys and obtaining their public keys,
 //! to then generate and inject attn certs from a given root or intermedidate CA.
+//! Certificates and public keys can be read back for verification, see `files`.
+//! All of this can also be done in one step, see `manifest`.
+//! Finally, the device is locked, see `lock`.
 //!
 //! See `solo2-cli` for usage.
 #![no_std]
@@ -14,11 +18,12 @@
 extern crate delog;
 generate_macros!();
 
-use core::convert::TryFrom;
+use core::convert::{TryFrom, TryInto};
 
 use trussed::types::LfsStorage;
 
 pub const FILESYSTEM_BOUNDARY: usize = 0x8_0000;
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 use littlefs2::path::{PathBuf};
 use trussed::store::{self, Store};
@@ -35,7 +40,14 @@ use apdu_dispatch::{Command, response, command::SIZE as CommandSize, response::S
 
 #[cfg_attr(feature = "lpc55", path = "board_lpc55.rs")]
 #[cfg_attr(feature = "nrf52", path = "board_nrf52.rs")]
-mod board;
+pub mod board;
+mod attestation;
+mod csr;
+pub mod files;
+pub mod lock;
+pub mod manifest;
+mod upload;
+pub mod x509;
 
 //
 const SOLO_PROVISIONER_AID: [u8; 9] = [ 0xA0, 0x00, 0x00, 0x08, 0x47, 0x01, 0x00, 0x00, 0x01];
@@ -43,6 +55,9 @@ const SOLO_PROVISIONER_AID: [u8; 9] = [ 0xA0, 0x00, 0x00, 0x08, 0x47, 0x01, 0x00
 const TESTER_FILENAME_ID: [u8; 2] = [0xe1,0x01];
 const TESTER_FILE_ID: [u8; 2] = [0xe1,0x02];
 
+/// P1 of the Generate*Key instructions to reply with a certification request.
+const REPLY_CSR: u8 = 0x01;
+
 #[repr(u8)]
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Instructions {
@@ -62,6 +77,20 @@ pub enum Instructions {
 
     SaveT1IntermediatePublicKey = 0xb5,
 
+    GeneratePivAttestationKey = 0xb4,
+    SavePivAttestationCertificate = 0xb3,
+
+    ReadFile = 0xb2,
+    ListDirectory = 0xb1,
+    Sha256File = 0xb0,
+
+    Lock = 0xaf,
+    Provision = 0xae,
+
+    SaveFidoAttestationKey = 0xad,
+    SaveFidoAttestationCertificate = 0xac,
+    SaveFidoAaguid = 0xab,
+
     #[cfg(feature = "test-attestation")]
     TestAttestation = 0xb8,
 }
@@ -87,6 +116,20 @@ impl TryFrom<u8> for Instructions {
 
             0xb5 => SaveT1IntermediatePublicKey,
 
+            0xb4 => GeneratePivAttestationKey,
+            0xb3 => SavePivAttestationCertificate,
+
+            0xb2 => ReadFile,
+            0xb1 => ListDirectory,
+            0xb0 => Sha256File,
+
+            0xaf => Lock,
+            0xae => Provision,
+
+            0xad => SaveFidoAttestationKey,
+            0xac => SaveFidoAttestationCertificate,
+            0xab => SaveFidoAaguid,
+
             #[cfg(feature = "test-attestation")]
             0xb8 => TestAttestation,
             _ => return Err(()),
@@ -94,6 +137,19 @@ impl TryFrom<u8> for Instructions {
     }
 }
 
+impl Instructions {
+    /// Refused once the device is locked, see `lock`.
+    fn is_mutating(&self) -> bool {
+        use Instructions::*;
+        match self {
+            GetUuid | BootToBootrom | ReadFile | ListDirectory | Sha256File => false,
+            #[cfg(feature = "test-attestation")]
+            TestAttestation => false,
+            _ => true,
+        }
+    }
+}
+
 #[cfg(feature = "test-attestation")]
 #[derive(Copy,Clone)]
 enum TestAttestationP1 {
@@ -116,6 +172,16 @@ const FILENAME_P256_CERT: &'static [u8] = b"/attn/x5c/01";
 const FILENAME_ED255_CERT: &'static [u8] = b"/attn/x5c/02";
 const FILENAME_X255_CERT: &'static [u8] = b"/attn/x5c/03";
 
+// PIV slot F9, `KeyId::from_special(0)` and `CertId::from_special(0)` of the PIV app
+const FILENAME_PIV_ATTESTATION_SECRET: &'static [u8] = b"/piv/sec/00";
+const FILENAME_PIV_ATTESTATION_CERT: &'static [u8] = b"/piv/x5c/00";
+
+// `ATTESTATION_KEY_ID` and `ATTESTATION_CERT_ID` of the FIDO app
+const FILENAME_FIDO_ATTESTATION_SECRET: &'static [u8] = b"/fido/sec/00";
+const FILENAME_FIDO_ATTESTATION_CERT: &'static [u8] = b"/fido/x5c/00";
+// the FIDO app's file `aaguid`, reported in getInfo
+const FILENAME_FIDO_AAGUID: &'static [u8] = b"/fido/dat/aaguid";
+
 
 
 enum SelectedBuffer {
@@ -170,15 +236,21 @@ where S: Store,
         match command.instruction() {
             Instruction::Select => self.select(command, reply),
             Instruction::WriteBinary => {
-                let _offset: u16 = ((command.p1 as u16) << 8) | command.p2 as u16;
+                if !lock::is_unlocked(self.store) {
+                    return Err(Status::ConditionsOfUseNotSatisfied);
+                }
+                let offset = ((command.p1 as usize) << 8) | command.p2 as usize;
                 match self.selected_buffer {
-                    SelectedBuffer::Filename => self.buffer_filename.extend_from_slice(command.data()).unwrap(),
-                    SelectedBuffer::File => self.buffer_file_contents.extend_from_slice(command.data()).unwrap(),
-                };
-                Ok(())
+                    SelectedBuffer::Filename => upload::write_at(&mut self.buffer_filename, offset, command.data()),
+                    SelectedBuffer::File => upload::write_at(&mut self.buffer_file_contents, offset, command.data()),
+                }
             }
             Instruction::Unknown(ins) => {
                 if let Ok(instruction) = Instructions::try_from(ins) {
+                    if instruction.is_mutating() && !lock::is_unlocked(self.store) {
+                        info!("locked, refusing {:?}", instruction);
+                        return Err(Status::ConditionsOfUseNotSatisfied);
+                    }
                     use Instructions::*;
                     match instruction {
                         ReformatFilesystem => {
@@ -191,6 +263,11 @@ where S: Store,
                         WriteFile => {
                             if self.buffer_file_contents.len() == 0 || self.buffer_filename.len() == 0 {
                                 Err(Status::IncorrectDataParameter)
+                            } else if let Err(status) = upload::verify(&self.buffer_file_contents, command.data()) {
+                                info!("file hash mismatch, discarding upload");
+                                self.buffer_file_contents.clear();
+                                self.buffer_filename.clear();
+                                Err(status)
                             } else {
                                 // self.buffer_filename.push(0);
                                 let _filename = unsafe{ core::str::from_utf8_unchecked(self.buffer_filename.as_slice()) };
@@ -237,10 +314,7 @@ where S: Store,
                             ).map_err(|_| Status::NotEnoughMemory)?;
                             info!("stored to {}", core::str::from_utf8(FILENAME_P256_SECRET).unwrap());
 
-                            let keypair = nisty::Keypair::generate_patiently(&seed);
-
-                            reply.extend_from_slice(keypair.public.as_bytes()).unwrap();
-                            Ok(())
+                            self.reply_public_key(command, attestation::Kind::P256, &seed, reply)
                         }
                         GenerateEd255Key => {
 
@@ -268,15 +342,16 @@ where S: Store,
                                 &serialized_bytes
                             ).map_err(|_| Status::NotEnoughMemory)?;
 
-                            let keypair = salty::Keypair::from(&seed);
-
-                            reply.extend_from_slice(keypair.public.as_bytes()).unwrap();
-                            Ok(())
+                            self.reply_public_key(command, attestation::Kind::Ed255, &seed, reply)
                         },
 
                         GenerateX255Key => {
 
                             info_now!("GenerateX255Key");
+                            // the request is signed by the P-256 key
+                            if command.p1 == REPLY_CSR && !PathBuf::from(FILENAME_P256_SECRET).exists(&self.store.ifs()) {
+                                return Err(Status::ConditionsOfUseNotSatisfied);
+                            }
                             let mut seed = [0u8; 32];
                             seed.copy_from_slice(
                                 &syscall!(self.trussed.random_bytes(32)).bytes.as_slice()
@@ -300,68 +375,63 @@ where S: Store,
                                 &serialized_bytes
                             ).map_err(|_| Status::NotEnoughMemory)?;
 
-                            let secret_key = salty::agreement::SecretKey::from_seed(&seed);
-                            let public_key = salty::agreement::PublicKey::from(&secret_key);
-
-                            reply.extend_from_slice(&public_key.to_bytes()).unwrap();
-                            Ok(())
+                            self.reply_public_key(command, attestation::Kind::X255, &seed, reply)
                         },
 
-                        SaveP256AttestationCertificate => {
-                            let secret_path = PathBuf::from(FILENAME_P256_SECRET);
+                        GeneratePivAttestationKey => {
+                            info!("GeneratePivAttestationKey");
+                            let mut seed = [0u8; 32];
+                            seed.copy_from_slice(
+                                &syscall!(self.trussed.random_bytes(32)).bytes.as_slice()
+                            );
+
+                            let serialized_key = Key {
+                                flags: Flags::LOCAL | Flags::SENSITIVE,
+                                kind: KeyKind::P256,
+                                material: Vec::from_slice(&seed).unwrap(),
+                            };
+
+                            let serialized_bytes = serialized_key.serialize();
+
+                            store::store(
+                                self.store,
+                                trussed::types::Location::Internal,
+                                &PathBuf::from(FILENAME_PIV_ATTESTATION_SECRET),
+                                &serialized_bytes
+                            ).map_err(|_| Status::NotEnoughMemory)?;
+
+                            self.reply_public_key(command, attestation::Kind::P256, &seed, reply)
+                        }
+
+                        SavePivAttestationCertificate => {
+                            let secret_path = PathBuf::from(FILENAME_PIV_ATTESTATION_SECRET);
                             if !secret_path.exists(&self.store.ifs()) {
                                 Err(Status::IncorrectDataParameter)
                             } else if command.data().len() < 100 {
                                 // Assuming certs will always be >100 bytes
                                 Err(Status::IncorrectDataParameter)
                             } else {
-                                info!("saving P256 CERT, {} bytes", command.data().len());
+                                info!("saving PIV ATTESTATION CERT, {} bytes", command.data().len());
                                 store::store(
                                     self.store,
                                     trussed::types::Location::Internal,
-                                    &PathBuf::from(FILENAME_P256_CERT),
+                                    &PathBuf::from(FILENAME_PIV_ATTESTATION_CERT),
                                     command.data()
                                 ).map_err(|_| Status::NotEnoughMemory)?;
                                 Ok(())
                             }
                         },
 
+                        SaveP256AttestationCertificate => {
+                            self.save_attestation_certificate(attestation::Kind::P256, FILENAME_P256_SECRET, FILENAME_P256_CERT, command.data())
+                        },
+
                         SaveEd255AttestationCertificate => {
-                            let secret_path = PathBuf::from(FILENAME_ED255_SECRET);
-                            if !secret_path.exists(&self.store.ifs()) {
-                                Err(Status::IncorrectDataParameter)
-                            } else if command.data().len() < 100 {
-                                // Assuming certs will always be >100 bytes
-                                Err(Status::IncorrectDataParameter)
-                            } else {
-                                info!("saving ED25519 CERT, {} bytes", command.data().len());
-                                store::store(
-                                    self.store,
-                                    trussed::types::Location::Internal,
-                                    &PathBuf::from(FILENAME_ED255_CERT),
-                                    command.data()
-                                ).map_err(|_| Status::NotEnoughMemory)?;
-                                Ok(())
-                            }
+                            self.save_attestation_certificate(attestation::Kind::Ed255, FILENAME_ED255_SECRET, FILENAME_ED255_CERT, command.data())
                         },
 
                         SaveX255AttestationCertificate => {
-                            let secret_path = PathBuf::from(FILENAME_X255_SECRET);
-                            if !secret_path.exists(&self.store.ifs()) {
-                                Err(Status::IncorrectDataParameter)
-                            } else if command.data().len() < 100 {
-                                // Assuming certs will always be >100 bytes
-                                Err(Status::IncorrectDataParameter)
-                            } else {
-                                info!("saving X25519 CERT, {} bytes", command.data().len());
-                                store::store(
-                                    self.store,
-                                    trussed::types::Location::Internal,
-                                    &PathBuf::from(FILENAME_X255_CERT),
-                                    command.data()
-                                ).map_err(|_| Status::NotEnoughMemory)?;
-                                Ok(())
-                            }
+                            self.save_attestation_certificate(attestation::Kind::X255, FILENAME_X255_SECRET, FILENAME_X255_CERT, command.data())
                         },
 
                         SaveT1IntermediatePublicKey => {
@@ -387,6 +457,58 @@ where S: Store,
                             }
                         },
 
+                        ReadFile => {
+                            let path = files::readable_file(command.data())?;
+                            let offset = ((command.p1 as usize) << 8) | command.p2 as usize;
+                            files::read::<S::I>(self.store.ifs(), &path, offset, reply)
+                        }
+
+                        ListDirectory => {
+                            let dir = files::readable_dir(command.data())?;
+                            files::list::<S::I>(self.store.ifs(), &dir, reply)
+                        }
+
+                        Sha256File => {
+                            let path = files::readable_file(command.data())?;
+                            let hash = files::sha256::<S::I>(self.store.ifs(), &path)?;
+                            reply.extend_from_slice(&hash).unwrap();
+                            Ok(())
+                        }
+
+                        Lock => {
+                            let digest = lock::lock(self.store)?;
+                            info!("locked");
+                            reply.extend_from_slice(&digest).unwrap();
+                            Ok(())
+                        }
+
+                        Provision => {
+                            let result = if command.data().is_empty() {
+                                manifest::provision(self.store, &mut self.trussed, &self.buffer_file_contents, reply)
+                            } else {
+                                manifest::provision(self.store, &mut self.trussed, command.data(), reply)
+                            };
+                            self.buffer_file_contents.clear();
+                            self.buffer_filename.clear();
+                            result
+                        }
+
+                        SaveFidoAttestationKey => {
+                            let key: [u8; 32] = command.data().try_into()
+                                .map_err(|_| Status::IncorrectDataParameter)?;
+                            self.save_fido_attestation_key(&key)
+                        }
+
+                        SaveFidoAttestationCertificate => {
+                            self.save_fido_attestation_certificate(command.data())
+                        }
+
+                        SaveFidoAaguid => {
+                            let aaguid: [u8; 16] = command.data().try_into()
+                                .map_err(|_| Status::IncorrectDataParameter)?;
+                            self.save_fido_aaguid(&aaguid)
+                        }
+
                         #[cfg(feature = "test-attestation")]
                         TestAttestation => {
                             // This is only exposed for development and testing.
@@ -512,10 +634,7 @@ where S: Store,
                             reply.extend_from_slice(&board::uuid()).unwrap();
                             Ok(())
                         },
-                        BootToBootrom => {
-                            board::boot_to_bootrom();
-                            Ok(())
-                        },
+                        BootToBootrom => board::boot_to_bootrom(),
 
                     }
                 } else {
@@ -526,6 +645,124 @@ where S: Store,
         }
     }
 
+    /// The public key of a just generated key, or with P1 = `REPLY_CSR` a request, see `csr`.
+    fn reply_public_key(&mut self, command: &Command, kind: attestation::Kind, seed: &[u8; 32], reply: &mut response::Data) -> ResponseResult {
+        use attestation::Kind;
+        let public_key = attestation::public_key(kind, seed);
+        if command.p1 != REPLY_CSR {
+            // P-256 keys as x and y, without the SEC1 prefix
+            let raw = if kind == Kind::P256 { &public_key[1..] } else { &public_key[..] };
+            reply.extend_from_slice(raw).unwrap();
+            return Ok(());
+        }
+
+        let uuid = board::uuid();
+        let request = match kind {
+            Kind::P256 => {
+                let keypair = nisty::Keypair::generate_patiently(seed);
+                csr::request(kind, &public_key, &uuid, csr::Signer::P256(&keypair))
+            }
+            Kind::Ed255 => {
+                let keypair = salty::Keypair::from(seed);
+                csr::request(kind, &public_key, &uuid, csr::Signer::Ed255(&keypair))
+            }
+            Kind::X255 => {
+                let p256_seed = attestation::load_material(self.store, FILENAME_P256_SECRET)?;
+                let keypair = nisty::Keypair::generate_patiently(&p256_seed);
+                csr::request(kind, &public_key, &uuid, csr::Signer::P256(&keypair))
+            }
+        }.map_err(|_| Status::NotEnoughMemory)?;
+        reply.extend_from_slice(&request).map_err(|_| Status::NotEnoughMemory)
+    }
+
+    fn save_attestation_certificate(
+        &mut self,
+        kind: attestation::Kind,
+        secret_path: &[u8],
+        certificate_path: &[u8],
+        certificate: &[u8],
+    ) -> ResponseResult {
+        if !PathBuf::from(secret_path).exists(&self.store.ifs()) {
+            return Err(Status::IncorrectDataParameter);
+        }
+        let seed = attestation::load_material(self.store, secret_path)?;
+        attestation::check(self.store, kind, &seed, FILENAME_T1_PUBLIC, certificate)?;
+
+        info!("saving {:?} certificate, {} bytes", kind, certificate.len());
+        store::store(
+            self.store,
+            trussed::types::Location::Internal,
+            &PathBuf::from(certificate_path),
+            certificate,
+        ).map_err(|_| Status::NotEnoughMemory)
+    }
+
+    /// A certificate for a previous key is removed, so the FIDO app never attests with a
+    /// certificate that does not match.
+    fn save_fido_attestation_key(&mut self, key: &[u8; 32]) -> ResponseResult {
+        let serialized_key = Key {
+            flags: Flags::LOCAL | Flags::SENSITIVE,
+            kind: KeyKind::P256,
+            material: Vec::from_slice(key).unwrap(),
+        };
+        store::store(
+            self.store,
+            trussed::types::Location::Internal,
+            &PathBuf::from(FILENAME_FIDO_ATTESTATION_SECRET),
+            &serialized_key.serialize(),
+        ).map_err(|_| Status::NotEnoughMemory)?;
+
+        let certificate_path = PathBuf::from(FILENAME_FIDO_ATTESTATION_CERT);
+        if certificate_path.exists(&self.store.ifs()) {
+            let certificate: trussed::types::Message = store::read(self.store, trussed::types::Location::Internal, &certificate_path)
+                .map_err(|_| Status::UnspecifiedPersistentExecutionError)?;
+            let matches = x509::Certificate::parse(&certificate).map_err(drop)
+                .and_then(|certificate| attestation::check_key(attestation::Kind::P256, key, &certificate).map_err(drop));
+            if matches.is_err() {
+                info!("removing the FIDO certificate of the previous key");
+                store::delete(self.store, trussed::types::Location::Internal, &certificate_path);
+            }
+        }
+        Ok(())
+    }
+
+    fn save_fido_attestation_certificate(&mut self, certificate: &[u8]) -> ResponseResult {
+        if !PathBuf::from(FILENAME_FIDO_ATTESTATION_SECRET).exists(&self.store.ifs()) {
+            return Err(Status::IncorrectDataParameter);
+        }
+        let key = attestation::load_material(self.store, FILENAME_FIDO_ATTESTATION_SECRET)?;
+        let parsed = x509::Certificate::parse(certificate).map_err(|_| Status::IncorrectDataParameter)?;
+        attestation::check_key(attestation::Kind::P256, &key, &parsed)?;
+        if let Some(aaguid) = attestation::load_aaguid(self.store, FILENAME_FIDO_AAGUID)? {
+            attestation::check_aaguid(&parsed, &aaguid)?;
+        }
+
+        info!("saving FIDO attestation certificate, {} bytes", certificate.len());
+        store::store(
+            self.store,
+            trussed::types::Location::Internal,
+            &PathBuf::from(FILENAME_FIDO_ATTESTATION_CERT),
+            certificate,
+        ).map_err(|_| Status::NotEnoughMemory)
+    }
+
+    fn save_fido_aaguid(&mut self, aaguid: &[u8; 16]) -> ResponseResult {
+        let certificate_path = PathBuf::from(FILENAME_FIDO_ATTESTATION_CERT);
+        if certificate_path.exists(&self.store.ifs()) {
+            let certificate: trussed::types::Message = store::read(self.store, trussed::types::Location::Internal, &certificate_path)
+                .map_err(|_| Status::UnspecifiedPersistentExecutionError)?;
+            let parsed = x509::Certificate::parse(&certificate).map_err(|_| Status::UnspecifiedPersistentExecutionError)?;
+            attestation::check_aaguid(&parsed, aaguid)?;
+        }
+
+        store::store(
+            self.store,
+            trussed::types::Location::Internal,
+            &PathBuf::from(FILENAME_FIDO_AAGUID),
+            aaguid,
+        ).map_err(|_| Status::NotEnoughMemory)
+    }
+
     fn select(&mut self, command: &Command, _reply: &mut response::Data) -> ResponseResult {
 
         if command.data().starts_with(&TESTER_FILENAME_ID) {
@@ -0,0 +1,107 @@
+//! The provisioning lock: after LOCK, the attestation material cannot be replaced.
+//!
+//! LOCK writes `PATH` with `VERSION` and the SHA-256 over the `SEALED` files, and replies
+//! with the digest.
+//! From then on, every instruction that changes the filesystem is refused, including LOCK.
+//! `PATH` is under `/attn`, so it survives the admin app's factory reset.
+//!
+//! Runners call `state` at boot, and may refuse to run the other apps unless it is `Locked`.
+//! If the sealed files changed since, e.g. by a debugger, the state is `Tampered`.
+
+use apdu_dispatch::iso7816::Status;
+use littlefs2::fs::Filesystem;
+use littlefs2::io::Read;
+use littlefs2::path::PathBuf;
+use sha2::{Digest, Sha256};
+use trussed::store::{self, Store};
+use trussed::types::{LfsStorage, Location};
+
+pub const PATH: &str = "/attn/locked";
+
+/// Of the lock, its first byte, followed by the digest over `SEALED`.
+pub const VERSION: u8 = 1;
+
+/// The attestation material, with its public parts, and the FIDO AAGUID.
+const SEALED: &[&str] = &[
+    "/attn/pub/00",
+    "/attn/sec/01",
+    "/attn/sec/02",
+    "/attn/sec/03",
+    "/attn/x5c/01",
+    "/attn/x5c/02",
+    "/attn/x5c/03",
+    "/fido/dat/aaguid",
+    "/fido/sec/00",
+    "/fido/x5c/00",
+    "/piv/sec/00",
+    "/piv/x5c/00",
+];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum State {
+    Unlocked,
+    Locked,
+    Tampered,
+}
+
+pub fn state<S: Store>(store: S) -> State {
+    let path = PathBuf::from(PATH);
+    if !path.exists(store.ifs()) {
+        return State::Unlocked;
+    }
+    let lock: trussed::Bytes<33> = match store::read(store, Location::Internal, &path) {
+        Ok(lock) => lock,
+        Err(_) => return State::Tampered,
+    };
+    if lock.len() != 33 || lock[0] != VERSION {
+        return State::Tampered;
+    }
+    match digest::<S::I>(store.ifs(), SEALED) {
+        Ok(current) if lock[1..] == current[..] => State::Locked,
+        _ => State::Tampered,
+    }
+}
+
+pub(crate) fn is_unlocked<S: Store>(store: S) -> bool {
+    !PathBuf::from(PATH).exists(store.ifs())
+}
+
+pub(crate) fn lock<S: Store>(store: S) -> Result<[u8; 32], Status> {
+    if !is_unlocked(store) {
+        return Err(Status::ConditionsOfUseNotSatisfied);
+    }
+    let digest = digest::<S::I>(store.ifs(), SEALED).map_err(|_| Status::UnspecifiedPersistentExecutionError)?;
+    let mut lock = [0u8; 33];
+    lock[0] = VERSION;
+    lock[1..].copy_from_slice(&digest);
+    store::store(store, Location::Internal, &PathBuf::from(PATH), &lock)
+        .map_err(|_| Status::NotEnoughMemory)?;
+    Ok(digest)
+}
+
+/// Per file in `sealed`: its path, then its length (big endian `u32`) and contents, or
+/// `0xffff_ffff` if it does not exist.
+fn digest<S: LfsStorage>(fs: &Filesystem<'static, S>, sealed: &[&str]) -> Result<[u8; 32], ()> {
+    let mut hash = Sha256::new();
+    for path in sealed {
+        hash.update(path.as_bytes());
+        let path = PathBuf::from(*path);
+        if !path.exists(fs) {
+            hash.update(&u32::MAX.to_be_bytes());
+            continue;
+        }
+        fs.open_file_and_then(&path, |file| {
+            hash.update(&(file.len()? as u32).to_be_bytes());
+            let mut buffer = [0u8; 128];
+            loop {
+                let chunk = file.read(&mut buffer)?;
+                if chunk == 0 {
+                    break;
+                }
+                hash.update(&buffer[..chunk]);
+            }
+            Ok(())
+        }).map_err(drop)?;
+    }
+    Ok(hash.finalize().into())
+}
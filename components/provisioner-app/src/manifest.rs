@@ -0,0 +1,530 @@
+//! The PROVISION instruction: one station step as a single CBOR manifest.
+//!
+//! The manifest is the command data, or if there is none, what was uploaded into the file
+//! buffer with WRITE BINARY. It is a CBOR map with these keys, all optional:
+//!
+//! - `generate`: map of `p256`, `ed255`, `x255` and `piv` to booleans, the keys to generate
+//! - `t1_public_key`: the T1 intermediate's Ed25519 public key, as for SaveT1IntermediatePublicKey
+//! - `certificates`: map of `p256`, `ed255`, `x255` and `piv` to DER certificates, checked as
+//!   for the Save*AttestationCertificate instructions, against keys generated before
+//! - `fido`: map with the FIDO batch attestation `key` (raw P-256 secret), `certificate` and
+//!   optionally `aaguid`, checked as for the SaveFido* instructions
+//! - `ndef_url`: the URL the NDEF app serves, stored as `/ndef/dat/url`
+//! - `config`: the admin app's device configuration, missing keys get `Config::default()`
+//! - `lock`: lock the device afterwards, see `lock`
+//!
+//! Everything is checked before anything is written. Files that exist already are moved aside
+//! first; if a write fails, the files written so far are removed and the previous ones put back.
+//! The reply is a CBOR map with
+//! the generated `public_keys` (as the Generate*Key instructions reply them), the `files`
+//! written (`path` and `sha256`) and, if locked, the `lock` digest.
+
+use apdu_dispatch::iso7816::Status;
+use apdu_dispatch::response;
+use core::convert::TryInto;
+use heapless_bytes::Bytes;
+use littlefs2::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use trussed::key::{Flags, Key, Kind as KeyKind};
+use trussed::store::{self, Store};
+use trussed::types::Location;
+use trussed::{syscall, Client as TrussedClient};
+
+use crate::attestation::{self, Kind};
+use crate::x509::Certificate;
+use crate::{lock, FILENAME_P256_SECRET, FILENAME_ED255_SECRET, FILENAME_X255_SECRET};
+use crate::{FILENAME_P256_CERT, FILENAME_ED255_CERT, FILENAME_X255_CERT, FILENAME_T1_PUBLIC};
+use crate::{FILENAME_PIV_ATTESTATION_SECRET, FILENAME_PIV_ATTESTATION_CERT};
+use crate::{FILENAME_FIDO_ATTESTATION_SECRET, FILENAME_FIDO_ATTESTATION_CERT, FILENAME_FIDO_AAGUID};
+
+pub const FILENAME_NDEF_URL: &[u8] = b"/ndef/dat/url";
+const MAX_URL: usize = 128;
+
+/// Files written by one manifest, at most.
+const MAX_FILES: usize = 14;
+
+/// As the Generate*Key instructions store keys.
+const SECRET: Flags = Flags::LOCAL.union(Flags::SENSITIVE);
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Generate {
+    pub p256: bool,
+    pub ed255: bool,
+    pub x255: bool,
+    pub piv: bool,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Certificates<'a> {
+    #[serde(borrow)]
+    pub p256: Option<&'a [u8]>,
+    #[serde(borrow)]
+    pub ed255: Option<&'a [u8]>,
+    #[serde(borrow)]
+    pub x255: Option<&'a [u8]>,
+    #[serde(borrow)]
+    pub piv: Option<&'a [u8]>,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fido<'a> {
+    pub key: &'a [u8],
+    pub certificate: &'a [u8],
+    #[serde(borrow)]
+    pub aaguid: Option<&'a [u8]>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest<'a> {
+    #[serde(default)]
+    pub generate: Generate,
+    #[serde(borrow)]
+    pub t1_public_key: Option<&'a [u8]>,
+    #[serde(borrow, default)]
+    pub certificates: Certificates<'a>,
+    #[serde(borrow)]
+    pub fido: Option<Fido<'a>>,
+    #[serde(borrow)]
+    pub ndef_url: Option<&'a str>,
+    pub config: Option<admin_app::Config>,
+    #[serde(default)]
+    pub lock: bool,
+}
+
+#[derive(Default, Serialize)]
+struct PublicKeys {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    p256: Option<Bytes<64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ed255: Option<Bytes<32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    x255: Option<Bytes<32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    piv: Option<Bytes<64>>,
+}
+
+#[derive(Serialize)]
+struct File {
+    path: &'static str,
+    sha256: Bytes<32>,
+}
+
+#[derive(Default, Serialize)]
+struct Report {
+    public_keys: PublicKeys,
+    files: heapless::Vec<File, MAX_FILES>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lock: Option<Bytes<32>>,
+}
+
+/// What to write, in order.
+enum Content<'a> {
+    Key(Flags, KeyKind, [u8; 32]),
+    Raw(&'a [u8]),
+    Config(admin_app::Config),
+}
+
+struct Write<'a> {
+    path: &'static [u8],
+    content: Content<'a>,
+}
+
+type Writes<'a> = heapless::Vec<Write<'a>, MAX_FILES>;
+
+pub(crate) fn provision<S, T>(store: S, trussed: &mut T, data: &[u8], reply: &mut response::Data) -> Result<(), Status>
+where S: Store,
+      T: TrussedClient,
+{
+    let manifest: Manifest = trussed::cbor_deserialize(data).map_err(|_| Status::IncorrectDataParameter)?;
+    let mut report = Report::default();
+    let writes = stage(store, trussed, &manifest, &mut report.public_keys)?;
+
+    commit(store, &writes, &mut report.files)?;
+
+    if manifest.lock {
+        report.lock = Some(Bytes::from_slice(&lock::lock(store)?).unwrap());
+    }
+
+    let mut buffer = [0u8; 1024];
+    let encoded = trussed::cbor_serialize(&report, &mut buffer).map_err(|_| Status::NotEnoughMemory)?;
+    reply.extend_from_slice(encoded).map_err(|_| Status::NotEnoughMemory)
+}
+
+fn random_seed<T: TrussedClient>(trussed: &mut T) -> [u8; 32] {
+    let mut seed = [0u8; 32];
+    seed.copy_from_slice(&syscall!(trussed.random_bytes(32)).bytes.as_slice());
+    seed
+}
+
+fn parse(certificate: &[u8]) -> Result<Certificate, Status> {
+    Certificate::parse(certificate).map_err(|_| Status::IncorrectDataParameter)
+}
+
+fn push<'a>(writes: &mut Writes<'a>, path: &'static [u8], content: Content<'a>) {
+    // at most one write per path, fewer than `MAX_FILES`
+    writes.push(Write { path, content }).ok();
+}
+
+/// Checks the manifest and generates keys, nothing is written yet.
+fn stage<'a, S, T>(store: S, trussed: &mut T, manifest: &Manifest<'a>, public_keys: &mut PublicKeys) -> Result<Writes<'a>, Status>
+where S: Store,
+      T: TrussedClient,
+{
+    let mut writes = Writes::new();
+    let generate = manifest.generate;
+    let certificates = manifest.certificates;
+
+    let t1 = match manifest.t1_public_key {
+        Some(t1) => {
+            let t1: [u8; 32] = t1.try_into().map_err(|_| Status::IncorrectDataParameter)?;
+            push(&mut writes, FILENAME_T1_PUBLIC, Content::Key(Flags::default(), KeyKind::Ed255, t1));
+            Some(t1)
+        }
+        None if PathBuf::from(FILENAME_T1_PUBLIC).exists(&store.ifs()) => {
+            Some(attestation::load_material(store, FILENAME_T1_PUBLIC)?)
+        }
+        None => None,
+    };
+
+    let attestation_keys = [
+        (Kind::P256, generate.p256, certificates.p256, FILENAME_P256_SECRET, FILENAME_P256_CERT),
+        (Kind::Ed255, generate.ed255, certificates.ed255, FILENAME_ED255_SECRET, FILENAME_ED255_CERT),
+        (Kind::X255, generate.x255, certificates.x255, FILENAME_X255_SECRET, FILENAME_X255_CERT),
+    ];
+    for (kind, generate, certificate, secret_path, certificate_path) in attestation_keys {
+        if generate {
+            // a certificate can only be for the previous key
+            if certificate.is_some() {
+                return Err(Status::IncorrectDataParameter);
+            }
+            let seed = random_seed(trussed);
+            let public_key = attestation::public_key(kind, &seed);
+            match kind {
+                Kind::P256 => public_keys.p256 = Some(Bytes::from_slice(&public_key[1..]).unwrap()),
+                Kind::Ed255 => public_keys.ed255 = Some(Bytes::from_slice(&public_key).unwrap()),
+                Kind::X255 => public_keys.x255 = Some(Bytes::from_slice(&public_key).unwrap()),
+            }
+            let key_kind = match kind {
+                Kind::P256 => KeyKind::P256,
+                Kind::Ed255 => KeyKind::Ed255,
+                Kind::X255 => KeyKind::X255,
+            };
+            push(&mut writes, secret_path, Content::Key(SECRET, key_kind, seed));
+        }
+        if let Some(certificate) = certificate {
+            if !PathBuf::from(secret_path).exists(&store.ifs()) {
+                return Err(Status::IncorrectDataParameter);
+            }
+            let seed = attestation::load_material(store, secret_path)?;
+            let parsed = parse(certificate)?;
+            attestation::check_key(kind, &seed, &parsed)?;
+            attestation::check_signature(t1.as_ref().ok_or(Status::ConditionsOfUseNotSatisfied)?, &parsed)?;
+            push(&mut writes, certificate_path, Content::Raw(certificate));
+        }
+    }
+
+    if generate.piv {
+        if certificates.piv.is_some() {
+            return Err(Status::IncorrectDataParameter);
+        }
+        let seed = random_seed(trussed);
+        public_keys.piv = Some(Bytes::from_slice(&attestation::public_key(Kind::P256, &seed)[1..]).unwrap());
+        push(&mut writes, FILENAME_PIV_ATTESTATION_SECRET, Content::Key(SECRET, KeyKind::P256, seed));
+    }
+    if let Some(certificate) = certificates.piv {
+        if !PathBuf::from(FILENAME_PIV_ATTESTATION_SECRET).exists(&store.ifs()) {
+            return Err(Status::IncorrectDataParameter);
+        }
+        let seed = attestation::load_material(store, FILENAME_PIV_ATTESTATION_SECRET)?;
+        attestation::check_key(Kind::P256, &seed, &parse(certificate)?)?;
+        push(&mut writes, FILENAME_PIV_ATTESTATION_CERT, Content::Raw(certificate));
+    }
+
+    if let Some(fido) = manifest.fido {
+        let key: [u8; 32] = fido.key.try_into().map_err(|_| Status::IncorrectDataParameter)?;
+        let certificate = parse(fido.certificate)?;
+        attestation::check_key(Kind::P256, &key, &certificate)?;
+        let aaguid = match fido.aaguid {
+            Some(aaguid) => Some(aaguid.try_into().map_err(|_| Status::IncorrectDataParameter)?),
+            None => attestation::load_aaguid(store, FILENAME_FIDO_AAGUID)?,
+        };
+        if let Some(aaguid) = &aaguid {
+            attestation::check_aaguid(&certificate, aaguid)?;
+        }
+        push(&mut writes, FILENAME_FIDO_ATTESTATION_SECRET, Content::Key(SECRET, KeyKind::P256, key));
+        push(&mut writes, FILENAME_FIDO_ATTESTATION_CERT, Content::Raw(fido.certificate));
+        if let Some(aaguid) = fido.aaguid {
+            push(&mut writes, FILENAME_FIDO_AAGUID, Content::Raw(aaguid));
+        }
+    }
+
+    if let Some(url) = manifest.ndef_url {
+        if url.is_empty() || url.len() > MAX_URL {
+            return Err(Status::IncorrectDataParameter);
+        }
+        push(&mut writes, FILENAME_NDEF_URL, Content::Raw(url.as_bytes()));
+    }
+
+    if let Some(config) = manifest.config {
+        push(&mut writes, admin_app::config::PATH.as_bytes(), Content::Config(config));
+    }
+
+    Ok(writes)
+}
+
+fn commit<S: Store>(store: S, writes: &Writes, files: &mut heapless::Vec<File, MAX_FILES>) -> Result<(), Status> {
+    for (i, write) in writes.iter().enumerate() {
+        if back_up(store, write.path).is_err() {
+            return Err(roll_back(store, &writes[..i], files));
+        }
+        let written = match &write.content {
+            Content::Key(flags, kind, material) => {
+                let key = Key {
+                    flags: *flags,
+                    kind: *kind,
+                    material: heapless::Vec::from_slice(material).unwrap(),
+                };
+                let serialized = key.serialize();
+                store_file(store, write.path, &serialized)
+            }
+            Content::Raw(data) => store_file(store, write.path, data),
+            Content::Config(config) => {
+                let mut buffer = [0u8; 256];
+                trussed::cbor_serialize(config, &mut buffer).map_err(drop)
+                    .and_then(|encoded| store_file(store, write.path, encoded))
+            }
+        };
+        match written {
+            Ok(file) => {
+                files.push(file).ok();
+            }
+            Err(()) => return Err(roll_back(store, &writes[..=i], files)),
+        }
+    }
+    for write in writes {
+        store::delete(store, Location::Internal, &backup_path(write.path));
+    }
+    Ok(())
+}
+
+/// Where `commit` keeps the previous file at `path`, next to it.
+fn backup_path(path: &[u8]) -> PathBuf {
+    // all our paths are short
+    let mut backup = heapless::Vec::<u8, 64>::from_slice(path).unwrap();
+    backup.push(b'~').unwrap();
+    PathBuf::from(backup.as_slice())
+}
+
+fn back_up<S: Store>(store: S, path: &[u8]) -> Result<(), ()> {
+    let file = PathBuf::from(path);
+    if !file.exists(&store.ifs()) {
+        return Ok(());
+    }
+    store.ifs().rename(&file, &backup_path(path)).map_err(drop)
+}
+
+/// Removes the files of `writes` and puts the previous ones back.
+fn roll_back<S: Store>(store: S, writes: &[Write], files: &mut heapless::Vec<File, MAX_FILES>) -> Status {
+    info!("provisioning failed, restoring the previous files");
+    for write in writes {
+        let file = PathBuf::from(write.path);
+        let backup = backup_path(write.path);
+        if backup.exists(&store.ifs()) {
+            // replaces what was written
+            store.ifs().rename(&backup, &file).ok();
+        } else {
+            store::delete(store, Location::Internal, &file);
+        }
+    }
+    files.clear();
+    Status::NotEnoughMemory
+}
+
+fn store_file<S: Store>(store: S, path: &'static [u8], data: &[u8]) -> Result<File, ()> {
+    store::store(store, Location::Internal, &PathBuf::from(path), data).map_err(drop)?;
+    Ok(File {
+        // all our paths are ASCII
+        path: core::str::from_utf8(path).unwrap(),
+        sha256: Bytes::from_slice(&Sha256::digest(data)).unwrap(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use trussed::types::Message;
+
+    mod platform {
+        use littlefs2::{const_ram_storage, consts, fs::{Allocation, Filesystem}};
+        use trussed::platform::{consent, reboot, ui};
+        use trussed::types::{LfsResult, LfsStorage};
+
+        const_ram_storage!(InternalStorage, 8192);
+        const_ram_storage!(ExternalStorage, 8192);
+        const_ram_storage!(VolatileStorage, 8192);
+
+        trussed::store!(Store,
+            Internal: InternalStorage,
+            External: ExternalStorage,
+            Volatile: VolatileStorage
+        );
+
+        pub struct UserInterface;
+
+        impl trussed::platform::UserInterface for UserInterface {
+            fn check_user_presence(&mut self) -> consent::Level { consent::Level::Normal }
+            fn set_status(&mut self, _status: ui::Status) {}
+            fn refresh(&mut self) {}
+            fn uptime(&mut self) -> core::time::Duration { core::time::Duration::default() }
+            fn reboot(&mut self, _to: reboot::To) -> ! { loop { continue; } }
+        }
+
+        trussed::platform!(Platform,
+            R: rand_core::OsRng,
+            S: Store,
+            UI: UserInterface,
+        );
+
+        pub fn new() -> Platform {
+            let store = Store::format(InternalStorage::new(), ExternalStorage::new(), VolatileStorage::new());
+            Platform::new(rand_core::OsRng, store, UserInterface)
+        }
+    }
+
+    type Client<'a> = trussed::ClientImplementation<&'a mut trussed::service::Service<platform::Platform>>;
+
+    /// With `files` (path, contents) in internal storage.
+    fn setup<R>(files: &[(&[u8], &[u8])], test: impl FnOnce(platform::Store, &mut Client) -> R) -> R {
+        use trussed::{platform::Platform as _, Interchange as _};
+        unsafe { trussed::pipe::TrussedInterchange::reset_claims(); }
+        let platform = platform::new();
+        let store = platform.store();
+        for (path, contents) in files {
+            store::store(store, Location::Internal, &PathBuf::from(*path), contents).unwrap();
+        }
+        let mut service = trussed::service::Service::new(platform);
+        let mut client = service.try_as_new_client("attn").unwrap();
+        test(store, &mut client)
+    }
+
+    fn manifest<'a>() -> Manifest<'a> {
+        Manifest {
+            generate: Generate::default(),
+            t1_public_key: None,
+            certificates: Certificates::default(),
+            fido: None,
+            ndef_url: None,
+            config: None,
+            lock: false,
+        }
+    }
+
+    fn read<S: Store>(store: S, path: &[u8]) -> Option<Message> {
+        store::read(store, Location::Internal, &PathBuf::from(path)).ok()
+    }
+
+    fn exists<S: Store>(store: S, path: &[u8]) -> bool {
+        PathBuf::from(path).exists(&store.ifs())
+    }
+
+    const CONFIG: &[u8] = admin_app::config::PATH.as_bytes();
+
+    #[test]
+    fn writes_files() {
+        setup(&[(FILENAME_NDEF_URL, b"https://old.example")], |store, trussed| {
+            let config = admin_app::Config { buttons: false, ..Default::default() };
+            let manifest = Manifest { ndef_url: Some("https://example.com"), config: Some(config), ..manifest() };
+            let writes = stage(store, trussed, &manifest, &mut PublicKeys::default()).unwrap();
+            assert_eq!(writes.len(), 2);
+
+            let mut files = heapless::Vec::new();
+            commit(store, &writes, &mut files).unwrap();
+            assert_eq!(files.len(), 2);
+            assert_eq!(files[0].path, "/ndef/dat/url");
+            assert_eq!(&files[0].sha256[..], &Sha256::digest(b"https://example.com")[..]);
+            assert_eq!(files[1].path, admin_app::config::PATH);
+
+            assert_eq!(&read(store, FILENAME_NDEF_URL).unwrap()[..], b"https://example.com");
+            assert_eq!(admin_app::Config::load(store, Default::default()), config);
+            assert!(!exists(store, &backup_path(FILENAME_NDEF_URL)));
+        });
+    }
+
+    #[test]
+    fn generates_keys() {
+        setup(&[], |store, trussed| {
+            let manifest = Manifest { generate: Generate { p256: true, ed255: true, ..Default::default() }, ..manifest() };
+            let mut public_keys = PublicKeys::default();
+            let writes = stage(store, trussed, &manifest, &mut public_keys).unwrap();
+            assert_eq!(public_keys.p256.as_ref().map(|key| key.len()), Some(64));
+            assert_eq!(public_keys.ed255.as_ref().map(|key| key.len()), Some(32));
+            assert!(public_keys.x255.is_none() && public_keys.piv.is_none());
+            assert!(!exists(store, FILENAME_P256_SECRET));
+
+            commit(store, &writes, &mut heapless::Vec::new()).unwrap();
+            let seed = attestation::load_material(store, FILENAME_P256_SECRET).unwrap();
+            assert_eq!(&attestation::public_key(Kind::P256, &seed)[1..], &public_keys.p256.unwrap()[..]);
+            let seed = attestation::load_material(store, FILENAME_ED255_SECRET).unwrap();
+            assert_eq!(&attestation::public_key(Kind::Ed255, &seed)[..], &public_keys.ed255.unwrap()[..]);
+        });
+    }
+
+    #[test]
+    fn checked_before_writing() {
+        setup(&[], |store, trussed| {
+            let long = [b'a'; MAX_URL + 1];
+            let long = core::str::from_utf8(&long).unwrap();
+            let manifests = [
+                Manifest { ndef_url: Some(""), ..manifest() },
+                Manifest { ndef_url: Some(long), ..manifest() },
+                // no key to certify
+                Manifest { certificates: Certificates { p256: Some(&[0x30, 0x00]), ..Default::default() }, ..manifest() },
+                // the certificate cannot be for the new key
+                Manifest {
+                    generate: Generate { piv: true, ..Default::default() },
+                    certificates: Certificates { piv: Some(&[0x30, 0x00]), ..Default::default() },
+                    ..manifest()
+                },
+                Manifest { t1_public_key: Some(&[0; 31]), ..manifest() },
+            ];
+            for manifest in &manifests {
+                let staged = stage(store, trussed, manifest, &mut PublicKeys::default());
+                assert_eq!(staged.err(), Some(Status::IncorrectDataParameter));
+            }
+            assert!(!exists(store, FILENAME_NDEF_URL));
+            assert!(!exists(store, FILENAME_PIV_ATTESTATION_SECRET));
+        });
+    }
+
+    #[test]
+    fn rolls_back() {
+        // the previous configuration cannot be moved aside, as there is a directory in the way
+        let files: &[(&[u8], &[u8])] = &[
+            (FILENAME_NDEF_URL, b"https://old.example"),
+            (CONFIG, &[0xa0]),
+            (b"/admin/dat/config.cbor~/in-the-way", b""),
+        ];
+        setup(files, |store, trussed| {
+            let manifest = Manifest {
+                generate: Generate { p256: true, ..Default::default() },
+                ndef_url: Some("https://example.com"),
+                config: Some(admin_app::Config::default()),
+                ..manifest()
+            };
+            let writes = stage(store, trussed, &manifest, &mut PublicKeys::default()).unwrap();
+            let mut files = heapless::Vec::new();
+            assert_eq!(commit(store, &writes, &mut files), Err(Status::NotEnoughMemory));
+            assert!(files.is_empty());
+
+            assert_eq!(&read(store, FILENAME_NDEF_URL).unwrap()[..], b"https://old.example");
+            assert!(!exists(store, &backup_path(FILENAME_NDEF_URL)));
+            assert!(!exists(store, FILENAME_P256_SECRET));
+            assert_eq!(&read(store, CONFIG).unwrap()[..], &[0xa0]);
+        });
+    }
+}
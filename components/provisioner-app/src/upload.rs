@@ -0,0 +1,103 @@
+//! Chunked uploads into the filename and file buffers.
+//!
+//! WRITE BINARY writes its data at the offset in P1P2. Chunks have to be sent in order:
+//! a chunk past the end (a gap) is refused, a chunk overlapping what is already there is
+//! accepted only if it agrees with it, so a retransmitted chunk does no harm.
+//!
+//! WRITE FILE may take the SHA-256 of the file contents as data, the file is only written
+//! if it matches. Otherwise both buffers are cleared and the upload has to start over.
+
+use apdu_dispatch::iso7816::Status;
+use heapless::Vec;
+use sha2::{Digest, Sha256};
+
+pub fn write_at<const N: usize>(buffer: &mut Vec<u8, N>, offset: usize, data: &[u8]) -> Result<(), Status> {
+    if data.is_empty() {
+        return Err(Status::WrongLength);
+    }
+    if offset > buffer.len() {
+        return Err(Status::IncorrectP1OrP2Parameter);
+    }
+    let end = offset.checked_add(data.len()).ok_or(Status::NotEnoughMemory)?;
+    if end > buffer.capacity() {
+        return Err(Status::NotEnoughMemory);
+    }
+
+    let overlap = core::cmp::min(end, buffer.len()) - offset;
+    if buffer[offset..offset + overlap] != data[..overlap] {
+        return Err(Status::IncorrectDataParameter);
+    }
+    // cannot fail, checked against the capacity
+    buffer.extend_from_slice(&data[overlap..]).map_err(|_| Status::NotEnoughMemory)
+}
+
+/// `hash` is empty (nothing to check) or the SHA-256 of `contents`.
+pub fn verify(contents: &[u8], hash: &[u8]) -> Result<(), Status> {
+    match hash.len() {
+        0 => Ok(()),
+        32 if Sha256::digest(contents)[..] == hash[..] => Ok(()),
+        32 => Err(Status::IncorrectDataParameter),
+        _ => Err(Status::WrongLength),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    #[test]
+    fn in_order() {
+        let mut buffer = Vec::<u8, 8>::new();
+        write_at(&mut buffer, 0, b"abc").unwrap();
+        write_at(&mut buffer, 3, b"def").unwrap();
+        assert_eq!(&buffer[..], b"abcdef");
+    }
+
+    #[test]
+    fn retransmit() {
+        let mut buffer = Vec::<u8, 8>::new();
+        write_at(&mut buffer, 0, b"abc").unwrap();
+        write_at(&mut buffer, 3, b"def").unwrap();
+        write_at(&mut buffer, 3, b"def").unwrap();
+        // overlapping the end, extending the rest
+        write_at(&mut buffer, 4, b"efgh").unwrap();
+        assert_eq!(&buffer[..], b"abcdefgh");
+    }
+
+    #[test]
+    fn gap() {
+        let mut buffer = Vec::<u8, 8>::new();
+        write_at(&mut buffer, 0, b"abc").unwrap();
+        assert_eq!(write_at(&mut buffer, 4, b"e"), Err(Status::IncorrectP1OrP2Parameter));
+        assert_eq!(&buffer[..], b"abc");
+    }
+
+    #[test]
+    fn conflicting_overlap() {
+        let mut buffer = Vec::<u8, 8>::new();
+        write_at(&mut buffer, 0, b"abc").unwrap();
+        assert_eq!(write_at(&mut buffer, 1, b"bXd"), Err(Status::IncorrectDataParameter));
+        assert_eq!(&buffer[..], b"abc");
+    }
+
+    #[test]
+    fn overflow() {
+        let mut buffer = Vec::<u8, 8>::new();
+        write_at(&mut buffer, 0, b"abcdef").unwrap();
+        assert_eq!(write_at(&mut buffer, 6, b"ghi"), Err(Status::NotEnoughMemory));
+        assert_eq!(write_at(&mut buffer, usize::MAX, b"x"), Err(Status::IncorrectP1OrP2Parameter));
+        assert_eq!(write_at(&mut buffer, 6, b""), Err(Status::WrongLength));
+        assert_eq!(&buffer[..], b"abcdef");
+    }
+
+    #[test]
+    fn hash() {
+        // SHA-256("abc"), FIPS 180-2
+        let digest = hex!("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(verify(b"abc", &[]), Ok(()));
+        assert_eq!(verify(b"abc", &digest), Ok(()));
+        assert_eq!(verify(b"abd", &digest), Err(Status::IncorrectDataParameter));
+        assert_eq!(verify(b"abc", &digest[..31]), Err(Status::WrongLength));
+    }
+}
@@ -0,0 +1,319 @@
+//! Just enough DER to take X.509 certificates apart (RFC 5280, 4.1), and to put
+//! certification requests together (see `csr`).
+//!
+//! Nothing is validated beyond the structure: callers check the key and signature themselves.
+
+pub mod oid {
+    //! DER contents of the object identifiers we care about
+    pub const EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+    pub const PRIME256V1: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+    pub const ECDSA_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+    pub const X25519: &[u8] = &[0x2b, 0x65, 0x6e];
+    pub const ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+    /// Attribute type of a subject's serial number, 2.5.4.5
+    pub const SERIAL_NUMBER: &[u8] = &[0x55, 0x04, 0x05];
+    /// FIDO AAGUID extension, 1.3.6.1.4.1.45724.1.1.4
+    pub const FIDO_GEN_CE_AAGUID: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0xe5, 0x1c, 0x01, 0x01, 0x04];
+}
+
+pub mod tag {
+    pub const BOOLEAN: u8 = 0x01;
+    pub const INTEGER: u8 = 0x02;
+    pub const BIT_STRING: u8 = 0x03;
+    pub const OCTET_STRING: u8 = 0x04;
+    pub const OID: u8 = 0x06;
+    pub const SEQUENCE: u8 = 0x30;
+    pub const SET: u8 = 0x31;
+    pub const PRINTABLE_STRING: u8 = 0x13;
+    /// `[0]`, constructed
+    pub const CONTEXT_0: u8 = 0xa0;
+    /// `[3]`, constructed
+    pub const CONTEXT_3: u8 = 0xa3;
+}
+
+pub type Result<T> = core::result::Result<T, ()>;
+
+/// Encoded DER, large enough for a certification request.
+pub type Der = heapless::Vec<u8, 512>;
+
+/// One TLV with the concatenation of `parts` as value.
+pub fn encode(tag: u8, parts: &[&[u8]]) -> Result<Der> {
+    let length: usize = parts.iter().map(|part| part.len()).sum();
+    let mut der = Der::new();
+    der.push(tag).map_err(drop)?;
+    match length {
+        0..=0x7f => der.push(length as u8).map_err(drop)?,
+        0x80..=0xff => der.extend_from_slice(&[0x81, length as u8])?,
+        _ => der.extend_from_slice(&[0x82, (length >> 8) as u8, length as u8])?,
+    }
+    for part in parts {
+        der.extend_from_slice(part)?;
+    }
+    Ok(der)
+}
+
+/// A BIT STRING without unused bits.
+pub fn encode_bit_string(bits: &[u8]) -> Result<Der> {
+    encode(tag::BIT_STRING, &[&[0], bits])
+}
+
+/// Reads consecutive TLVs.
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn peek_tag(&self) -> Option<u8> {
+        self.data.first().copied()
+    }
+
+    /// The next TLV, as tag, value and the whole encoding.
+    pub fn any(&mut self) -> Result<(u8, &'a [u8], &'a [u8])> {
+        let data = self.data;
+        let tag = *data.first().ok_or(())?;
+        // no high tag numbers in certificates
+        if tag & 0x1f == 0x1f {
+            return Err(());
+        }
+        let first = *data.get(1).ok_or(())?;
+        let (length, header) = match first {
+            0..=0x7f => (first as usize, 2),
+            0x81..=0x82 => {
+                let size = (first & 0x7f) as usize;
+                let bytes = data.get(2..2 + size).ok_or(())?;
+                let length = bytes.iter().fold(0usize, |length, &byte| length << 8 | byte as usize);
+                // DER: shortest form
+                if length < 0x80 || (size == 2 && length < 0x100) {
+                    return Err(());
+                }
+                (length, 2 + size)
+            }
+            _ => return Err(()),
+        };
+        let end = header.checked_add(length).ok_or(())?;
+        let whole = data.get(..end).ok_or(())?;
+        self.data = &data[end..];
+        Ok((tag, &whole[header..], whole))
+    }
+
+    /// The next TLV's value, which must have tag `tag`.
+    pub fn expect(&mut self, tag: u8) -> Result<&'a [u8]> {
+        match self.any()? {
+            (actual, value, _) if actual == tag => Ok(value),
+            _ => Err(()),
+        }
+    }
+
+    /// Like `expect`, but also returns the whole encoding.
+    pub fn expect_whole(&mut self, tag: u8) -> Result<&'a [u8]> {
+        match self.any()? {
+            (actual, _, whole) if actual == tag => Ok(whole),
+            _ => Err(()),
+        }
+    }
+
+    /// The next TLV's value if it has tag `tag`, nothing otherwise.
+    pub fn optional(&mut self, tag: u8) -> Result<Option<&'a [u8]>> {
+        if self.peek_tag() == Some(tag) {
+            self.expect(tag).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// A BIT STRING without unused bits.
+    pub fn bit_string(&mut self) -> Result<&'a [u8]> {
+        match self.expect(tag::BIT_STRING)? {
+            [0, bits @ ..] => Ok(bits),
+            _ => Err(()),
+        }
+    }
+
+    /// The OID and parameters of an AlgorithmIdentifier.
+    pub fn algorithm(&mut self) -> Result<(&'a [u8], Option<&'a [u8]>)> {
+        let mut algorithm = Reader::new(self.expect(tag::SEQUENCE)?);
+        let oid = algorithm.expect(tag::OID)?;
+        let parameters = if algorithm.is_empty() { None } else { Some(algorithm.any()?.2) };
+        algorithm.finish()?;
+        Ok((oid, parameters))
+    }
+
+    pub fn finish(&self) -> Result<()> {
+        if self.is_empty() { Ok(()) } else { Err(()) }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Certificate<'a> {
+    /// The signed part, whole DER encoding
+    pub tbs: &'a [u8],
+    pub signature_algorithm: &'a [u8],
+    pub signature: &'a [u8],
+    /// Algorithm OID and parameters of the subject public key
+    pub key_algorithm: (&'a [u8], Option<&'a [u8]>),
+    pub public_key: &'a [u8],
+    /// Contents of the extensions SEQUENCE, if any
+    extensions: Option<&'a [u8]>,
+}
+
+impl<'a> Certificate<'a> {
+    pub fn parse(der: &'a [u8]) -> Result<Self> {
+        let mut outer = Reader::new(der);
+        let mut certificate = Reader::new(outer.expect(tag::SEQUENCE)?);
+        outer.finish()?;
+
+        let tbs = certificate.expect_whole(tag::SEQUENCE)?;
+        let (signature_algorithm, _) = certificate.algorithm()?;
+        let signature = certificate.bit_string()?;
+        certificate.finish()?;
+
+        let mut fields = Reader::new(Reader::new(tbs).expect(tag::SEQUENCE)?);
+        fields.optional(tag::CONTEXT_0)?;
+        // serial number, signature, issuer, validity, subject
+        fields.expect(tag::INTEGER)?;
+        let (inner_algorithm, _) = fields.algorithm()?;
+        if inner_algorithm != signature_algorithm {
+            return Err(());
+        }
+        fields.expect(tag::SEQUENCE)?;
+        fields.expect(tag::SEQUENCE)?;
+        fields.expect(tag::SEQUENCE)?;
+
+        let mut key_info = Reader::new(fields.expect(tag::SEQUENCE)?);
+        let key_algorithm = key_info.algorithm()?;
+        let public_key = key_info.bit_string()?;
+        key_info.finish()?;
+
+        let mut extensions = None;
+        while !fields.is_empty() {
+            // issuer and subject unique IDs are skipped
+            let (kind, value, _) = fields.any()?;
+            if kind == tag::CONTEXT_3 {
+                let mut wrapped = Reader::new(value);
+                extensions = Some(wrapped.expect(tag::SEQUENCE)?);
+                wrapped.finish()?;
+            }
+        }
+
+        Ok(Self { tbs, signature_algorithm, signature, key_algorithm, public_key, extensions })
+    }
+
+    /// The value (contents of the OCTET STRING) of the extension with this OID.
+    pub fn extension(&self, oid: &[u8]) -> Result<Option<&'a [u8]>> {
+        let mut extensions = Reader::new(match self.extensions {
+            Some(extensions) => extensions,
+            None => return Ok(None),
+        });
+        while !extensions.is_empty() {
+            let mut extension = Reader::new(extensions.expect(tag::SEQUENCE)?);
+            let id = extension.expect(tag::OID)?;
+            extension.optional(tag::BOOLEAN)?;
+            let value = extension.expect(tag::OCTET_STRING)?;
+            extension.finish()?;
+            if id == oid {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    /// Self-signed, with the FIDO AAGUID extension 00112233445566778899aabbccddeeff
+    const P256_CERTIFICATE: &[u8] = include_bytes!("../tests/fixtures/p256.der");
+    const ED25519_CERTIFICATE: &[u8] = include_bytes!("../tests/fixtures/ed25519.der");
+
+    #[test]
+    fn p256_certificate() {
+        let certificate = Certificate::parse(P256_CERTIFICATE).unwrap();
+        // the TBS follows the four bytes of the outer SEQUENCE's header
+        assert_eq!(certificate.tbs, &P256_CERTIFICATE[4..4 + certificate.tbs.len()]);
+        assert_eq!(certificate.signature_algorithm, oid::ECDSA_WITH_SHA256);
+        assert_eq!(certificate.key_algorithm, (oid::EC_PUBLIC_KEY, Some(&hex!("06082a8648ce3d030107")[..])));
+        assert_eq!(certificate.public_key, &hex!("
+            041447ca3ffc312074e00b3317b10c63c6d32752aafea1cc49843439f9983e2f
+            83de746f70ac65ddb0f3a883d07009cb84e5bd0061ebe2e13c15cb948051b30816
+        ")[..]);
+        // DER ECDSA-Sig-Value
+        assert_eq!(certificate.signature[0], tag::SEQUENCE);
+        assert_eq!(
+            certificate.extension(oid::FIDO_GEN_CE_AAGUID),
+            Ok(Some(&hex!("0410 00112233445566778899aabbccddeeff")[..])),
+        );
+        assert_eq!(certificate.extension(oid::SERIAL_NUMBER), Ok(None));
+    }
+
+    #[test]
+    fn ed25519_certificate() {
+        let certificate = Certificate::parse(ED25519_CERTIFICATE).unwrap();
+        assert_eq!(certificate.tbs, &ED25519_CERTIFICATE[4..4 + certificate.tbs.len()]);
+        assert_eq!(certificate.signature_algorithm, oid::ED25519);
+        assert_eq!(certificate.key_algorithm, (oid::ED25519, None));
+        assert_eq!(certificate.public_key, &hex!("5859b4283579b203d72d6eb38c9fb6d8ace6169ba47dceebec7e035dd333af6e")[..]);
+        assert_eq!(certificate.signature.len(), 64);
+        assert_eq!(certificate.extension(oid::FIDO_GEN_CE_AAGUID), Ok(None));
+    }
+
+    #[test]
+    fn truncated() {
+        for length in [0, 1, 4, P256_CERTIFICATE.len() / 2, P256_CERTIFICATE.len() - 1] {
+            assert!(Certificate::parse(&P256_CERTIFICATE[..length]).is_err());
+        }
+        assert!(Reader::new(&hex!("04")).any().is_err());
+        assert!(Reader::new(&hex!("0403 0102")).any().is_err());
+        assert!(Reader::new(&hex!("0481")).any().is_err());
+        assert!(Reader::new(&hex!("048201")).any().is_err());
+    }
+
+    #[test]
+    fn lengths() {
+        let mut long = [0u8; 3 + 0x80];
+        long[..3].copy_from_slice(&hex!("048180"));
+        let mut reader = Reader::new(&long);
+        assert_eq!(reader.any(), Ok((tag::OCTET_STRING, &long[3..], &long[..])));
+        assert!(reader.is_empty());
+
+        // longer than the data
+        assert!(Reader::new(&hex!("0482ffff 00")).any().is_err());
+        // more than two length bytes, and the indefinite form
+        assert!(Reader::new(&hex!("048300008000")).any().is_err());
+        assert!(Reader::new(&hex!("0480 0000")).any().is_err());
+        // not the shortest form
+        assert!(Reader::new(&hex!("048101 00")).any().is_err());
+        assert!(Reader::new(&hex!("04820080")).any().is_err());
+        // high tag numbers
+        assert!(Reader::new(&hex!("1f0100")).any().is_err());
+    }
+
+    #[test]
+    fn trailing_garbage() {
+        let mut der = heapless::Vec::<u8, 512>::from_slice(P256_CERTIFICATE).unwrap();
+        der.push(0).unwrap();
+        assert!(Certificate::parse(&der).is_err());
+
+        let mut reader = Reader::new(&hex!("0400 00"));
+        assert_eq!(reader.expect(tag::OCTET_STRING), Ok(&[][..]));
+        assert!(reader.finish().is_err());
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(&encode(tag::OCTET_STRING, &[&[1], &[2, 3]]).unwrap()[..], &hex!("0403 010203"));
+        let long = encode(tag::OCTET_STRING, &[&[0u8; 0x100]]).unwrap();
+        assert_eq!(long[..4], hex!("04820100"));
+        assert_eq!(Reader::new(&long).expect(tag::OCTET_STRING), Ok(&[0u8; 0x100][..]));
+        assert_eq!(&encode_bit_string(&[0xff]).unwrap()[..], &hex!("0302 00ff"));
+    }
+}
@@ -32,6 +32,18 @@ pub use specifics::{
     led::RgbLed,
 };
 
+/// Board variant, as reported by the admin app's STATUS command.
+#[cfg(feature = "board-okdoe1")]
+pub const BOARD_NAME: &str = "okdoe1";
+#[cfg(all(feature = "board-lpcxpresso55", not(feature = "board-okdoe1")))]
+pub const BOARD_NAME: &str = "lpcxpresso55";
+#[cfg(feature = "board-nk3am")]
+pub const BOARD_NAME: &str = "nk3am";
+#[cfg(all(feature = "board-solo2", not(feature = "board-nk3am")))]
+pub const BOARD_NAME: &str = "solo2";
+#[cfg(feature = "board-nk3xn")]
+pub const BOARD_NAME: &str = "nk3xn";
+
 pub mod clock_controller;
 pub mod nfc;
 pub mod trussed;
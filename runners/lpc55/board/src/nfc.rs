@@ -44,6 +44,36 @@ pub type NfcChip = FM11NC08<
                 Pin<NfcIrqPin, pin::state::Gpio<pin::gpio::direction::Input>>,
             >;
 
+//                      no limit      2mA resistor    3.3V
+const REGU_CONFIG: u8 = (0b11 << 4) | (0b10 << 2) | (0b11 << 0);
+
+/// Write our configuration to the chip's EEPROM.
+pub fn configure(
+    fm: &mut NfcChip,
+    timer: &mut Timer<impl hal::peripherals::ctimer::Ctimer<hal::typestates::init_state::Enabled>>,
+    ) -> Result<(), ()> {
+
+    info!("writing EEPROM");
+
+    fm.configure(Configuration{
+        regu: REGU_CONFIG,
+        ataq: 0x4400,
+        sak1: 0x04,
+        sak2: 0x20,
+        tl: 0x05,
+        // (x[7:4], FSDI[3:0]) . FSDI[2] == 32 byte frame, FSDI[8] == 256 byte frame, 7==128byte
+        t0: 0x78,
+        // Support different data rates for both directions
+        // Support divisor 2 / 212kbps for tx and rx
+        ta: 0b10010001,
+        // (FWI[b4], SFGI[b4]), (256 * 16 / fc) * 2 ^ value
+        tb: 0x78,
+        tc: 0x00,
+            // enable P-on IRQ    14443-4 mode
+        nfc:    (0b0 << 1) |       (0b00 << 2),
+    }, timer)
+}
+
 pub fn try_setup(
     spi: Spi0<Enabled>,
     gpio: &mut hal::Gpio<Enabled>,
@@ -73,8 +103,6 @@ pub fn try_setup(
 
     let mut fm = FM11NC08::new(spi, nfc_cs, nfc_irq).enabled();
 
-    //                      no limit      2mA resistor    3.3V
-    const REGU_CONFIG: u8 = (0b11 << 4) | (0b10 << 2) | (0b11 << 0);
     let current_regu_config = fm.read_reg(fm11nc08::Register::ReguCfg);
     let current_nfc_config = fm.read_reg(fm11nc08::Register::NfcCfg);
 
@@ -94,26 +122,7 @@ pub fn try_setup(
         // info_now!("{:?}", fm.dump_eeprom() );
         // info_now!("{:?}", fm.dump_registers() );
 
-        info!("writing EEPROM");
-
-        let r = fm.configure(Configuration{
-            regu: REGU_CONFIG,
-            ataq: 0x4400,
-            sak1: 0x04,
-            sak2: 0x20,
-            tl: 0x05,
-            // (x[7:4], FSDI[3:0]) . FSDI[2] == 32 byte frame, FSDI[8] == 256 byte frame, 7==128byte
-            t0: 0x78,
-            // Support different data rates for both directions
-            // Support divisor 2 / 212kbps for tx and rx
-            ta: 0b10010001,
-            // (FWI[b4], SFGI[b4]), (256 * 16 / fc) * 2 ^ value
-            tb: 0x78,
-            tc: 0x00,
-                // enable P-on IRQ    14443-4 mode
-            nfc:    (0b0 << 1) |       (0b00 << 2),
-        }, timer);
-        if r.is_err() {
+        if configure(&mut fm, timer).is_err() {
             info!("Eeprom failed.  No NFC chip connected?");
             return None;
         }
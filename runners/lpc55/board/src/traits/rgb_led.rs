@@ -16,6 +16,18 @@ impl From<u32> for Intensities {
     }
 }
 
+impl Intensities {
+    /// Dim all LEDs, 255 keeps the intensities.
+    pub fn scaled(self, brightness: u8) -> Self {
+        let scale = |intensity: u8| ((intensity as u16 * brightness as u16) / 255) as u8;
+        Intensities {
+            red: scale(self.red),
+            green: scale(self.green),
+            blue: scale(self.blue),
+        }
+    }
+}
+
 pub trait RgbLed {
 
     /// Set all LEDs
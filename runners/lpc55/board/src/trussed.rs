@@ -6,7 +6,7 @@ use crate::hal::{
     typestates::init_state,
 };
 use crate::traits::buttons::{Press, Edge};
-use crate::traits::rgb_led::RgbLed;
+use crate::traits::rgb_led::{Intensities, RgbLed};
 use trussed::platform::{
     ui,
     reboot,
@@ -43,6 +43,41 @@ impl UserPresenceStatus {
     }
 }
 
+// Set by the runner once the admin app's self test failed, until reboot.
+static mut SELF_TEST_FAILED: bool = false;
+pub struct SelfTestStatus {}
+impl SelfTestStatus {
+    pub fn set_failed() {
+        unsafe { SELF_TEST_FAILED = true };
+    }
+    pub fn failed() -> bool {
+        unsafe { SELF_TEST_FAILED }
+    }
+}
+
+/// An LED animation requested by an app, e.g. the admin app's LED command.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Animation {
+    /// RGB words shown alternately
+    pub colors: [u32; 2],
+    /// Milliseconds each color is shown
+    pub interval: u32,
+    /// Uptime at which the animation ends
+    pub until: core::time::Duration,
+}
+
+static mut ANIMATION: Option<Animation> = None;
+pub struct AnimationStatus {}
+impl AnimationStatus {
+    /// Replaces the running animation, `None` cancels it.
+    pub fn set(animation: Option<Animation>) {
+        unsafe { ANIMATION = animation };
+    }
+    pub fn current() -> Option<Animation> {
+        unsafe { ANIMATION }
+    }
+}
+
 
 pub struct UserInterface<BUTTONS, RGB>
 where
@@ -52,6 +87,7 @@ RGB: RgbLed,
     rtc: Rtc<init_state::Enabled>,
     buttons: Option<BUTTONS>,
     rgb: Option<RGB>,
+    brightness: u8,
 }
 
 impl<BUTTONS, RGB> UserInterface<BUTTONS, RGB>
@@ -59,13 +95,21 @@ where
 BUTTONS: Press + Edge,
 RGB: RgbLed,
 {
-    pub fn new(rtc: Rtc<init_state::Enabled>, _buttons: Option<BUTTONS>, rgb: Option<RGB>) -> Self {
-        #[cfg(not(feature = "no-buttons"))]
-        let ui = Self { rtc, buttons: _buttons, rgb };
-        #[cfg(feature = "no-buttons")]
-        let ui = Self { rtc, buttons: None, rgb };
+    /// Without buttons, every user presence check succeeds.
+    pub fn new(rtc: Rtc<init_state::Enabled>, buttons: Option<BUTTONS>, rgb: Option<RGB>) -> Self {
+        Self { rtc, buttons, rgb, brightness: u8::MAX }
+    }
 
-        ui
+    /// Scale all LED colors, 255 is full brightness.
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+    }
+
+    fn set_color(&mut self, color: Intensities) {
+        let brightness = self.brightness;
+        if let Some(rgb) = &mut self.rgb {
+            rgb.set(color.scaled(brightness));
+        }
     }
 }
 
@@ -104,31 +148,43 @@ RGB: RgbLed,
 
     fn set_status(&mut self, status: ui::Status) {
 
-        if let Some(rgb) = &mut self.rgb {
-
-            match status {
-                ui::Status::Idle => {
-                    // green
-                    rgb.set(0x00_ff_02.into());
-                },
-                ui::Status::Processing => {
-                    // teal
-                    rgb.set(0x00_ff_5a.into());
-                }
-                ui::Status::WaitingForUserPresence => {
-                    // orange
-                    rgb.set(0xff_7e_00.into());
-                },
-                ui::Status::Error => {
-                    // Red
-                    rgb.set(0xff_00_00.into());
-                },
+        match status {
+            ui::Status::Idle => {
+                // green
+                self.set_color(0x00_ff_02.into());
+            },
+            ui::Status::Processing => {
+                // teal
+                self.set_color(0x00_ff_5a.into());
             }
-
+            ui::Status::WaitingForUserPresence => {
+                // orange
+                self.set_color(0xff_7e_00.into());
+            },
+            ui::Status::Error => {
+                // Red
+                self.set_color(0xff_00_00.into());
+            },
         }
     }
 
     fn refresh(&mut self) {
+        if SelfTestStatus::failed() {
+            // Three red flashes every two seconds, also without buttons.
+            let time = self.uptime().as_millis() % 2000;
+            let color = if time < 900 && time % 300 < 150 { 0xff_00_00 } else { 0 };
+            self.set_color(color.into());
+            return;
+        }
+        if let Some(animation) = AnimationStatus::current() {
+            let now = self.uptime();
+            if now < animation.until {
+                let step = now.as_millis() as u32 / animation.interval.max(1);
+                self.set_color(animation.colors[(step % 2) as usize].into());
+                return;
+            }
+            AnimationStatus::set(None);
+        }
         if self.rgb.is_some() && self.buttons.is_some() {
             // 1. Get time & pick a period (here 4096).
             // 2. Map it to a value between 0 and pi.
@@ -149,7 +205,7 @@ RGB: RgbLed,
             // crate::logger::info!("time: {}", time).ok();
             // crate::logger::info!("amp: {}", hex!(amplitude)).ok();
             // crate::logger::info!("color: {}", hex!(color)).ok();
-            self.rgb.as_mut().unwrap().set(color.into());
+            self.set_color(color.into());
         }
     }
 
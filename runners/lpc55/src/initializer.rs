@@ -72,6 +72,28 @@ pub struct Initializer {
     config: Config,
 }
 
+/// Used until a configuration is set through the admin app.
+fn default_device_config() -> types::DeviceConfig {
+    types::DeviceConfig {
+        buttons: !cfg!(feature = "no-buttons"),
+        fido_reset_time_window: !cfg!(feature = "no-reset-time-window"),
+        reconfigure_nfc: cfg!(feature = "reconfigure-nfc"),
+        ..Default::default()
+    }
+}
+
+/// Only the admin and provisioner apps, until the provisioner's LOCK was done.
+#[cfg(feature = "require-provisioning-lock")]
+fn until_provisioned(store: types::Store, mut config: types::DeviceConfig) -> types::DeviceConfig {
+    let lock = provisioner_app::lock::state(store);
+    if lock != provisioner_app::lock::State::Locked {
+        info!("provisioning lock: {:?}, only admin and provisioner available", lock);
+        config.usb_apps &= device_config::app::PROVISIONER;
+        config.nfc_apps &= device_config::app::PROVISIONER;
+    }
+    config
+}
+
 // SoloKeys stores a product string in the first 64 bytes of CMPA.
 fn get_product_string(pfr: &mut Pfr<hal::typestates::init_state::Enabled>) -> &'static str {
     let data = pfr.cmpa_customer_data();
@@ -241,15 +263,14 @@ impl Initializer {
         pint.enable_interrupt(&mut mux, &nfc_irq, hal::peripherals::pint::Slot::Slot0, hal::peripherals::pint::Mode::ActiveLow);
         mux.disabled(syscon);
 
-        let force_nfc_reconfig = cfg!(feature = "reconfigure-nfc");
-
+        // forced reconfiguration has to wait for the device configuration, see `initialize_filesystem`
         board::nfc::try_setup(
             spi,
             gpio,
             iocon,
             nfc_irq,
             delay_timer,
-            force_nfc_reconfig,
+            false,
         )
 
     }
@@ -701,12 +722,27 @@ impl Initializer {
 
         if let Some(iso14443) = &mut nfc_stage.iso14443 { iso14443.poll(); }
 
+        let config = types::DeviceConfig::load(store, default_device_config());
+        info!("device config: {:?}", &config);
+        #[cfg(feature = "require-provisioning-lock")]
+        let config = until_provisioned(store, config);
+
+        if config.reconfigure_nfc && !self.is_nfc_passive {
+            let delay_timer = &mut basic_stage.delay_timer;
+            if let Some(iso14443) = &mut nfc_stage.iso14443 {
+                iso14443.borrow(|fm| {
+                    board::nfc::configure(fm, delay_timer).ok();
+                });
+            }
+        }
+
         // Cancel any possible outstanding use in delay timer
         basic_stage.delay_timer.cancel().ok();
 
         stages::Filesystem {
             store,
             internal_storage_fs: unsafe { &mut INTERNAL_STORAGE },
+            config,
         }
     }
 
@@ -731,9 +767,15 @@ impl Initializer {
             basic_stage.rgb.take()
         };
 
-        let three_buttons = basic_stage.three_buttons.take();
+        let three_buttons = if filesystem_stage.config.buttons {
+            basic_stage.three_buttons.take()
+        } else {
+            None
+        };
 
         let mut solobee_interface = board::trussed::UserInterface::new(rtc, three_buttons, rgb);
+        let config = &filesystem_stage.config;
+        solobee_interface.set_brightness(if config.stealth { 0 } else { config.led_brightness });
         solobee_interface.set_status(trussed::platform::ui::Status::Idle);
 
         let rng = flash_stage.rng.take().unwrap();
@@ -861,6 +903,10 @@ impl Initializer {
         return self.is_nfc_passive;
     }
 
+    pub fn nfc_enabled(&self) -> bool {
+        self.config.nfc_enabled
+    }
+
 }
 
 
@@ -72,10 +72,11 @@ pub struct Flash {
     pub rng: Option<hal::peripherals::rng::Rng<hal::Enabled>>,
 }
 
-/// Initialized filesystem.
+/// Initialized filesystem, and the device configuration stored in it.
 pub struct Filesystem {
     pub store: types::Store,
     pub internal_storage_fs: &'static mut Option<types::FlashStorage>,
+    pub config: types::DeviceConfig,
 }
 
 /// Initialized everything that is needed, minus unecessary intermediates
@@ -120,7 +120,7 @@ pub fn init_board(
         hal.rtc,
     );
 
-    let _is_passive_mode = initializer.is_in_passive_operation(&everything.clock);
+    let is_passive_mode = initializer.is_in_passive_operation(&everything.clock);
     let clock_controller = initializer.get_dynamic_clock_control(&mut everything.clock, &mut everything.basic);
 
     // rgb.turn_off();
@@ -131,14 +131,25 @@ pub fn init_board(
     #[cfg(feature = "provisioner-app")]
     let internal_fs = everything.filesystem.internal_storage_fs;
 
+    #[cfg(feature = "admin-app")]
+    let device_info = types::Lpc55DeviceInfo::new(
+        everything.filesystem.store.clone(),
+        initializer.nfc_enabled().then(|| everything.nfc.iso14443.is_some()),
+        &mut everything.basic.pfr,
+    );
+
     let apps = types::Apps::new(
         &mut everything.trussed,
+        everything.filesystem.config,
+        is_passive_mode,
+        #[cfg(feature = "admin-app")]
+        device_info,
         #[cfg(feature = "provisioner-app")]
         {
             types::ProvisionerNonPortable {
                 store,
                 stolen_filesystem: internal_fs.as_mut().unwrap(),
-                nfc_powered: _is_passive_mode,
+                nfc_powered: is_passive_mode,
             }
         }
     );
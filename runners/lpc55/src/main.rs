@@ -132,6 +132,10 @@ const APP: () = {
         let schedule = c.schedule;
 
         info_now!("inside IDLE");
+
+        #[cfg(feature = "admin-app")]
+        apps.admin.boot_self_test();
+
         loop {
 
             let mut time = 0;
@@ -145,7 +149,11 @@ const APP: () = {
                 runner::Delogger::flush();
             }
 
-            match apps.apdu_dispatch(|apps| apdu_dispatch.poll(apps)) {
+            let interface = apps.apdu_dispatch(|apps| apdu_dispatch.poll(apps));
+            if let Some(interface) = interface {
+                apps.set_interface(interface);
+            }
+            match interface {
 
                 Some(apdu_dispatch::dispatch::Interface::Contact) => {
                     rtic::pend(USB_INTERRUPT);
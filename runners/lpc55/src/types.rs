@@ -24,6 +24,11 @@ pub type FlashStorage = PlainFilesystem;
 #[cfg(not(feature = "no-encrypted-storage"))]
 pub type FlashStorage = PrinceFilesystem;
 
+/// Stored and changed by the admin app; without it, the defaults selected by the runner's
+/// features apply.
+pub use device_config::Config as DeviceConfig;
+pub mod gated;
+pub use gated::Gated;
 pub mod usb;
 pub use usb::{UsbClasses, EnabledUsbPeripheral, SerialClass, CcidClass, CtapHidClass};
 
@@ -85,7 +90,9 @@ pub type ExternalInterrupt = hal::Pint<hal::typestates::init_state::Enabled>;
 pub type ApduDispatch = apdu_dispatch::dispatch::ApduDispatch;
 pub type CtaphidDispatch = ctaphid_dispatch::dispatch::Dispatch;
 
+#[cfg(feature = "admin-app")]
 pub struct Lpc55Reboot {}
+#[cfg(feature = "admin-app")]
 impl admin_app::Reboot for Lpc55Reboot {
     fn reboot() -> ! {
         hal::raw::SCB::sys_reset()
@@ -105,8 +112,132 @@ impl admin_app::Reboot for Lpc55Reboot {
     }
 }
 
+/// Device information for the admin app's STATUS command.
+///
+/// The CMPA (customer manufacturing programmable area) and the reset reason
+/// are read once during initialization, as is whether the NFC chip answered.
+#[cfg(feature = "admin-app")]
+pub struct Lpc55DeviceInfo {
+    store: Store,
+    nfc: Option<bool>,
+    locked: bool,
+    secure_boot: bool,
+    boot_reason: admin_app::BootReason,
+}
+
+#[cfg(feature = "admin-app")]
+impl Lpc55DeviceInfo {
+    /// `nfc` is `None` if NFC is disabled in the runner, else whether the chip was found.
+    pub fn new(store: Store, nfc: Option<bool>, pfr: &mut hal::peripherals::pfr::Pfr<hal::Enabled>) -> Self {
+        let (locked, secure_boot) = match pfr.read_cmpa() {
+            Ok(cmpa) => {
+                // DBGEN (bit 1) fixed by CC_SOCU_PIN, to disabled by CC_SOCU_DFLT
+                let locked = cmpa.cc_socu_pin & 0b10 != 0 && cmpa.cc_socu_default & 0b10 == 0;
+                // SEC_BOOT_EN (bits 30-31), zero means plain images boot
+                let secure_boot = cmpa.secure_boot_cfg >> 30 != 0;
+                (locked, secure_boot)
+            }
+            Err(_) => (false, false),
+        };
+
+        // AOREG1 keeps the reset causes across resets (UM11126, 13.5.46)
+        let reset_causes = unsafe { &*hal::raw::PMC::ptr() }.aoreg1.read().bits();
+        let boot_reason = match reset_causes {
+            causes if causes & (1 << 8 | 1 << 13) != 0 => admin_app::BootReason::Watchdog,
+            causes if causes & (1 << 6) != 0 => admin_app::BootReason::Brownout,
+            causes if causes & (1 << 5) != 0 => admin_app::BootReason::ResetPin,
+            causes if causes & (1 << 7 | 1 << 9) != 0 => admin_app::BootReason::Software,
+            causes if causes & (0b111 << 10) != 0 => admin_app::BootReason::WakeUp,
+            causes if causes & (1 << 4) != 0 => admin_app::BootReason::PowerOn,
+            _ => admin_app::BootReason::Unknown,
+        };
+
+        Self { store, nfc, locked, secure_boot, boot_reason }
+    }
+}
+
+#[cfg(feature = "admin-app")]
+fn blocks<S: LfsStorage>(fs: &littlefs2::fs::Filesystem<'static, S>) -> Option<admin_app::Blocks> {
+    let total = fs.total_blocks();
+    let free = fs.available_blocks().ok()?;
+    Some(admin_app::Blocks { free: free as u32, used: (total - free) as u32 })
+}
+
+#[cfg(feature = "admin-app")]
+impl admin_app::DeviceInfo for Lpc55DeviceInfo {
+    fn board(&self) -> &'static str {
+        board::BOARD_NAME
+    }
+
+    fn firmware_version(&self) -> &'static str {
+        env!("CARGO_PKG_VERSION")
+    }
+
+    fn git_hash(&self) -> &'static str {
+        build_constants::CARGO_PKG_HASH.trim_end()
+    }
+
+    fn apps(&self) -> &'static [admin_app::AppInfo] {
+        use admin_app::AppInfo;
+        &[
+            AppInfo { name: "admin", version: Some(admin_app::VERSION) },
+            #[cfg(feature = "fido-authenticator")]
+            AppInfo { name: "fido", version: Some(fido_authenticator::VERSION) },
+            #[cfg(feature = "oath-authenticator")]
+            AppInfo { name: "oath", version: None },
+            #[cfg(feature = "ndef-app")]
+            AppInfo { name: "ndef", version: Some(ndef_app::VERSION) },
+            #[cfg(feature = "piv-authenticator")]
+            AppInfo { name: "piv", version: Some(piv_authenticator::VERSION) },
+            #[cfg(feature = "provisioner-app")]
+            AppInfo { name: "provisioner", version: Some(provisioner_app::VERSION) },
+        ]
+    }
+
+    fn filesystem(&mut self, location: trussed::types::Location) -> Option<admin_app::Blocks> {
+        use trussed::store::Store as _;
+        use trussed::types::Location::*;
+        match location {
+            Internal => blocks(&self.store.ifs()),
+            External => blocks(&self.store.efs()),
+            Volatile => blocks(&self.store.vfs()),
+        }
+    }
+
+    fn locked(&mut self) -> bool {
+        self.locked
+    }
+
+    fn secure_boot(&mut self) -> bool {
+        self.secure_boot
+    }
+
+    fn boot_reason(&mut self) -> admin_app::BootReason {
+        self.boot_reason
+    }
+
+    fn factory_reset(&mut self) -> Result<(), ()> {
+        admin_app::factory_reset::wipe(self.store)
+    }
+
+    fn nfc_present(&mut self) -> Option<bool> {
+        self.nfc
+    }
+
+    fn indicate_self_test_failure(&mut self) {
+        board::trussed::SelfTestStatus::set_failed();
+    }
+
+    fn led(&mut self, animation: Option<admin_app::led::Animation>) {
+        board::trussed::AnimationStatus::set(animation.map(|animation| {
+            let (colors, interval) = animation.pattern.blink();
+            board::trussed::Animation { colors, interval, until: animation.until }
+        }));
+    }
+}
+
 #[cfg(feature = "admin-app")]
-pub type AdminApp = admin_app::App<TrussedClient, Lpc55Reboot>;
+pub type AdminApp = admin_app::App<TrussedClient, Lpc55Reboot, Lpc55DeviceInfo>;
 #[cfg(feature = "piv-authenticator")]
 pub type PivApp = piv_authenticator::Authenticator<TrussedClient, {apdu_dispatch::command::SIZE}>;
 #[cfg(feature = "oath-authenticator")]
@@ -114,7 +245,7 @@ pub type OathApp = oath_authenticator::Authenticator<TrussedClient>;
 #[cfg(feature = "fido-authenticator")]
 pub type FidoApp = dispatch_fido::Fido<fido_authenticator::NonSilentAuthenticator, TrussedClient>;
 #[cfg(feature = "ndef-app")]
-pub type NdefApp = ndef_app::App<'static>;
+pub type NdefApp = ndef_app::App<TrussedClient>;
 #[cfg(feature = "provisioner-app")]
 pub type ProvisionerApp = provisioner_app::Provisioner<Store, FlashStorage, TrussedClient>;
 
@@ -170,7 +301,17 @@ impl TrussedApp for PivApp {
 
     type NonPortable = ();
     fn with_client(trussed: TrussedClient, _: ()) -> Self {
-        Self::new(trussed)
+        Self::new(trussed, hal::uuid())
+    }
+}
+
+#[cfg(feature = "ndef-app")]
+impl TrussedApp for NdefApp {
+    const CLIENT_ID: &'static [u8] = b"ndef\0";
+
+    type NonPortable = ();
+    fn with_client(trussed: TrussedClient, _: ()) -> Self {
+        Self::new(trussed, hal::uuid())
     }
 }
 
@@ -178,10 +319,9 @@ impl TrussedApp for PivApp {
 impl TrussedApp for AdminApp {
     const CLIENT_ID: &'static [u8] = b"admin\0";
 
-    // TODO: declare uuid + version
-    type NonPortable = ();
-    fn with_client(trussed: TrussedClient, _: ()) -> Self {
-        Self::new(trussed, hal::uuid(), build_constants::CARGO_PKG_VERSION)
+    type NonPortable = (Lpc55DeviceInfo, DeviceConfig);
+    fn with_client(trussed: TrussedClient, (device_info, config): Self::NonPortable) -> Self {
+        Self::new(trussed, hal::uuid(), build_constants::CARGO_PKG_VERSION, device_info, config)
     }
 }
 
@@ -189,12 +329,12 @@ impl TrussedApp for AdminApp {
 impl TrussedApp for FidoApp {
     const CLIENT_ID: &'static [u8] = b"fido\0";
 
-    type NonPortable = ();
-    fn with_client(trussed: TrussedClient, _: ()) -> Self {
+    type NonPortable = DeviceConfig;
+    fn with_client(trussed: TrussedClient, config: DeviceConfig) -> Self {
         let authnr = fido_authenticator::Authenticator::new(
             trussed,
             fido_authenticator::NonSilentAuthenticator {},
-        );
+        ).with_reset_time_window(config.fido_reset_time_window);
 
         Self::new(authnr)
     }
@@ -230,24 +370,31 @@ pub struct Apps {
     pub piv: PivApp,
     #[cfg(feature = "provisioner-app")]
     pub provisioner: ProvisionerApp,
+    config: DeviceConfig,
+    /// Of the last APDU, for SELECT, see `Gated`
+    interface: Option<apdu_dispatch::app::Interface>,
 }
 
 impl Apps {
     pub fn new(
         trussed: &mut trussed::Service<crate::Board>,
+        config: DeviceConfig,
+        nfc_passive: bool,
+        #[cfg(feature = "admin-app")]
+        admin: Lpc55DeviceInfo,
         #[cfg(feature = "provisioner-app")]
         provisioner: ProvisionerNonPortable
     ) -> Self {
         #[cfg(feature = "admin-app")]
-        let admin = AdminApp::with(trussed, ());
+        let admin = AdminApp::with(trussed, (admin, config));
         #[cfg(feature = "fido-authenticator")]
-        let fido = FidoApp::with(trussed, ());
+        let fido = FidoApp::with(trussed, config);
         #[cfg(feature = "oath-authenticator")]
         let oath = OathApp::with(trussed, ());
         #[cfg(feature = "piv-authenticator")]
         let piv = PivApp::with(trussed, ());
         #[cfg(feature = "ndef-app")]
-        let ndef = NdefApp::new();
+        let ndef = NdefApp::with(trussed, ());
         #[cfg(feature = "provisioner-app")]
         let provisioner = ProvisionerApp::with(trussed, provisioner);
 
@@ -264,28 +411,39 @@ impl Apps {
             piv,
             #[cfg(feature = "provisioner-app")]
             provisioner,
+            config,
+            // without USB, there is only NFC
+            interface: if nfc_passive { Some(apdu_dispatch::app::Interface::Contactless) } else { None },
         }
     }
 
+    /// Records the interface of the APDU just dispatched.
+    pub fn set_interface(&mut self, interface: apdu_dispatch::app::Interface) {
+        self.interface = Some(interface);
+    }
+
     pub fn apdu_dispatch<F, T>(&mut self, f: F) -> T
     where
         F: FnOnce(&mut [&mut dyn
                 ApduApp<CommandSize, ResponseSize>
             ]) -> T
     {
+        use device_config::app;
+        let config = &self.config;
+        let interface = self.interface;
         f(&mut [
             #[cfg(feature = "ndef-app")]
-            &mut self.ndef,
+            &mut Gated::new(&mut self.ndef, config, app::NDEF, interface),
             #[cfg(feature = "piv-authenticator")]
-            &mut self.piv,
+            &mut Gated::new(&mut self.piv, config, app::PIV, interface),
             #[cfg(feature = "oath-authenticator")]
-            &mut self.oath,
+            &mut Gated::new(&mut self.oath, config, app::OATH, interface),
             #[cfg(feature = "fido-authenticator")]
-            &mut self.fido,
+            &mut Gated::new(&mut self.fido, config, app::FIDO, interface),
             #[cfg(feature = "admin-app")]
             &mut self.admin,
             #[cfg(feature = "provisioner-app")]
-            &mut self.provisioner,
+            &mut Gated::new(&mut self.provisioner, config, app::PROVISIONER, interface),
         ])
     }
 
@@ -295,7 +453,7 @@ impl Apps {
     {
         f(&mut [
             #[cfg(feature = "fido-authenticator")]
-            &mut self.fido,
+            &mut Gated::new(&mut self.fido, &self.config, device_config::app::FIDO, None),
             #[cfg(feature = "admin-app")]
             &mut self.admin,
         ])
@@ -0,0 +1,82 @@
+//! Hides apps from the interfaces they are disabled on, see `DeviceConfig::usb_apps`.
+//!
+//! apdu-dispatch does not tell apps the interface on SELECT, so the runner passes the interface
+//! of the last request, see `Apps::set_interface`. When powered by the NFC field, that is always
+//! contactless. While both interfaces are in use, a SELECT may be attributed to the wrong one;
+//! all further commands are checked against their actual interface.
+//! CTAPHID is USB only, it follows the contact interface.
+
+use apdu_dispatch::{App as ApduApp, Command, app, command::SIZE as CommandSize, response::{self, SIZE as ResponseSize}};
+use apdu_dispatch::iso7816::{self, Status};
+use ctaphid_dispatch::app::{self as hid, App as CtaphidApp, Command as HidCommand, Message};
+
+pub struct Gated<'a, A> {
+    app: &'a mut A,
+    contact: bool,
+    contactless: bool,
+    /// The interface SELECT probably came from, if known
+    interface: Option<app::Interface>,
+}
+
+impl<'a, A> Gated<'a, A> {
+    /// `bit` is one of `device_config::app`
+    pub fn new(app: &'a mut A, config: &super::DeviceConfig, bit: u16, interface: Option<app::Interface>) -> Self {
+        Self {
+            app,
+            contact: config.is_enabled(bit, app::Interface::Contact),
+            contactless: config.is_enabled(bit, app::Interface::Contactless),
+            interface,
+        }
+    }
+
+    fn is_enabled(&self, interface: app::Interface) -> bool {
+        match interface {
+            app::Interface::Contact => self.contact,
+            app::Interface::Contactless => self.contactless,
+        }
+    }
+}
+
+impl<A: iso7816::App> iso7816::App for Gated<'_, A> {
+    fn aid(&self) -> iso7816::Aid {
+        self.app.aid()
+    }
+}
+
+impl<A: ApduApp<CommandSize, ResponseSize>> ApduApp<CommandSize, ResponseSize> for Gated<'_, A> {
+    fn select(&mut self, apdu: &Command, reply: &mut response::Data) -> app::Result {
+        let enabled = match self.interface {
+            Some(interface) => self.is_enabled(interface),
+            None => self.contact || self.contactless,
+        };
+        if !enabled {
+            return Err(Status::NotFound);
+        }
+        self.app.select(apdu, reply)
+    }
+
+    fn deselect(&mut self) {
+        self.app.deselect()
+    }
+
+    fn call(&mut self, interface: app::Interface, apdu: &Command, reply: &mut response::Data) -> app::Result {
+        if !self.is_enabled(interface) {
+            return Err(Status::ConditionsOfUseNotSatisfied);
+        }
+        self.app.call(interface, apdu, reply)
+    }
+}
+
+impl<A: CtaphidApp> CtaphidApp for Gated<'_, A> {
+    fn commands(&self) -> &'static [HidCommand] {
+        if self.contact {
+            self.app.commands()
+        } else {
+            &[]
+        }
+    }
+
+    fn call(&mut self, command: HidCommand, request: &Message, response: &mut Message) -> hid::AppResult {
+        self.app.call(command, request, response)
+    }
+}
@@ -0,0 +1,28 @@
+use std::path::Path;
+use std::process::Command;
+
+fn git(args: &[&str]) -> Option<String> {
+	let output = Command::new("git").args(args).output().ok()?;
+	if !output.status.success() {
+		return None;
+	}
+	Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn main() {
+	// reported by the admin app's STATUS command
+	let hash = git(&["rev-parse", "HEAD"]).unwrap_or_default();
+	println!("cargo:rustc-env=GIT_HASH={}", hash);
+
+	// rebuild when HEAD moves, be it to another branch or to a new commit on it
+	if let Some(git_dir) = git(&["rev-parse", "--git-dir"]) {
+		let head_ref = git(&["symbolic-ref", "-q", "HEAD"]);
+		// a missing file would rerun this script on every build
+		for file in ["HEAD", "packed-refs"].iter().copied().chain(head_ref.as_deref()) {
+			let path = Path::new(&git_dir).join(file);
+			if path.exists() {
+				println!("cargo:rerun-if-changed={}", path.display());
+			}
+		}
+	}
+}
@@ -9,6 +9,8 @@ use nrf52840_hal::{
 
 use crate::types::*;
 
+pub const BOARD_NAME: &str = "proto1";
+
 pub fn init_early(_device: &Peripherals, _core: &CorePeripherals) -> () {
 
 }
@@ -1,4 +1,6 @@
+use core::cell::Cell;
 use core::convert::TryInto;
+use cortex_m::interrupt::{self, Mutex};
 use nrf52840_hal::{
 	gpio::{Input, Output, Pin, PullDown, PushPull},
 	prelude::{OutputPin},
@@ -322,3 +324,57 @@ impl<T> FingerprintReader<T> where T: nrf52840_hal::uarte::Instance {
 		self.receive(resp)
 	}
 }
+
+//////////////////////////////////////////////////////////////////////////////
+// PIV ON-CARD BIOMETRIC COMPARISON
+
+type Reader = FingerprintReader<nrf52840_hal::pac::UARTE0>;
+
+/// The fingerprint reader, if there is one. It is the only owner: users take the
+/// reader out for as long as they need it (see `with_reader`), so the critical
+/// section is short, and a concurrent user finds the reader busy.
+static READER: Mutex<Cell<Option<&'static mut Reader>>> = Mutex::new(Cell::new(None));
+
+pub fn install(reader: &'static mut Reader) {
+	interrupt::free(|cs| READER.borrow(cs).set(Some(reader)));
+}
+
+/// Run `f` with the reader, if there is one and it is not in use.
+pub fn with_reader<R>(f: impl FnOnce(&mut Reader) -> R) -> Option<R> {
+	let reader = interrupt::free(|cs| READER.borrow(cs).take())?;
+	let result = f(&mut *reader);
+	interrupt::free(|cs| READER.borrow(cs).set(Some(reader)));
+	Some(result)
+}
+
+/// The PIV app's view of the fingerprint reader, see `READER`.
+///
+/// Enrolment stays with the buttons (see `irq_gpiote`). The reader only holds
+/// one template, which is the primary finger.
+pub struct PivBiometrics;
+
+impl piv_authenticator::Biometrics for PivBiometrics {
+	fn is_enrolled(&mut self, finger: piv_authenticator::Finger) -> bool {
+		if finger != piv_authenticator::Finger::Primary {
+			return false;
+		}
+		with_reader(|reader| {
+			let enrolled = reader.power_up().is_ok() && reader.is_enrolled();
+			reader.power_down().ok();
+			enrolled
+		}).unwrap_or(false)
+	}
+
+	fn verify(&mut self, finger: piv_authenticator::Finger) -> bool {
+		if finger != piv_authenticator::Finger::Primary {
+			return false;
+		}
+		let matched = with_reader(|reader| {
+			let matched = reader.power_up().is_ok() && reader.verify().unwrap_or(false);
+			reader.power_down().ok();
+			matched
+		}).unwrap_or(false);
+		info!("FPR: PIV verify {}", matched);
+		matched
+	}
+}
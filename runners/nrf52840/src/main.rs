@@ -81,9 +81,86 @@ impl trussed::platform::Syscall for NRFSyscall {
 
 pub struct NRFReboot {}
 impl admin_app::Reboot for NRFReboot {
-	fn reboot() -> ! { todo!() }
-	fn reboot_to_firmware_update() -> ! { todo!() }
-	fn reboot_to_firmware_update_destructive() -> ! { todo!() }
+	fn reboot() -> ! { cortex_m::peripheral::SCB::sys_reset() }
+
+	fn reboot_to_firmware_update() -> ! { provisioner_app::board::boot_to_bootrom() }
+
+	fn reboot_to_firmware_update_destructive() -> ! { provisioner_app::board::boot_to_bootrom_destructive() }
+}
+
+/// Device information for the admin app's STATUS command.
+pub struct NRFDeviceInfo {
+	store: StickStore,
+}
+
+fn lfs_blocks<S: LfsStorage>(fs: &littlefs2::fs::Filesystem<'static, S>) -> Option<admin_app::Blocks> {
+	let total = fs.total_blocks();
+	let free = fs.available_blocks().ok()?;
+	Some(admin_app::Blocks { free: free as u32, used: (total - free) as u32 })
+}
+
+impl admin_app::DeviceInfo for NRFDeviceInfo {
+	fn board(&self) -> &'static str { board::BOARD_NAME }
+
+	fn firmware_version(&self) -> &'static str { env!("CARGO_PKG_VERSION") }
+
+	fn git_hash(&self) -> &'static str { env!("GIT_HASH") }
+
+	fn apps(&self) -> &'static [admin_app::AppInfo] {
+		&[
+			admin_app::AppInfo { name: "admin", version: Some(admin_app::VERSION) },
+			admin_app::AppInfo { name: "fido", version: Some(fido_authenticator::VERSION) },
+			admin_app::AppInfo { name: "piv", version: Some(piv_authenticator::VERSION) },
+			admin_app::AppInfo { name: "provisioner", version: Some(provisioner_app::VERSION) },
+		]
+	}
+
+	fn filesystem(&mut self, location: trussed::types::Location) -> Option<admin_app::Blocks> {
+		use trussed::store::Store as _;
+		match location {
+			trussed::types::Location::Internal => lfs_blocks(&self.store.ifs()),
+			trussed::types::Location::External => lfs_blocks(&self.store.efs()),
+			trussed::types::Location::Volatile => lfs_blocks(&self.store.vfs()),
+		}
+	}
+
+	fn locked(&mut self) -> bool {
+		/* APPROTECT: 0xFF disabled, anything else enabled */
+		let uicr = unsafe { &*nrf52840_pac::UICR::ptr() };
+		uicr.approtect.read().bits() & 0xff != 0xff
+	}
+
+	/* no boot ROM verifying the application */
+	fn secure_boot(&mut self) -> bool { false }
+
+	fn boot_reason(&mut self) -> admin_app::BootReason {
+		/* RESETREAS is cumulative until cleared, all zero means power-on reset */
+		let power = unsafe { &*nrf52840_pac::POWER::ptr() };
+		let reasons = power.resetreas.read().bits();
+		match reasons {
+			r if r & (1 << 1) != 0 => admin_app::BootReason::Watchdog,
+			r if r & (1 << 3) != 0 => admin_app::BootReason::Lockup,
+			r if r & (1 << 2) != 0 => admin_app::BootReason::Software,
+			r if r & (1 << 0) != 0 => admin_app::BootReason::ResetPin,
+			r if r & (0b1_1111 << 16) != 0 => admin_app::BootReason::WakeUp,
+			0 => admin_app::BootReason::PowerOn,
+			_ => admin_app::BootReason::Unknown,
+		}
+	}
+
+	fn factory_reset(&mut self) -> Result<(), ()> {
+		admin_app::factory_reset::wipe(self.store)
+	}
+
+	/* no NFC frontend on these boards */
+	fn nfc_present(&mut self) -> Option<bool> { None }
+
+	fn indicate_self_test_failure(&mut self) {
+		ui::indicate_self_test_failure();
+	}
+
+	/* no RGB LED, the DK's LEDs only show the heartbeat */
+	fn led(&mut self, _animation: Option<admin_app::led::Animation>) {}
 }
 
 type TrussedNRFClient = trussed::ClientImplementation<NRFSyscall>;
@@ -99,7 +176,6 @@ const APP: () = {
 		gpiote: Gpiote,
 		ui: ui::StickUI,
 		trussed_service: trussed::service::Service<StickPlatform>,
-		finger: Option<fpr::FingerprintReader<nrf52840_hal::pac::UARTE0>>,
 		pre_usb: Option<usb::USBPreinitObjects>,
 		#[init(None)]
 		usb: Option<usb::USBObjects<'static>>,
@@ -110,7 +186,7 @@ const APP: () = {
 		power: nrf52840_hal::pac::POWER,
 		rtc: Rtc<nrf52840_hal::pac::RTC0>,
 		fido_app: dispatch_fido::Fido<fido_authenticator::NonSilentAuthenticator, TrussedNRFClient>,
-		admin_app: admin_app::App<TrussedNRFClient, NRFReboot>,
+		admin_app: admin_app::App<TrussedNRFClient, NRFReboot, NRFDeviceInfo>,
 		piv_app: piv_authenticator::Authenticator<TrussedNRFClient, {apdu_dispatch::command::SIZE}>,
 		prov_app: provisioner_app::Provisioner<StickStore, flash::FlashStorage, TrussedNRFClient>,
 	}
@@ -192,8 +268,15 @@ const APP: () = {
 		let se050 = if board_gpio.se_pins.is_some() {
 			let twim1 = Twim::new(ctx.device.TWIM1, board_gpio.se_pins.take().unwrap(), nrf52840_hal::twim::Frequency::K400);
 			let mut secelem = se050::Se050::new(twim1, board_gpio.se_power.take().unwrap());
-			secelem.enable().expect("SE050 ERROR");
-			Some(secelem)
+			/* boards may be populated without it */
+			match secelem.enable() {
+				Ok(()) => Some(secelem),
+				Err(e) => {
+					info!("SE050 not present: {:?}", e);
+					secelem.disable();
+					None
+				}
+			}
 		} else { None };
 
 		debug!("Internal Flash");
@@ -239,16 +322,13 @@ const APP: () = {
 
 		let usb_preinit = usb::preinit(ctx.device.USBD, clocks);
 
-		let fprx = {
 		if board_gpio.fpr_power.is_some() {
 			debug!("Fingerprint Reader");
-			let fprx_ = fpr::FingerprintReader::new(uart, 0xffff_ffffu32,
+			let fprx = fpr::FingerprintReader::new(uart, 0xffff_ffffu32,
 						board_gpio.fpr_power.take().unwrap(),
 						board_gpio.fpr_detect.take().unwrap());
-			Some(fprx_)
-		} else {
-			None
-		}};
+			fpr::install(cortex_m::singleton!(: fpr::FingerprintReader<nrf52840_hal::pac::UARTE0> = fprx).unwrap());
+		}
 
 		debug!("Finalizing");
 
@@ -263,7 +343,6 @@ const APP: () = {
 			gpiote,
 			ui,
 			trussed_service: srv,
-			finger: fprx,
 			pre_usb: Some(usb_preinit),
 			extflash: Some(stickextflash),
 			se050,
@@ -313,6 +392,8 @@ const APP: () = {
 	fn userspace_apps(ctx: userspace_apps::Context) {
 		let userspace_apps::Resources { usb_dispatcher, fido_app, admin_app, piv_app, prov_app} = ctx.resources;
 
+		admin_app.boot_self_test();
+
 		//usb_dispatcher.lock(|usb_dispatcher| {
 		if usb_dispatcher.is_some() {
 			cortex_m::peripheral::NVIC::mask(nrf52840_hal::pac::Interrupt::USBD);
@@ -357,9 +438,9 @@ const APP: () = {
 		ctx.resources.trussed_service.process();
 	}
 
-	#[task(priority = 1, binds = GPIOTE, resources = [ui, gpiote, finger, se050])]
+	#[task(priority = 1, binds = GPIOTE, resources = [ui, gpiote, se050])]
 	fn irq_gpiote(ctx: irq_gpiote::Context) {
-		let irq_gpiote::Resources { ui, gpiote, finger, se050 } = ctx.resources;
+		let irq_gpiote::Resources { ui, gpiote, se050 } = ctx.resources;
 		let sources: u32;
 		let val_p0: u32;
 		let val_p1: u32;
@@ -371,7 +452,7 @@ const APP: () = {
 		}
 		debug!("irq GPIO {:x} {:x} -> {:x}", val_p0, val_p1, sources);
 		// let buttons = ui.check_buttons(&[latch_p0, latch_p1]);
-		if let Some(finger_) = finger {
+		fpr::with_reader(|finger_| {
 			if (sources & 0b0000_0100) != 0 {
 				finger_.power_up().ok();
 				finger_.erase().ok();
@@ -385,7 +466,7 @@ const APP: () = {
 				}
 				finger_.power_down().ok();
 			}
-		}
+		});
 		if (sources & 0b0000_0010) != 0 && se050.is_some() {
 			se050.as_mut().unwrap().get_applet_id();
 		}
@@ -472,9 +553,9 @@ const APP: () = {
 		}
 	}
 
-	#[task(priority = 1, resources = [extflash, finger, ui, power, se050])]
+	#[task(priority = 1, resources = [extflash, ui, power, se050])]
 	fn try_system_off(ctx: try_system_off::Context, c: u32) {
-		let try_system_off::Resources { extflash, finger, ui, mut power, se050 } = ctx.resources;
+		let try_system_off::Resources { extflash, ui, mut power, se050 } = ctx.resources;
 
 		match c/8 {
 		60 => {
@@ -485,7 +566,7 @@ const APP: () = {
 		70 => {
 			debug!("System OFF: FPR");
 			/* cut power to fingerprint */
-			finger.as_mut().unwrap().power_down().ok();
+			fpr::with_reader(|finger| finger.power_down().ok());
 		}
 		80 => {
 			debug!("System OFF: EXTFLASH");
@@ -565,27 +646,36 @@ static mut VOLATILE_FS_ALLOC: Option<littlefs2::fs::Allocation<VolatileRAMStore>
 
 fn instantiate_apps(srv: &mut trussed::service::Service<StickPlatform>, store: StickStore, device_uuid: [u8; 16]) ->
 	(dispatch_fido::Fido<fido_authenticator::NonSilentAuthenticator, TrussedNRFClient>,
-	admin_app::App<TrussedNRFClient, NRFReboot>,
+	admin_app::App<TrussedNRFClient, NRFReboot, NRFDeviceInfo>,
 	piv_authenticator::Authenticator<TrussedNRFClient, {apdu_dispatch::command::SIZE}>,
 	provisioner_app::Provisioner<StickStore, flash::FlashStorage, TrussedNRFClient>) {
 	let fido_trussed_xch = trussed::pipe::TrussedInterchange::claim().unwrap();
 	let fido_lfs2_path = littlefs2::path::PathBuf::from("fido");
 	srv.add_endpoint(fido_trussed_xch.1, fido_lfs2_path).ok();
 	let fido_trussed_client = TrussedNRFClient::new(fido_trussed_xch.0, NRFSyscall {});
-	let fido_auth = fido_authenticator::Authenticator::new(fido_trussed_client, fido_authenticator::NonSilentAuthenticator {});
+	/* the nRF has no NFC chip, and no LED brightness control */
+	let device_config = admin_app::Config::load(store, admin_app::Config {
+		fido_reset_time_window: !cfg!(feature = "no-reset-time-window"),
+		..Default::default()
+	});
+	let fido_auth = fido_authenticator::Authenticator::new(fido_trussed_client, fido_authenticator::NonSilentAuthenticator {})
+		.with_reset_time_window(device_config.fido_reset_time_window);
 	let fido_app = dispatch_fido::Fido::<fido_authenticator::NonSilentAuthenticator, TrussedNRFClient>::new(fido_auth);
 
 	let admin_trussed_xch = trussed::pipe::TrussedInterchange::claim().unwrap();
 	let admin_lfs2_path = littlefs2::path::PathBuf::from("admin");
 	srv.add_endpoint(admin_trussed_xch.1, admin_lfs2_path).ok();
 	let admin_trussed_client = TrussedNRFClient::new(admin_trussed_xch.0, NRFSyscall {});
-	let admin_app = admin_app::App::<TrussedNRFClient, NRFReboot>::new(admin_trussed_client, device_uuid, 0x10203040);
+	let admin_device_info = NRFDeviceInfo { store: store.clone() };
+	let admin_app = admin_app::App::<TrussedNRFClient, NRFReboot, NRFDeviceInfo>::new(admin_trussed_client, device_uuid, 0x10203040, admin_device_info, device_config);
 
 	let piv_trussed_xch = trussed::pipe::TrussedInterchange::claim().unwrap();
 	let piv_lfs2_path = littlefs2::path::PathBuf::from("piv");
 	srv.add_endpoint(piv_trussed_xch.1, piv_lfs2_path).ok();
 	let piv_trussed_client = TrussedNRFClient::new(piv_trussed_xch.0, NRFSyscall {});
-	let piv_app = piv_authenticator::Authenticator::<TrussedNRFClient, {apdu_dispatch::command::SIZE}>::new(piv_trussed_client);
+	let piv_biometrics = cortex_m::singleton!(: fpr::PivBiometrics = fpr::PivBiometrics).unwrap();
+	let piv_app = piv_authenticator::Authenticator::<TrussedNRFClient, {apdu_dispatch::command::SIZE}>::new(piv_trussed_client, device_uuid)
+		.with_biometrics(piv_biometrics);
 
 	let prov_trussed_xch = trussed::pipe::TrussedInterchange::claim().unwrap();
 	let prov_lfs2_path = littlefs2::path::PathBuf::from("attn");
@@ -10,6 +10,9 @@ const I2CS_SE050_ADDRESS: u8 = 0x48;
 const T1_NAD_HD2SE: u8 = 0x5a;
 const T1_NAD_SE2HD: u8 = 0xa5;
 
+/* give up on NACKs after the default block waiting time, 1ms each - e.g. if not populated */
+const MAX_ANACK_RETRIES: u32 = 1000;
+
 struct Se050ATR {
 	blockwait_ms: u16,
 	minpoll_ms: u8,
@@ -215,7 +218,7 @@ impl<T> Se050<T> where T: nrf52840_hal::twim::Instance {
 	/* Low-Level TWI send/receive functions with retry in case SE is still busy */
 
 	fn send_retry_anack(&mut self, buf: &[u8]) -> Result<(), SeError> {
-		loop {
+		for _ in 0..MAX_ANACK_RETRIES {
 			self.delay_provider.delay_ms(1u32);
 			let err = self.twi.write(I2CS_SE050_ADDRESS, buf);
 			match err {
@@ -224,10 +227,11 @@ impl<T> Se050<T> where T: nrf52840_hal::twim::Instance {
 			Err(e) => { return Err(SeError::TransmitError(e)); }
 			}
 		}
+		Err(SeError::TransmitError(TwimError::AddressNack))
 	}
 
 	fn recv_retry_anack(&mut self, buf: &mut [u8]) -> Result<(), SeError> {
-		loop {
+		for _ in 0..MAX_ANACK_RETRIES {
 			self.delay_provider.delay_ms(1u32);
 			let err = self.twi.read(I2CS_SE050_ADDRESS, buf);
 			match err {
@@ -236,6 +240,7 @@ impl<T> Se050<T> where T: nrf52840_hal::twim::Instance {
 			Err(e) => { return Err(SeError::ReceiveError(e)); }
 			}
 		}
+		Err(SeError::ReceiveError(TwimError::AddressNack))
 	}
 }
 
@@ -1,4 +1,5 @@
 // use crate::{rle, TRUSSED_LOGO_RLE};
+use core::sync::atomic::{AtomicBool, Ordering};
 use embedded_graphics::{
 	DrawTarget,
 	pixelcolor::{RgbColor, Rgb565, raw::RawU16, raw::RawData},
@@ -31,6 +32,13 @@ pub enum StickBatteryState {
 	Charging(u32),		/* supposedly via USB */
 }
 
+/* set by the admin app's self test, shown until reboot */
+static SELF_TEST_FAILED: AtomicBool = AtomicBool::new(false);
+
+pub fn indicate_self_test_failure() {
+	SELF_TEST_FAILED.store(true, Ordering::Relaxed);
+}
+
 /* sufficient (rgb565) room for a 32x32 sprite, a 60x15 sprite or six 9x18 characters */
 static mut DISPLAY_BUF: [u8; 2048] = [0; 2048];
 const FONT: &[u8; (9*18*2)*192] = include_bytes!("../data/font_9x18.raw");
@@ -180,6 +188,9 @@ impl StickUI {
 			draw_sprite!(self, BATTERY_MAP, battsprite, 240-26, 2);
 			BATTERY_MAP.draw(battsprite, self.buf, 0).ok();
 			self.dsp.blit_at(&self.buf[0..25*10*2], 240-26, 2, 25, 10);
+			if SELF_TEST_FAILED.load(Ordering::Relaxed) {
+				self.render_text(b"SELF TEST FAILED", 5, 6);
+			}
 			self.update_due = t + 8;
 			}
 		StickUIState::PoweredDown => {}